@@ -123,8 +123,6 @@ where
             Ok(n) => n,
         };
 
-        if let Err(e) = to.write_all(&buf[0..n]).await {
-            return Err(e);
-        }
+        to.write_all(&buf[0..n]).await?;
     }
 }
@@ -0,0 +1,184 @@
+extern crate nix;
+
+use super::config::Program;
+use nix::libc;
+
+// by number, as in linux/capability.h
+const NAMES: [&str; 41] = [
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+const ALL: u64 = (1 << NAMES.len()) - 1;
+
+// like NET_BIND_SERVICE or cap_net_bind_service, or ALL
+fn parse(name: &str) -> Result<u64, String> {
+    let upper = name.to_uppercase();
+    let bare = upper.trim_start_matches("CAP_");
+    if bare == "ALL" {
+        return Ok(ALL);
+    }
+    NAMES
+        .iter()
+        .position(|n| *n == bare)
+        .map(|i| 1 << i)
+        .ok_or_else(|| format!("unknown capability {:?}", name))
+}
+
+// the capabilities the program keeps: all but the dropped ones, kept ones win
+pub fn kept(prog: &Program) -> Result<u64, String> {
+    let mut dropped = 0;
+    for name in &prog.drop_caps {
+        dropped |= parse(name)?;
+    }
+    let mut kept = 0;
+    for name in &prog.keep_caps {
+        kept |= parse(name)?;
+    }
+    Ok((ALL & !dropped) | kept)
+}
+
+#[repr(C)]
+struct Header {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Data {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const VERSION_3: u32 = 0x2008_0522;
+
+// limits the child to the kept capabilities before exec. the bounding set covers
+// root and setuid binaries, the ambient set carries the kept ones over the exec of
+// a program that isn't root
+pub fn restrict(cmd: &mut tokio::process::Command, kept: u64) {
+    let restrict = move || {
+        for cap in 0..NAMES.len() {
+            if kept & (1 << cap) == 0 {
+                // fails without CAP_SETPCAP, and then there is nothing to drop
+                unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) };
+            }
+        }
+
+        let mut header = Header {
+            version: VERSION_3,
+            pid: 0,
+        };
+        let mut data = [Data::default(); 2];
+        unsafe {
+            if libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        for (i, d) in data.iter_mut().enumerate() {
+            let mask = (kept >> (32 * i)) as u32;
+            d.permitted &= mask;
+            d.effective &= mask;
+            d.inheritable = d.permitted;
+        }
+        unsafe {
+            if libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        let permitted = data[0].permitted as u64 | (data[1].permitted as u64) << 32;
+        for cap in 0..NAMES.len() {
+            if permitted & (1 << cap) != 0 {
+                unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap, 0, 0) };
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(restrict);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prog(drop: &[&str], keep: &[&str]) -> Program {
+        let mut prog = Program::from_spec("name=p exec=sh").unwrap();
+        prog.drop_caps = drop.iter().map(|s| s.to_string()).collect();
+        prog.keep_caps = keep.iter().map(|s| s.to_string()).collect();
+        prog
+    }
+
+    #[test]
+    fn kept_capabilities() {
+        assert_eq!(ALL, kept(&prog(&[], &[])).unwrap());
+        assert_eq!(
+            1 << 10,
+            kept(&prog(&["ALL"], &["NET_BIND_SERVICE"])).unwrap()
+        );
+        assert_eq!(
+            ALL & !(1 << 21 | 1 << 12),
+            kept(&prog(&["cap_sys_admin", "NET_ADMIN"], &[])).unwrap()
+        );
+        assert!(kept(&prog(&["NET_BIND_SERVIEC"], &[])).is_err());
+    }
+
+    #[tokio::test]
+    async fn restricts_the_child() {
+        let mut cmd = tokio::process::Command::new("/bin/sh");
+        cmd.arg("-c")
+            .arg("grep -E '^Cap(Eff|Bnd)' /proc/self/status");
+        restrict(&mut cmd, 1 << 10);
+        let output = cmd.output().await.unwrap();
+        let status = String::from_utf8_lossy(&output.stdout).to_string();
+
+        // whatever decompose had to begin with, nothing beyond the kept one is left
+        assert_eq!(2, status.lines().count(), "{}", status);
+        for line in status.lines() {
+            let mask = u64::from_str_radix(line.split('\t').nth(1).unwrap(), 16).unwrap();
+            assert_eq!(0, mask & !(1 << 10), "{}", line);
+        }
+    }
+}
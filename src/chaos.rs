@@ -0,0 +1,155 @@
+extern crate log;
+extern crate nix;
+extern crate serde;
+extern crate toml;
+
+use super::config;
+use super::process::Running;
+
+use serde::Deserialize;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// randomly terminates programs, to see how the rest of the system copes
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Chaos {
+    // seconds between kills on average
+    pub kill_interval: f64,
+    // name patterns like "worker-*", all programs if empty
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub signal: Signal,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Signal {
+    #[default]
+    Term,
+    Kill,
+}
+
+impl Chaos {
+    // from an inline toml table, like '{kill_interval = 30, targets = ["worker-*"]}'
+    pub fn parse(arg: &str) -> Result<Chaos> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            chaos: Chaos,
+        }
+
+        let wrapper: Wrapper = toml::from_str(format!("chaos = {}", arg).as_str())
+            .map_err(|e| format!("invalid chaos {}: {}", arg, e))?;
+        if wrapper.chaos.kill_interval <= 0.0 {
+            return Err("chaos kill_interval must be positive".into());
+        }
+        Ok(wrapper.chaos)
+    }
+
+    fn targets(&self, name: &str) -> bool {
+        self.targets.is_empty() || self.targets.iter().any(|t| config::wildcard_match(t, name))
+    }
+
+    fn pick(&self, running: &Running, random: u64) -> Option<(String, u32)> {
+        let candidates: Vec<(String, u32)> = running
+            .pids()
+            .into_iter()
+            .filter(|(name, _)| self.targets(name))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[(random % candidates.len() as u64) as usize].clone())
+    }
+}
+
+// good enough for picking victims, without another dependency
+fn random() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+pub async fn run(chaos: Chaos, running: Running) {
+    use nix::sys::signal;
+
+    let signal = match chaos.signal {
+        Signal::Term => signal::Signal::SIGTERM,
+        Signal::Kill => signal::Signal::SIGKILL,
+    };
+
+    loop {
+        // somewhere between half and one and a half times the interval
+        let jitter = 0.5 + (random() % 1000) as f64 / 1000.0;
+        tokio::time::delay_for(Duration::from_secs_f64(chaos.kill_interval * jitter)).await;
+
+        match chaos.pick(&running, random()) {
+            Some((name, pid)) => {
+                log::warn!("chaos: sending {} to {}:{}", signal, name, pid);
+                let pid = nix::unistd::Pid::from_raw(pid as i32);
+                if let Err(e) = signal::kill(pid, signal) {
+                    log::debug!("chaos: {}", e);
+                }
+            }
+            None => log::debug!("chaos: nothing to kill"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses() {
+        let chaos = Chaos::parse(r#"{kill_interval = 30, targets = ["worker-*"]}"#).unwrap();
+        assert_eq!(
+            Chaos {
+                kill_interval: 30.0,
+                targets: vec!["worker-*".to_string()],
+                signal: Signal::Term,
+            },
+            chaos
+        );
+
+        assert!(Chaos::parse("{targets = []}").is_err());
+        assert!(Chaos::parse("{kill_interval = 0}").is_err());
+        assert!(Chaos::parse("nonsense").is_err());
+    }
+
+    #[test]
+    fn picks_targets() {
+        let chaos = Chaos::parse(r#"{kill_interval = 1, targets = ["worker-*"]}"#).unwrap();
+        let running = Running::default();
+        running.add("db", 1);
+        assert_eq!(None, chaos.pick(&running, 0));
+
+        running.add("worker-1", 2);
+        running.add("worker-2", 3);
+        assert_eq!(Some(("worker-1".to_string(), 2)), chaos.pick(&running, 0));
+        assert_eq!(Some(("worker-2".to_string(), 3)), chaos.pick(&running, 1));
+
+        running.remove("worker-1");
+        assert_eq!(Some(("worker-2".to_string(), 3)), chaos.pick(&running, 0));
+    }
+
+    #[tokio::test]
+    async fn kills() {
+        let mut child = tokio::process::Command::new("/bin/sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let running = Running::default();
+        running.add("sleeper", child.id());
+
+        let chaos = Chaos::parse("{kill_interval = 0.01, signal = \"kill\"}").unwrap();
+        let status = tokio::select! {
+            status = &mut child => status.unwrap(),
+            _ = run(chaos, running) => unreachable!(),
+        };
+        assert!(!status.success());
+    }
+}
@@ -0,0 +1,473 @@
+extern crate serde;
+extern crate serde_any;
+extern crate serde_json;
+extern crate toml;
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use toml::value::{Table, Value};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+// the parts of a docker-compose file that mean something outside of containers
+#[derive(Deserialize, Debug)]
+struct Compose {
+    #[serde(default)]
+    services: BTreeMap<String, Service>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Service {
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    entrypoint: Option<CommandLine>,
+    #[serde(default)]
+    command: Option<CommandLine>,
+    #[serde(default)]
+    environment: Option<Environment>,
+    #[serde(default)]
+    working_dir: Option<String>,
+    #[serde(default)]
+    depends_on: Option<DependsOn>,
+    #[serde(default)]
+    healthcheck: Option<Healthcheck>,
+    #[serde(default)]
+    ports: Vec<serde_json::Value>,
+    // whatever else there is, to say it was left out
+    #[serde(flatten)]
+    rest: BTreeMap<String, serde_json::Value>,
+}
+
+// a string is run by a shell, a list is the argv
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum CommandLine {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+impl CommandLine {
+    fn argv(&self) -> Vec<String> {
+        match self {
+            CommandLine::Shell(cmd) => vec!["/bin/sh".to_string(), "-c".to_string(), cmd.clone()],
+            CommandLine::Argv(argv) => argv.clone(),
+        }
+    }
+}
+
+// either KEY: value, or a list of KEY=value
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Environment {
+    Map(BTreeMap<String, Option<serde_json::Value>>),
+    List(Vec<String>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DependsOn {
+    List(Vec<String>),
+    Map(BTreeMap<String, Dependency>),
+}
+
+#[derive(Deserialize, Debug)]
+struct Dependency {
+    #[serde(default)]
+    condition: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Healthcheck {
+    #[serde(default)]
+    test: Option<CommandLine>,
+    #[serde(default)]
+    interval: Option<String>,
+    #[serde(default)]
+    disable: bool,
+}
+
+// keys that have no meaning for a local run, so leaving them out needs no mention
+const CONTAINER_ONLY: [&str; 8] = [
+    "container_name",
+    "hostname",
+    "networks",
+    "restart",
+    "labels",
+    "logging",
+    "stdin_open",
+    "tty",
+];
+
+// converts the services of a docker-compose file into programs. the notes say what
+// could not be converted faithfully and needs a human to look at
+pub fn import(raw: &str) -> Result<(String, Vec<String>)> {
+    let value: serde_json::Value = serde_any::from_str(raw, serde_any::Format::Yaml)
+        .map_err(|e| format!("invalid compose file: {:?}", e))?;
+    let compose: Compose = serde_json::from_value(value)?;
+    if compose.services.is_empty() {
+        return Err("no services found".into());
+    }
+
+    let mut notes = Vec::new();
+    // ready once done, for what only starts after they completed
+    let completes: BTreeSet<String> = compose
+        .services
+        .values()
+        .filter_map(|s| match &s.depends_on {
+            Some(DependsOn::Map(deps)) => Some(deps),
+            _ => None,
+        })
+        .flat_map(|deps| deps.iter())
+        .filter(|(_, dep)| dep.condition.as_deref() == Some("service_completed_successfully"))
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    let mut programs = Vec::new();
+    for (name, service) in compose.services.iter() {
+        let completed = completes.contains(name);
+        programs.push(Value::Table(program(name, service, completed, &mut notes)?));
+    }
+
+    let mut config = Table::new();
+    config.insert("program".to_string(), Value::Array(programs));
+    Ok((toml::to_string(&config)?, notes))
+}
+
+fn program(
+    name: &str,
+    service: &Service,
+    completed: bool,
+    notes: &mut Vec<String>,
+) -> Result<Table> {
+    let mut prog = Table::new();
+    prog.insert("name".to_string(), Value::String(name.to_string()));
+
+    let env = environment(name, service.environment.as_ref(), notes);
+
+    let mut argv = match (&service.entrypoint, &service.command) {
+        (None, None) => Vec::new(),
+        (None, Some(command)) => command.argv(),
+        (Some(entrypoint @ CommandLine::Shell(_)), command) => {
+            if command.is_some() {
+                notes.push(format!(
+                    "{}: command left out, a shell form entrypoint ignores it",
+                    name
+                ));
+            }
+            entrypoint.argv()
+        }
+        (Some(CommandLine::Argv(entrypoint)), command) => {
+            let mut argv = entrypoint.clone();
+            match command {
+                Some(CommandLine::Argv(args)) => argv.extend(args.iter().cloned()),
+                // compose splits it into words too
+                Some(CommandLine::Shell(args)) => {
+                    argv.extend(args.split_whitespace().map(String::from))
+                }
+                None => (),
+            }
+            argv
+        }
+    };
+    if argv.is_empty() {
+        let image = service
+            .image
+            .as_ref()
+            .ok_or_else(|| format!("{}: neither a command nor an image", name))?;
+        notes.push(format!(
+            "{}: no command, runs image {} with docker, replace it with a local command",
+            name, image
+        ));
+        argv = docker_run(name, image, service, &env);
+    }
+    prog.insert("exec".to_string(), Value::String(argv.remove(0)));
+    if !argv.is_empty() {
+        prog.insert("args".to_string(), strings(argv));
+    }
+
+    if !env.is_empty() {
+        let env = env
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+        prog.insert("env".to_string(), Value::Table(env));
+    }
+
+    if let Some(dir) = &service.working_dir {
+        notes.push(format!(
+            "{}: working_dir {} is a path in the container, check it exists here",
+            name, dir
+        ));
+        prog.insert("cwd".to_string(), Value::String(dir.clone()));
+    }
+
+    let depends: Vec<String> = match &service.depends_on {
+        Some(DependsOn::List(deps)) => deps.clone(),
+        Some(DependsOn::Map(deps)) => deps.keys().cloned().collect(),
+        None => Vec::new(),
+    };
+    if !depends.is_empty() {
+        prog.insert("depends".to_string(), strings(depends));
+    }
+
+    match (&service.healthcheck, completed) {
+        (Some(check), false) if !check.disable => {
+            if let Some(ready) = healthcheck(name, check, notes)? {
+                prog.insert("ready".to_string(), ready);
+            }
+        }
+        (_, true) => {
+            prog.insert("ready".to_string(), Value::String("completed".to_string()));
+        }
+        _ => (),
+    }
+
+    for key in service.rest.keys() {
+        if !CONTAINER_ONLY.contains(&key.as_str()) {
+            notes.push(format!("{}: {} left out", name, key));
+        }
+    }
+    Ok(prog)
+}
+
+// the variables, where ones without a value come from the environment decompose runs in
+fn environment(
+    name: &str,
+    env: Option<&Environment>,
+    notes: &mut Vec<String>,
+) -> BTreeMap<String, String> {
+    let pairs: Vec<(String, Option<String>)> = match env {
+        None => Vec::new(),
+        Some(Environment::Map(map)) => map
+            .iter()
+            .map(|(k, v)| {
+                let v = v.as_ref().map(|v| match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                });
+                (k.clone(), v)
+            })
+            .collect(),
+        Some(Environment::List(list)) => list
+            .iter()
+            .map(|var| match var.split_once('=') {
+                Some((k, v)) => (k.to_string(), Some(v.to_string())),
+                None => (var.clone(), None),
+            })
+            .collect(),
+    };
+
+    let mut env = BTreeMap::new();
+    for (key, value) in pairs {
+        match value {
+            Some(value) => {
+                env.insert(key, value);
+            }
+            None => {
+                notes.push(format!(
+                    "{}: {} has no value, passed on from the environment",
+                    name, key
+                ));
+                env.insert(key.clone(), format!("${{{}}}", key));
+            }
+        }
+    }
+    env
+}
+
+fn docker_run(
+    name: &str,
+    image: &str,
+    service: &Service,
+    env: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut argv: Vec<String> = ["docker", "run", "--rm", "--name", name]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    for port in service.ports.iter() {
+        let port = match port {
+            serde_json::Value::String(s) => s.clone(),
+            p => p.to_string(),
+        };
+        argv.extend(vec!["-p".to_string(), port]);
+    }
+    // the values are in the program's environment, docker passes them on
+    for key in env.keys() {
+        argv.extend(vec!["-e".to_string(), key.clone()]);
+    }
+    argv.push(image.to_string());
+    argv
+}
+
+fn healthcheck(name: &str, check: &Healthcheck, notes: &mut Vec<String>) -> Result<Option<Value>> {
+    let argv = match &check.test {
+        None => return Ok(None),
+        Some(CommandLine::Shell(cmd)) => CommandLine::Shell(cmd.clone()).argv(),
+        Some(CommandLine::Argv(test)) => match test.split_first() {
+            Some((kind, rest)) if kind == "CMD" && !rest.is_empty() => rest.to_vec(),
+            Some((kind, rest)) if kind == "CMD-SHELL" && rest.len() == 1 => {
+                CommandLine::Shell(rest[0].clone()).argv()
+            }
+            Some((kind, _)) if kind == "NONE" => return Ok(None),
+            _ => return Err(format!("{}: invalid healthcheck test {:?}", name, test).into()),
+        },
+    };
+    notes.push(format!(
+        "{}: the healthcheck ran in the container, check {} works here",
+        name, argv[0]
+    ));
+
+    let mut exec = Table::new();
+    exec.insert("command".to_string(), strings(argv));
+    if let Some(interval) = &check.interval {
+        let secs = duration(interval)
+            .ok_or_else(|| format!("{}: invalid healthcheck interval {}", name, interval))?;
+        exec.insert("interval".to_string(), Value::Float(secs));
+    }
+
+    let mut ready = Table::new();
+    ready.insert("exec".to_string(), Value::Table(exec));
+    Ok(Some(Value::Table(ready)))
+}
+
+// compose durations like "1m30s" or "500ms", in seconds
+fn duration(s: &str) -> Option<f64> {
+    let mut total = 0.0;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let n: f64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            "us" => 0.000_001,
+            _ => return None,
+        };
+        total += n * scale;
+        rest = &rest[unit..];
+    }
+    Some(total)
+}
+
+fn strings(v: Vec<String>) -> Value {
+    Value::Array(v.into_iter().map(Value::String).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::{ReadySignal, System};
+    use super::*;
+
+    const COMPOSE: &str = r#"
+version: "3.8"
+services:
+  db:
+    image: postgres:15
+    ports:
+      - "5432:5432"
+    environment:
+      POSTGRES_PASSWORD: secret
+    healthcheck:
+      test: ["CMD", "pg_isready", "-U", "postgres"]
+      interval: 2s
+  migrate:
+    command: ["./migrate", "up"]
+    depends_on:
+      db:
+        condition: service_healthy
+  api:
+    entrypoint: ["./api"]
+    command: --port 8080
+    working_dir: /srv/api
+    environment:
+      - LOG_LEVEL=debug
+      - HOME
+    depends_on:
+      migrate:
+        condition: service_completed_successfully
+    volumes:
+      - ./data:/data
+    restart: always
+"#;
+
+    #[test]
+    fn imports_services() {
+        let (config, notes) = import(COMPOSE).unwrap();
+        let sys = System::from_toml(config.as_str()).expect(config.as_str());
+        let prog = |name: &str| sys.program.iter().find(|p| p.name == name).unwrap();
+
+        let db = prog("db");
+        assert_eq!("docker", db.exec);
+        assert_eq!(
+            vec![
+                "run",
+                "--rm",
+                "--name",
+                "db",
+                "-p",
+                "5432:5432",
+                "-e",
+                "POSTGRES_PASSWORD",
+                "postgres:15"
+            ],
+            db.args
+        );
+        assert_eq!("secret", db.env["POSTGRES_PASSWORD"]);
+        assert_eq!("exec pg_isready -U postgres", db.ready.describe());
+
+        let migrate = prog("migrate");
+        assert_eq!("./migrate", migrate.exec);
+        assert_eq!(vec!["up"], migrate.args);
+        assert_eq!(vec!["db"], migrate.depends);
+        assert_eq!(ReadySignal::Completed, migrate.ready);
+
+        let api = prog("api");
+        assert_eq!("./api", api.exec);
+        assert_eq!(vec!["--port", "8080"], api.args);
+        assert_eq!("/srv/api", api.cwd);
+        assert_eq!("debug", api.env["LOG_LEVEL"]);
+        assert_eq!(vec!["migrate"], api.depends);
+
+        let notes = notes.join("\n");
+        assert!(notes.contains("db: no command"), "{}", notes);
+        assert!(
+            notes.contains("db: the healthcheck ran in the container"),
+            "{}",
+            notes
+        );
+        assert!(notes.contains("api: volumes left out"), "{}", notes);
+        assert!(notes.contains("api: HOME has no value"), "{}", notes);
+        assert!(!notes.contains("restart"), "{}", notes);
+    }
+
+    #[test]
+    fn invalid_compose_files() {
+        assert!(import("services: {}\n").is_err());
+        assert!(import("services:\n  x:\n    ports: [\"80\"]\n").is_err());
+        let bad = "services:\n  x:\n    command: x\n    healthcheck:\n      test: [\"WHAT\"]\n";
+        assert!(import(bad).is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Some(90.0), duration("1m30s"));
+        assert_eq!(Some(0.5), duration("500ms"));
+        assert_eq!(Some(2.0), duration("2s"));
+        assert_eq!(None, duration("2 days"));
+        assert_eq!(None, duration(""));
+    }
+}
@@ -1,11 +1,19 @@
+extern crate chrono;
 extern crate serde;
 extern crate serde_any;
+extern crate serde_json;
+extern crate serde_path_to_error;
 extern crate shellexpand;
+extern crate toml;
 
+use super::caps;
+use super::error;
+use super::secrets;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
@@ -19,37 +27,476 @@ pub struct System {
 
     #[serde(default = "default_start_timeout")]
     pub start_timeout: Option<f64>,
+
+    #[serde(default = "default_max_concurrent_stops")]
+    pub max_concurrent_stops: Option<usize>,
+
+    // port, healthcheck, udp, dns and plugin probes running at once, unlimited if not given
+    #[serde(default)]
+    pub max_concurrent_probes: Option<usize>,
+
+    #[serde(default = "default_port_check")]
+    pub port_check: PortCheck,
+
+    #[serde(default = "default_disabled_dependency")]
+    pub disabled_dependency: DisabledDependency,
+
+    // what the host needs to have before anything is started
+    #[serde(default)]
+    pub requires: Requirements,
+
+    #[serde(default)]
+    pub output: Option<OutputSink>,
+
+    // output lines are cut off beyond this many bytes, for programs that don't set their own
+    #[serde(default)]
+    pub max_line_length: Option<usize>,
+
+    // where in --outdir the log files go, with --output=files
+    #[serde(default)]
+    pub outdir_layout: OutdirLayout,
+
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    #[serde(default = "default_on_parent_death")]
+    pub on_parent_death: ParentDeath,
+
+    // whether programs that don't say run in a session of their own, away from the
+    // terminal's signals
+    #[serde(default)]
+    pub new_session: bool,
+
+    // raise the core limit and collect the cores of crashed programs
+    #[serde(default)]
+    pub core_dumps: bool,
+
+    #[serde(default)]
+    pub crash_loop: CrashLoop,
+
+    // sizes of the channels between executor, process manager and output
+    #[serde(default)]
+    pub channels: Channels,
+
+    // restarts across all programs, beyond which restarts are held until resumed
+    #[serde(default)]
+    pub restart_limit: Option<RestartLimit>,
+
+    // programs in a phase start only once every program in the earlier phases is ready
+    #[serde(default)]
+    pub phases: Vec<String>,
+
+    // filters on the environment inherited by all programs, like "AWS_*"
+    #[serde(default)]
+    pub env_allow: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub env_deny: Vec<String>,
+
+    // named sets of variables programs can opt into
+    #[serde(default)]
+    pub envgroup: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Socket {
+    pub port: u16,
+    #[serde(default = "default_socket_host")]
+    pub host: String,
+    // passed in LISTEN_FDNAMES, the port if not given
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Fd {
+    pub fd: i32,
+    #[serde(flatten)]
+    pub source: FdSource,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FdSource {
+    Read(String),
+    // appended to, created if needed
+    Write(String),
+    // ends of a pipe shared between programs by name
+    PipeRead(String),
+    PipeWrite(String),
+    // a listening socket on localhost
+    Listen(u16),
+}
+
+// host requirements, all unmet ones are reported together
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Requirements {
+    // in the filesystem of the outdir
+    #[serde(default)]
+    pub free_disk_mb: Option<u64>,
+
+    #[serde(default)]
+    pub available_memory_mb: Option<u64>,
+
+    // looked up in PATH, like the shell does
+    #[serde(default)]
+    pub binaries: Vec<String>,
+
+    // minimum soft limits by ulimit name, like nofile = 4096
+    #[serde(default)]
+    pub ulimits: HashMap<String, u64>,
+}
+
+// a token bucket: up to this many restarts at once, refilling at that many per period
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct RestartLimit {
+    pub restarts: usize,
+    #[serde(default = "default_restart_limit_per")]
+    pub per: f64,
+}
+
+fn default_restart_limit_per() -> f64 {
+    60.0
+}
+
+fn default_socket_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Channels {
+    #[serde(default = "default_channel_capacity")]
+    pub commands: usize,
+    #[serde(default = "default_channel_capacity")]
+    pub events: usize,
+    // lines a reader of a program's output may fall behind before missing some
+    #[serde(default = "default_output_capacity")]
+    pub output: usize,
+    #[serde(default = "default_overflow")]
+    pub overflow: Overflow,
+}
+
+impl Default for Channels {
+    fn default() -> Channels {
+        Channels {
+            commands: default_channel_capacity(),
+            events: default_channel_capacity(),
+            output: default_output_capacity(),
+            overflow: default_overflow(),
+        }
+    }
+}
+
+impl Channels {
+    fn validate(&self) -> Result<()> {
+        if self.commands == 0 || self.events == 0 || self.output == 0 {
+            return Err("channel capacities must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+// what sending commands or events into a full channel does: wait for room, or
+// give up on the run
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    Block,
+    Fail,
+}
+
+// programs restarted this many times within the window are given up on
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct CrashLoop {
+    #[serde(default = "default_crash_loop_restarts")]
+    pub restarts: usize,
+    #[serde(default = "default_crash_loop_within")]
+    pub within: f64,
+}
+
+impl Default for CrashLoop {
+    fn default() -> CrashLoop {
+        CrashLoop {
+            restarts: default_crash_loop_restarts(),
+            within: default_crash_loop_within(),
+        }
+    }
+}
+
+impl CrashLoop {
+    fn validate(&self) -> Result<()> {
+        if !self.within.is_finite() || self.within <= 0.0 {
+            let msg = format!("invalid crash_loop window {}", self.within);
+            return Err(msg.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct OutdirLayout {
+    // a chrono format for the directory of each run, with {pid} the process id
+    #[serde(default = "default_run_dir")]
+    pub run_dir: String,
+    // symlink to the directory of the newest run, none if empty
+    #[serde(default = "default_latest")]
+    pub latest: String,
+    // the logs of each program in a directory of its own
+    #[serde(default)]
+    pub per_program: bool,
+    // no run directories, logs go straight into the outdir, overwriting the last run
+    #[serde(default)]
+    pub flat: bool,
+    // gzip the .out and .err files of earlier runs when a new one starts
+    #[serde(default)]
+    pub compress: bool,
+    // remove all but the newest run directories when a new one starts, this one included
+    #[serde(default)]
+    pub keep_runs: Option<usize>,
+    #[serde(default)]
+    pub rotate: Option<Rotation>,
+}
+
+impl Default for OutdirLayout {
+    fn default() -> OutdirLayout {
+        OutdirLayout {
+            run_dir: default_run_dir(),
+            latest: default_latest(),
+            per_program: false,
+            flat: false,
+            compress: false,
+            keep_runs: None,
+            rotate: None,
+        }
+    }
+}
+
+// live log files are moved aside to name.1, name.2 and so on once too big or too old
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Rotation {
+    #[serde(default)]
+    pub max_mb: Option<f64>,
+    // seconds
+    #[serde(default)]
+    pub max_age: Option<f64>,
+    // how many moved aside files to keep
+    #[serde(default = "default_rotate_keep")]
+    pub keep: usize,
+}
+
+impl OutdirLayout {
+    fn validate(&self) -> Result<()> {
+        use chrono::format::{Item, StrftimeItems};
+
+        if self.run_dir.is_empty()
+            || StrftimeItems::new(self.run_dir.as_str()).any(|i| i == Item::Error)
+        {
+            let msg = format!("invalid outdir_layout run_dir {:?}", self.run_dir);
+            return Err(msg.into());
+        }
+        if self.run_dir.contains('/') || self.latest.contains('/') {
+            return Err("outdir_layout run_dir and latest are names, not paths".into());
+        }
+        if self.keep_runs == Some(0) {
+            return Err("outdir_layout keep_runs has to keep the current run at least".into());
+        }
+        if let Some(rotate) = &self.rotate {
+            let limits = [rotate.max_mb, rotate.max_age];
+            if limits.iter().all(Option::is_none) {
+                return Err("outdir_layout rotate needs max_mb or max_age".into());
+            }
+            if limits.iter().flatten().any(|l| !l.is_finite() || *l <= 0.0) {
+                return Err("outdir_layout rotate limits have to be positive".into());
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Program {
     pub name: String,
+
+    // empty only for tunnels, which get ssh filled in
+    #[serde(default)]
     pub exec: String,
 
+    #[serde(default = "default_program_type", rename = "type")]
+    pub kind: ProgramType,
+
+    // tunnel settings: forward local port to remote host:port through the via host
+    #[serde(default)]
+    pub via: Option<String>,
+
+    #[serde(default)]
+    pub local: Option<u16>,
+
+    #[serde(default)]
+    pub remote: Option<String>,
+
+    // listening sockets bound by decompose, passed as in systemd socket activation
+    #[serde(default)]
+    pub sockets: Vec<Socket>,
+
+    // extra fds opened by decompose and mapped into the program
+    #[serde(default)]
+    pub fds: Vec<Fd>,
+
+    // another config whose programs are run as part of this one
+    #[serde(default)]
+    pub subsystem: Option<String>,
+
     #[serde(default)]
     pub args: Vec<String>,
 
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    // an allowlist replaces the system's, denylists add up
+    #[serde(default)]
+    pub env_allow: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub env_deny: Vec<String>,
+
+    // merged below env, later groups win
+    #[serde(default)]
+    pub env_groups: Vec<String>,
+
     #[serde(default = "default_cwd")]
     pub cwd: String,
 
+    // create cwd, and its parents, before starting
+    #[serde(default)]
+    pub create_cwd: bool,
+
     #[serde(default = "default_ready_signal")]
     pub ready: ReadySignal,
 
+    // log every probe of the ready signal and what came of it
+    #[serde(default)]
+    pub ready_debug: bool,
+
+    // probed from ready until it stops, to notice it hanging
+    #[serde(default)]
+    pub liveness: Option<Liveness>,
+
     #[serde(default = "default_depends")]
     pub depends: Vec<String>,
 
     #[serde(default)]
     pub critical: bool,
 
+    // decompose stops when this program does, exiting with its exit code
+    #[serde(default)]
+    pub primary: bool,
+
     #[serde(default)]
     pub disabled: bool,
+
+    // included only if the condition holds, "env:USE_KAFKA" for that variable
+    // being set and not empty
+    #[serde(default)]
+    pub only_if: Option<String>,
+
+    // included only on these operating systems, like "linux" or "macos"
+    #[serde(default)]
+    pub platforms: Vec<String>,
+
+    // why only_if or platforms left it out, which disables it
+    #[serde(skip)]
+    pub excluded: Option<String>,
+
+    #[serde(default)]
+    pub phase: Option<String>,
+
+    #[serde(default)]
+    pub quiet: bool,
+
+    #[serde(default)]
+    pub log_rate_limit: Option<RateLimit>,
+
+    // cut off output lines beyond this many bytes
+    #[serde(default)]
+    pub max_line_length: Option<usize>,
+
+    #[serde(default)]
+    pub output: Option<OutputSink>,
+
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    #[serde(default)]
+    pub build: Vec<String>,
+
+    #[serde(default = "default_on_dep_failure")]
+    pub on_dep_failure: DepFailurePolicy,
+
+    #[serde(default)]
+    pub umask: Option<Umask>,
+
+    // sent to stop it instead of SIGTERM, SIGKILL follows after terminate_timeout
+    #[serde(default)]
+    pub stop_signal: Option<Signal>,
+
+    // signals sent in turn, each given its timeout before the next. SIGKILL follows
+    // the last one
+    #[serde(default)]
+    pub stop_escalation: Vec<StopStep>,
+
+    // setsid before exec, so ctrl-c and ctrl-z on the terminal only reach decompose
+    #[serde(default)]
+    pub new_session: Option<bool>,
+
+    // the cpus the program may run on, any if empty
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+
+    #[serde(default)]
+    pub seccomp: Option<Seccomp>,
+
+    // capabilities like "NET_BIND_SERVICE", or "ALL". kept ones stay even if dropped
+    #[serde(default)]
+    pub drop_caps: Vec<String>,
+
+    #[serde(default)]
+    pub keep_caps: Vec<String>,
+
+    #[serde(default)]
+    pub stdin: Stdin,
+
+    #[serde(default)]
+    pub exports: HashMap<String, Export>,
+
+    #[serde(default)]
+    pub scratch_dir: bool,
+
+    #[serde(default)]
+    pub keep_scratch_on_failure: bool,
+
+    // files relative to cwd, like "src/**/*.rs", that restart the program when they change
+    #[serde(default)]
+    pub watch: Vec<String>,
+
+    // restart what depends on it too, stopping that first and starting it again
+    // once the program is up
+    #[serde(default)]
+    pub watch_dependents: bool,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
+pub enum ProgramType {
+    Process,
+    Tunnel,
+    // stands in for the programs of a subsystem, ready when they all are
+    Subsystem,
+}
+
+// written like {port = 8080, stable_for = 3.0} for a check that has to hold that
+// many seconds on end, see the Deserialize impl below
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase", remote = "Self")]
 pub enum ReadySignal {
     Nothing,
     Manual,
@@ -58,7 +505,405 @@ pub enum ReadySignal {
     Stdout(String),
     Stderr(String),
     Completed,
-    Healthcheck(Endpoint),
+    Healthcheck(Box<Endpoint>),
+    Plugin(Plugin),
+    Exec(Exec),
+    Udp(Udp),
+    Dns(Dns),
+    // a newline written to this fd, s6 style
+    #[serde(rename = "notification_fd")]
+    NotificationFd(i32),
+    // anything written to this fifo, which decompose creates
+    Fifo(String),
+    // the check, ready once it succeeded for this many seconds without failing
+    #[serde(skip)]
+    Stable(Box<ReadySignal>, f64),
+}
+
+impl<'de> Deserialize<'de> for ReadySignal {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<ReadySignal, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ReadySignalVisitor)
+    }
+}
+
+struct ReadySignalVisitor;
+
+impl<'de> serde::de::Visitor<'de> for ReadySignalVisitor {
+    type Value = ReadySignal;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a ready signal")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> std::result::Result<ReadySignal, E> {
+        use serde::de::IntoDeserializer;
+        ReadySignal::deserialize(v.into_deserializer())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<ReadySignal, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        use serde::de::Error;
+
+        let mut signal = None;
+        let mut stable_for = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "stable_for" => stable_for = Some(map.next_value()?),
+                _ if signal.is_none() => signal = Some(map.next_value_seed(Entry(key))?),
+                _ => return Err(A::Error::custom(format!("unexpected {:?}", key))),
+            }
+        }
+
+        let signal = signal.ok_or_else(|| A::Error::custom("missing ready signal"))?;
+        match stable_for {
+            Some(secs) => Ok(ReadySignal::Stable(Box::new(signal), secs)),
+            None => Ok(signal),
+        }
+    }
+}
+
+// the key of a ready signal table, which names the variant its value is for
+struct Entry(String);
+
+impl<'de> serde::de::DeserializeSeed<'de> for Entry {
+    type Value = ReadySignal;
+
+    fn deserialize<D>(self, value: D) -> std::result::Result<ReadySignal, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        ReadySignal::deserialize(Variant {
+            name: self.0,
+            value,
+        })
+    }
+}
+
+struct Variant<D> {
+    name: String,
+    value: D,
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for Variant<D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::de::EnumAccess<'de> for Variant<D> {
+    type Error = D::Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> std::result::Result<(V::Value, Self), D::Error>
+    where
+        V: serde::de::DeserializeSeed<'de>,
+    {
+        use serde::de::IntoDeserializer;
+
+        let name: serde::de::value::StringDeserializer<D::Error> =
+            self.name.clone().into_deserializer();
+        seed.deserialize(name).map(|v| (v, self))
+    }
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::de::VariantAccess<'de> for Variant<D> {
+    type Error = D::Error;
+
+    // like {nothing = {}}, whatever the value is
+    fn unit_variant(self) -> std::result::Result<(), D::Error> {
+        serde::de::IgnoredAny::deserialize(self.value).map(|_| ())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> std::result::Result<T::Value, D::Error>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> std::result::Result<V::Value, D::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.value.deserialize_tuple(len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, D::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.value.deserialize_struct("", fields, visitor)
+    }
+}
+
+impl ReadySignal {
+    // the name used for it in config files
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReadySignal::Nothing => "nothing",
+            ReadySignal::Manual => "manual",
+            ReadySignal::Timer(_) => "timer",
+            ReadySignal::Port(_) => "port",
+            ReadySignal::Stdout(_) => "stdout",
+            ReadySignal::Stderr(_) => "stderr",
+            ReadySignal::Completed => "completed",
+            ReadySignal::Healthcheck(_) => "healthcheck",
+            ReadySignal::Plugin(_) => "plugin",
+            ReadySignal::Exec(_) => "exec",
+            ReadySignal::Udp(_) => "udp",
+            ReadySignal::Dns(_) => "dns",
+            ReadySignal::NotificationFd(_) => "notification_fd",
+            ReadySignal::Fifo(_) => "fifo",
+            ReadySignal::Stable(signal, _) => signal.kind(),
+        }
+    }
+
+    // the check itself, without how long it has to hold
+    pub fn signal(&self) -> &ReadySignal {
+        match self {
+            ReadySignal::Stable(signal, _) => signal,
+            other => other,
+        }
+    }
+
+    // the kind, with what it is about when that fits in a few words
+    pub fn describe(&self) -> String {
+        match self {
+            ReadySignal::Port(port) => format!("port {}", port),
+            ReadySignal::Udp(udp) => format!("udp {}", udp.port),
+            ReadySignal::Dns(dns) => format!("dns {}", dns.name),
+            ReadySignal::Timer(s) => format!("timer {}s", s),
+            ReadySignal::Healthcheck(endpoint) => format!("healthcheck {}", endpoint.path),
+            ReadySignal::Exec(exec) => format!("exec {}", exec.command.join(" ")),
+            ReadySignal::Stable(signal, s) => format!("{}, stable for {}s", signal.describe(), s),
+            other => other.kind().to_string(),
+        }
+    }
+}
+
+// an external readiness check: exit code 0 means ready, anything else means
+// not ready yet, and the plugin is run again after the interval
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Plugin {
+    pub exec: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_plugin_interval")]
+    pub interval: f64,
+}
+
+// a command run until it exits 0, like pg_isready or redis-cli ping. the command
+// line as a list, or a table to set the interval too
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(from = "ExecForm")]
+pub struct Exec {
+    pub command: Vec<String>,
+    pub interval: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExecForm {
+    Command(Vec<String>),
+    Table {
+        command: Vec<String>,
+        #[serde(default = "default_plugin_interval")]
+        interval: f64,
+    },
+}
+
+impl From<ExecForm> for Exec {
+    fn from(form: ExecForm) -> Exec {
+        match form {
+            ExecForm::Command(command) => Exec {
+                command,
+                interval: default_plugin_interval(),
+            },
+            ExecForm::Table { command, interval } => Exec { command, interval },
+        }
+    }
+}
+
+// a datagram sent to the port. ready once nothing refuses it, or once it is
+// answered within the timeout if a reply is expected
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Udp {
+    pub port: u16,
+    #[serde(default = "localhost")]
+    pub host: String,
+    #[serde(default)]
+    pub payload: String,
+    #[serde(default)]
+    pub expect_reply: bool,
+    #[serde(default = "default_udp_timeout")]
+    pub timeout: f64,
+}
+
+// a name that has to resolve, through the system resolver or by asking the given
+// server, as host:port, directly
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Dns {
+    pub name: String,
+    #[serde(default)]
+    pub server: Option<String>,
+}
+
+impl Dns {
+    // the name goes into the query label by label, each with a length byte
+    fn validate(&self) -> Result<()> {
+        let name = self.name.trim_end_matches('.');
+        if name.len() > 253 || name.split('.').any(|l| l.is_empty() || l.len() > 63) {
+            let msg = format!("invalid dns name {:?}", self.name);
+            return Err(msg.into());
+        }
+        Ok(())
+    }
+}
+
+// whether to verify that the ports programs will listen on are free before starting
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PortCheck {
+    Off,
+    Warn,
+    Fail,
+}
+
+// what to make of enabled programs depending on disabled ones, which start as if
+// the dependency was ready
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DisabledDependency {
+    Off,
+    Warn,
+    Fail,
+}
+
+// signal the children get when decompose itself dies without cleaning up
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ParentDeath {
+    None,
+    Term,
+    Kill,
+}
+
+// commands run on program lifecycle events, empty means nothing to run
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Hooks {
+    #[serde(default)]
+    pub on_ready: Vec<String>,
+    #[serde(default)]
+    pub on_crash: Vec<String>,
+    #[serde(default)]
+    pub on_stop: Vec<String>,
+    // once every program is ready, only for the whole system
+    #[serde(default)]
+    pub on_system_ready: Vec<String>,
+}
+
+// where program output goes, instead of the sink selected on the command line
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputSink {
+    // an external process, fed the output lines on its stdin
+    Pipe(Vec<String>),
+}
+
+// what the program reads on its stdin: nothing, decompose's own, a pipe that is
+// kept open but never written to, or a file
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Stdin {
+    #[default]
+    Null,
+    Inherit,
+    Pipe,
+    File(String),
+}
+
+// a value handed to dependents, captured by the first line of output matching the
+// regex. the first capture group is the value, or the whole match if there is none
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Export {
+    FromStdout(String),
+    FromStderr(String),
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct RateLimit {
+    pub lines_per_sec: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn validate(&self) -> Result<()> {
+        if !self.lines_per_sec.is_finite() || self.lines_per_sec <= 0.0 {
+            let msg = format!("invalid log rate limit {}", self.lines_per_sec);
+            return Err(msg.into());
+        }
+        if self.burst == 0 {
+            return Err("log rate limit burst must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+// what to do when a dependency with a completed ready signal exits with failure
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DepFailurePolicy {
+    Abort,
+    Skip,
+    StartAnyway,
+}
+
+// one of port, healthcheck or exec, probed every interval seconds. failing threshold
+// times in a row counts as not alive
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Liveness {
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub healthcheck: Option<Endpoint>,
+    #[serde(default)]
+    pub exec: Option<Exec>,
+    #[serde(default = "default_liveness_interval")]
+    pub interval: f64,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default)]
+    pub on_failure: LivenessFailure,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LivenessFailure {
+    #[default]
+    Restart,
+    // keep it running, but have the system show as degraded until it recovers
+    Degrade,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -67,6 +912,163 @@ pub struct Endpoint {
     pub path: String,
     #[serde(default = "localhost")]
     pub host: String,
+    #[serde(default)]
+    pub expect_json: Option<JsonExpectation>,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default)]
+    pub insecure: bool,
+    // accepted status codes, empty means any 2xx
+    #[serde(default)]
+    pub status: Vec<StatusRange>,
+}
+
+impl Endpoint {
+    // the method is parsed the way the probe does, so a typo fails the load rather
+    // than every probe
+    fn validate(&self) -> Result<()> {
+        reqwest::Method::from_bytes(self.method.to_uppercase().as_bytes())
+            .map_err(|_| format!("invalid healthcheck method {:?}", self.method))?;
+        Ok(())
+    }
+}
+
+// a single status code like "204", or an inclusive range like "200-399"
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StatusRange {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl StatusRange {
+    pub fn contains(&self, code: u16) -> bool {
+        self.from <= code && code <= self.to
+    }
+}
+
+impl std::str::FromStr for StatusRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<StatusRange, String> {
+        let parse = |c: &str| {
+            c.trim()
+                .parse::<u16>()
+                .map_err(|_| format!("invalid status range {:?}", s))
+        };
+
+        let range = match s.find('-') {
+            Some(i) => StatusRange {
+                from: parse(&s[..i])?,
+                to: parse(&s[i + 1..])?,
+            },
+            None => {
+                let code = parse(s)?;
+                StatusRange {
+                    from: code,
+                    to: code,
+                }
+            }
+        };
+
+        if range.from > range.to {
+            return Err(format!("invalid status range {:?}", s));
+        }
+        Ok(range)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusRange {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<StatusRange, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// a syscall filter for the program: "default" denies the ones that can harm the
+// host, like mount, reboot and ptrace. or a raw bpf program from a file
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Seccomp {
+    Default,
+    Bpf(String),
+}
+
+// file mode creation mask, given in octal like "0022"
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Umask(pub u32);
+
+impl std::str::FromStr for Umask {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Umask, String> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mask) if mask <= 0o777 => Ok(Umask(mask)),
+            _ => Err(format!("invalid umask {:?}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Umask, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// a signal by name, like "SIGINT" or "INT"
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Signal(pub nix::sys::signal::Signal);
+
+impl std::str::FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Signal, String> {
+        let name = s.to_uppercase();
+        let name = match name.starts_with("SIG") {
+            true => name,
+            false => format!("SIG{}", name),
+        };
+        match name.parse() {
+            Ok(signal) => Ok(Signal(signal)),
+            Err(_) => Err(format!("invalid signal {:?}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Signal {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Signal, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// one step of stopping a program, the signal and how many seconds it gets to work
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct StopStep {
+    pub signal: Signal,
+    pub timeout: f64,
+}
+
+// the value at the json pointer in the response body must equal the given value
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct JsonExpectation {
+    pub pointer: String,
+    pub equals: serde_json::Value,
 }
 
 fn default_cwd() -> String {
@@ -83,58 +1085,640 @@ fn default_start_timeout() -> Option<f64> {
     None
 }
 
-fn default_ready_signal() -> ReadySignal {
-    ReadySignal::Nothing
+fn default_max_concurrent_stops() -> Option<usize> {
+    None
+}
+
+fn default_port_check() -> PortCheck {
+    PortCheck::Warn
+}
+
+fn default_disabled_dependency() -> DisabledDependency {
+    DisabledDependency::Warn
+}
+
+fn default_run_dir() -> String {
+    "%Y-%m-%dT%H:%M:%S.{pid}".to_string()
+}
+
+fn default_latest() -> String {
+    "latest".to_string()
+}
+
+fn default_liveness_interval() -> f64 {
+    5.0
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_rotate_keep() -> usize {
+    3
+}
+
+fn default_channel_capacity() -> usize {
+    10
+}
+
+fn default_output_capacity() -> usize {
+    100
+}
+
+fn default_overflow() -> Overflow {
+    Overflow::Block
+}
+
+fn default_crash_loop_restarts() -> usize {
+    5
+}
+
+fn default_crash_loop_within() -> f64 {
+    30.0
+}
+
+fn default_program_type() -> ProgramType {
+    ProgramType::Process
+}
+
+fn default_on_parent_death() -> ParentDeath {
+    ParentDeath::Term
+}
+
+fn default_ready_signal() -> ReadySignal {
+    ReadySignal::Nothing
+}
+
+fn default_depends() -> Vec<String> {
+    Vec::new()
+}
+
+fn default_on_dep_failure() -> DepFailurePolicy {
+    DepFailurePolicy::Abort
+}
+
+fn default_plugin_interval() -> f64 {
+    1.0
+}
+
+fn default_udp_timeout() -> f64 {
+    0.1
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn localhost() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl Program {
+    // why the program is left out on this host, if it is
+    fn exclusion(&self) -> Result<Option<String>> {
+        if let Some(condition) = &self.only_if {
+            let var = condition.strip_prefix("env:").ok_or_else(|| {
+                format!(
+                    "program {:?} has only_if {:?}, expected env:<variable>",
+                    self.name, condition
+                )
+            })?;
+            if std::env::var_os(var).is_none_or(|v| v.is_empty()) {
+                return Ok(Some(format!("only_if {}, which is not set", condition)));
+            }
+        }
+        let os = std::env::consts::OS;
+        if !self.platforms.is_empty() && !self.platforms.iter().any(|p| p == os) {
+            let platforms = self.platforms.join(", ");
+            return Ok(Some(format!("platforms {}, not {}", platforms, os)));
+        }
+        Ok(None)
+    }
+
+    // how to stop it, SIGTERM given terminate_timeout unless configured otherwise
+    pub fn stop_steps(&self, terminate_timeout: f64) -> Vec<StopStep> {
+        if !self.stop_escalation.is_empty() {
+            return self.stop_escalation.clone();
+        }
+        let signal = self
+            .stop_signal
+            .unwrap_or(Signal(nix::sys::signal::Signal::SIGTERM));
+        vec![StopStep {
+            signal,
+            timeout: terminate_timeout,
+        }]
+    }
+
+    // ports on this host the program is expected to listen on, judging by its ready signal
+    pub fn ports(&self) -> Vec<u16> {
+        let mut ports = match self.ready.signal() {
+            ReadySignal::Port(port) => vec![*port],
+            ReadySignal::Healthcheck(endpoint) if is_local(endpoint.host.as_str()) => {
+                vec![endpoint.port]
+            }
+            _ => Vec::new(),
+        };
+        for socket in &self.sockets {
+            if !ports.contains(&socket.port) {
+                ports.push(socket.port);
+            }
+        }
+        ports
+    }
+
+    // whether the variable is passed on from decompose's own environment. explicit
+    // env entries are always set
+    pub fn inherits(&self, var: &str) -> bool {
+        let allowed = match &self.env_allow {
+            Some(allow) => allow.iter().any(|p| wildcard_match(p, var)),
+            None => true,
+        };
+        allowed && !self.env_deny.iter().any(|p| wildcard_match(p, var))
+    }
+
+    pub fn filters_env(&self) -> bool {
+        self.env_allow.is_some() || !self.env_deny.is_empty()
+    }
+
+    // the file to read stdin from, a relative one being relative to cwd like
+    // everything else the program opens
+    pub fn stdin_file(&self) -> Option<PathBuf> {
+        match &self.stdin {
+            Stdin::File(path) => Some(Path::new(&self.cwd).join(path)),
+            _ => None,
+        }
+    }
+
+    // turns a tunnel into the ssh command supervising it, ready once the local port is up
+    fn expand_tunnel(&mut self) -> Result<()> {
+        let (via, local, remote) = match (&self.via, self.local, &self.remote) {
+            (Some(via), Some(local), Some(remote)) => (via, local, remote),
+            _ => {
+                let msg = format!("tunnel {:?} needs via, local and remote", self.name);
+                return Err(msg.into());
+            }
+        };
+
+        self.exec = "ssh".to_string();
+        self.args = vec![
+            "-N".to_string(),
+            "-o".to_string(),
+            "ExitOnForwardFailure=yes".to_string(),
+            "-L".to_string(),
+            format!("{}:{}", local, remote),
+            via.clone(),
+        ];
+        if self.ready == ReadySignal::Nothing {
+            self.ready = ReadySignal::Port(local);
+        }
+        Ok(())
+    }
+
+    // a program given on the command line, as key=value pairs like
+    // name=debugger exec=dlv args="attach 123" depends=api,db
+    pub fn from_spec(spec: &str) -> error::Result<Program> {
+        Self::parse_spec(spec).map_err(error::Error::config)
+    }
+
+    fn parse_spec(spec: &str) -> Result<Program> {
+        let mut fields = serde_json::Map::new();
+        for word in split_words(spec)? {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", word))?;
+
+            let value = match serde_json::from_str(value) {
+                Ok(v @ serde_json::Value::Array(_)) => v,
+                _ if key == "args" || key == "build" => split_words(value)?.into(),
+                _ if key == "depends" => value.split(',').collect::<Vec<_>>().into(),
+                Ok(v) => v,
+                Err(_) => value.into(),
+            };
+            fields.insert(key.to_string(), value);
+        }
+
+        let prog = serde_json::from_value(fields.into())
+            .map_err(|e| format!("invalid program {:?}: {}", spec, e))?;
+        Ok(prog)
+    }
+}
+
+// tells which key failed to deserialize, and where when the format knows it. other
+// formats go through a json value, losing the position but still giving the key
+fn deserialize(raw: &str, format: Option<serde_any::Format>) -> Result<System> {
+    use serde_any::Format;
+
+    fn describe<E: std::fmt::Display>(e: serde_path_to_error::Error<E>) -> Box<dyn Error> {
+        match e.path().to_string().as_str() {
+            "." => e.inner().to_string().into(),
+            path => format!("{}: {}", path, e.inner()).into(),
+        }
+    }
+
+    match format {
+        Some(Format::Toml) => {
+            serde_path_to_error::deserialize(&mut toml::Deserializer::new(raw)).map_err(describe)
+        }
+        Some(Format::Json) => {
+            serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(raw))
+                .map_err(describe)
+        }
+        format => {
+            let value: serde_json::Value = match format {
+                Some(format) => serde_any::from_str(raw, format),
+                None => serde_any::from_str_any(raw),
+            }
+            .map_err(|e| format!("{:?}", e))?;
+            serde_path_to_error::deserialize(value).map_err(describe)
+        }
+    }
+}
+
+// glob style matching, where only * is special
+pub fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !s.starts_with(first) {
+        return false;
+    }
+    let mut rest = &s[first.len()..];
+
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            last
+        }
+        None => return rest.is_empty(),
+    };
+    rest.ends_with(last)
+}
+
+// makes the paths of a subsystem program relative to where its config lives
+// a relative stdin file needs nothing, it moves along with cwd
+fn relocate(prog: &mut Program, dir: &Path) {
+    let exec = Path::new(&prog.exec);
+    if exec.is_relative() && prog.exec.contains('/') {
+        prog.exec = dir.join(exec).to_string_lossy().to_string();
+    }
+
+    let cwd = Path::new(&prog.cwd);
+    if prog.cwd == default_cwd() {
+        prog.cwd = dir.to_string_lossy().to_string();
+    } else if cwd.is_relative() {
+        prog.cwd = dir.join(cwd).to_string_lossy().to_string();
+    }
+    if prog.cwd.is_empty() {
+        prog.cwd = ".".to_string();
+    }
 }
 
-fn default_depends() -> Vec<String> {
-    Vec::new()
+// splits on whitespace, honouring single and double quotes
+fn split_words(s: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(format!("unterminated quote in {:?}", s).into());
+    }
+    words.extend(word);
+    Ok(words)
 }
 
-fn localhost() -> String {
-    "127.0.0.1".to_string()
+fn is_local(host: &str) -> bool {
+    use std::net::IpAddr;
+
+    host == "localhost"
+        || host
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback() || ip.is_unspecified())
+            .unwrap_or(false)
 }
 
 impl System {
-    pub fn from_file(filename: &str) -> Result<System> {
-        let format = serde_any::guess_format(filename);
-        let raw_data = std::fs::read_to_string(filename)?;
-        Self::from_str(raw_data.as_str(), format)
+    // encrypted files are decrypted first, key is the age identity to use for that
+    pub fn from_file(filename: &str, key: Option<&str>) -> error::Result<System> {
+        Self::load(filename, key).map_err(error::Error::config)
+    }
+
+    fn load(filename: &str, key: Option<&str>) -> Result<System> {
+        let raw_data = std::fs::read(filename)?;
+        let (raw_data, plain) = secrets::decrypt(filename, raw_data, key)?;
+        let format = serde_any::guess_format(plain.as_str());
+        let sys = Self::from_str(raw_data.as_str(), format)
+            .map_err(|e| format!("{}: {}", filename, e))?;
+
+        let base = Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        sys.expand_subsystems(base, key)
+    }
+
+    // adds a program for this run only, on top of the ones from the file
+    pub fn add(self, prog: Program) -> error::Result<System> {
+        let mut sys = self;
+        sys.program.push(prog);
+        System::validate(sys).map_err(error::Error::config)
     }
 
     #[allow(dead_code)] // surpress false warning, used in tests
-    pub fn from_toml(toml: &str) -> Result<System> {
+    pub fn from_toml(toml: &str) -> error::Result<System> {
         Self::from_str(toml, Some(serde_any::Format::Toml))
+            .and_then(|sys| sys.expand_subsystems(Path::new(""), None))
+            .map_err(error::Error::config)
+    }
+
+    // flattens the programs of subsystems into this system, named <subsystem>.<program>.
+    // subsystem paths are relative to the including config, as are the relative
+    // paths inside the subsystem
+    fn expand_subsystems(self, base: &Path, key: Option<&str>) -> Result<System> {
+        if self.program.iter().all(|p| p.subsystem.is_none()) {
+            return Ok(self);
+        }
+
+        let mut sys = self;
+        let mut programs = Vec::new();
+        for mut entry in sys.program.drain(..) {
+            let path = match &entry.subsystem {
+                Some(path) => base.join(path),
+                None => {
+                    programs.push(entry);
+                    continue;
+                }
+            };
+
+            let subsystem = System::from_file(path.to_str().ok_or("invalid path")?, key)
+                .map_err(|e| format!("subsystem {}: {}", entry.name, e))?;
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            let namespaced = |name: &str| format!("{}.{}", entry.name, name);
+
+            let mut members = Vec::new();
+            for mut prog in subsystem.program {
+                prog.name = namespaced(prog.name.as_str());
+                prog.depends = match prog.depends.is_empty() {
+                    true => entry.depends.clone(),
+                    false => prog.depends.iter().map(|d| namespaced(d)).collect(),
+                };
+                prog.disabled |= entry.disabled;
+                prog.phase = entry.phase.clone();
+                relocate(&mut prog, dir);
+
+                members.push(prog.name.clone());
+                programs.push(prog);
+            }
+
+            entry.kind = ProgramType::Subsystem;
+            entry.depends = members;
+            programs.push(entry);
+        }
+
+        sys.program = programs;
+        System::validate(sys)
     }
 
     fn from_str(raw_data: &str, format: Option<serde_any::Format>) -> Result<System> {
         let expanded = shellexpand::env(raw_data)?;
-        let s = match format {
-            Some(format) => serde_any::from_str(&expanded, format),
-            None => serde_any::from_str_any(&expanded),
-        };
-        System::validate(s)
+        System::validate(deserialize(&expanded, format)?)
     }
 
-    fn validate(sys: std::result::Result<System, serde_any::Error>) -> Result<System> {
-        if let Err(e) = sys {
-            let e = format!("{:?}", e);
-            return Err(e.into());
+    fn validate(mut sys: System) -> Result<System> {
+        for prog in sys.program.iter_mut() {
+            if prog.max_line_length.is_none() {
+                prog.max_line_length = sys.max_line_length;
+            }
+            if prog.new_session.is_none() {
+                prog.new_session = Some(sys.new_session);
+            }
+            if let Some(reason) = prog.exclusion()? {
+                log::debug!("{} excluded: {}", prog.name, reason);
+                prog.disabled = true;
+                prog.excluded = Some(reason);
+            }
+            if prog.env_allow.is_none() {
+                prog.env_allow = sys.env_allow.clone();
+            }
+            for deny in &sys.env_deny {
+                if !prog.env_deny.contains(deny) {
+                    prog.env_deny.push(deny.clone());
+                }
+            }
+
+            let mut env = HashMap::new();
+            for group in &prog.env_groups {
+                let vars = sys.envgroup.get(group).ok_or_else(|| {
+                    format!("program {:?} uses unknown env group {:?}", prog.name, group)
+                })?;
+                env.extend(vars.clone());
+            }
+            env.extend(prog.env.drain());
+            prog.env = env;
+
+            match prog.kind {
+                ProgramType::Tunnel => prog.expand_tunnel()?,
+                ProgramType::Process if prog.exec.is_empty() && prog.subsystem.is_none() => {
+                    let msg = format!("program {:?} has no exec", prog.name);
+                    return Err(msg.into());
+                }
+                ProgramType::Process | ProgramType::Subsystem => (),
+            }
+        }
+
+        if let Some(OutputSink::Pipe(cmd)) = &sys.output {
+            if cmd.is_empty() {
+                return Err("empty output pipe".into());
+            }
         }
-        let sys = sys.unwrap();
 
         let mut found_starting_point = false;
         let mut names = HashSet::new();
+        let mut primary = None;
         for prog in &sys.program {
             if prog.depends.is_empty() {
                 found_starting_point = true;
             }
+            if !prog.hooks.on_system_ready.is_empty() {
+                let msg = format!("program {:?} can't have an on_system_ready hook", prog.name);
+                return Err(msg.into());
+            }
+            caps::kept(prog).map_err(|e| format!("program {:?}: {}", prog.name, e))?;
+            if prog.primary && primary.replace(prog.name.as_str()).is_some() {
+                let msg = format!("program {:?} is a second primary program", prog.name);
+                return Err(msg.into());
+            }
             if !names.insert(prog.name.clone()) {
                 let msg = format!("duplicate program name {:?}", prog.name);
                 return Err(msg.into());
             }
+            // 0 to 2 are the standard streams, the sockets come right after
+            let mut taken: Vec<i32> = (0..3 + prog.sockets.len() as i32).collect();
+            let notification = match prog.ready {
+                ReadySignal::NotificationFd(fd) => Some(fd),
+                _ => None,
+            };
+            for fd in prog.fds.iter().map(|f| f.fd).chain(notification) {
+                if taken.contains(&fd) {
+                    let msg = format!("program {:?} can't use fd {}", prog.name, fd);
+                    return Err(msg.into());
+                }
+                taken.push(fd);
+            }
+            if let ReadySignal::Stable(signal, secs) = &prog.ready {
+                match signal.as_ref() {
+                    ReadySignal::Port(_)
+                    | ReadySignal::Healthcheck(_)
+                    | ReadySignal::Udp(_)
+                    | ReadySignal::Dns(_)
+                    | ReadySignal::Plugin(_)
+                    | ReadySignal::Exec(_) => (),
+                    other => {
+                        let msg = format!(
+                            "program {:?} can't have a {} ready signal stable for some time",
+                            prog.name,
+                            other.kind()
+                        );
+                        return Err(msg.into());
+                    }
+                }
+                if !secs.is_finite() || *secs < 0.0 {
+                    let msg = format!("program {:?} has invalid stable_for {}", prog.name, secs);
+                    return Err(msg.into());
+                }
+            }
+            if let Some(liveness) = &prog.liveness {
+                let probes = [
+                    liveness.port.is_some(),
+                    liveness.healthcheck.is_some(),
+                    liveness.exec.is_some(),
+                ];
+                if probes.iter().filter(|p| **p).count() != 1 {
+                    let msg = format!(
+                        "program {:?} liveness needs one of port, healthcheck or exec",
+                        prog.name
+                    );
+                    return Err(msg.into());
+                }
+                if !liveness.interval.is_finite()
+                    || liveness.interval <= 0.0
+                    || liveness.failure_threshold == 0
+                {
+                    let msg = format!(
+                        "program {:?} liveness needs a positive interval and failure_threshold",
+                        prog.name
+                    );
+                    return Err(msg.into());
+                }
+                if liveness.exec.as_ref().is_some_and(|e| e.command.is_empty()) {
+                    let msg = format!("program {:?} has an empty liveness exec", prog.name);
+                    return Err(msg.into());
+                }
+            }
+            if prog.stop_signal.is_some() && !prog.stop_escalation.is_empty() {
+                let msg = format!(
+                    "program {:?} has both stop_signal and stop_escalation",
+                    prog.name
+                );
+                return Err(msg.into());
+            }
+            for step in prog.stop_escalation.iter() {
+                if !step.timeout.is_finite() || step.timeout < 0.0 {
+                    let msg = format!(
+                        "program {:?} has invalid stop timeout {}",
+                        prog.name, step.timeout
+                    );
+                    return Err(msg.into());
+                }
+            }
+            if let ReadySignal::Exec(exec) = prog.ready.signal() {
+                if exec.command.is_empty() {
+                    let msg = format!("program {:?} has an empty exec ready signal", prog.name);
+                    return Err(msg.into());
+                }
+                if !exec.interval.is_finite() || exec.interval <= 0.0 {
+                    let msg = format!(
+                        "program {:?} has invalid exec interval {}",
+                        prog.name, exec.interval
+                    );
+                    return Err(msg.into());
+                }
+            }
+            let endpoints = match prog.ready.signal() {
+                ReadySignal::Healthcheck(endpoint) => Some(endpoint.as_ref()),
+                _ => None,
+            };
+            let liveness = prog.liveness.as_ref().and_then(|l| l.healthcheck.as_ref());
+            for endpoint in endpoints.into_iter().chain(liveness) {
+                endpoint
+                    .validate()
+                    .map_err(|e| format!("program {:?}: {}", prog.name, e))?;
+            }
+            if let Some(limit) = &prog.log_rate_limit {
+                limit
+                    .validate()
+                    .map_err(|e| format!("program {:?}: {}", prog.name, e))?;
+            }
+            if let Some(OutputSink::Pipe(cmd)) = &prog.output {
+                if cmd.is_empty() {
+                    let msg = format!("program {:?} has an empty output pipe", prog.name);
+                    return Err(msg.into());
+                }
+            }
+            if let ReadySignal::Plugin(plugin) = prog.ready.signal() {
+                if plugin.exec.is_empty() {
+                    let msg = format!("program {:?} has an empty plugin ready signal", prog.name);
+                    return Err(msg.into());
+                }
+                if !plugin.interval.is_finite() || plugin.interval <= 0.0 {
+                    let msg = format!(
+                        "program {:?} has invalid plugin interval {}",
+                        prog.name, plugin.interval
+                    );
+                    return Err(msg.into());
+                }
+            }
+            if let ReadySignal::Udp(udp) = prog.ready.signal() {
+                if !udp.timeout.is_finite() || udp.timeout <= 0.0 {
+                    let msg = format!(
+                        "program {:?} has invalid udp timeout {}",
+                        prog.name, udp.timeout
+                    );
+                    return Err(msg.into());
+                }
+            }
+            if let ReadySignal::Dns(dns) = prog.ready.signal() {
+                dns.validate()
+                    .map_err(|e| format!("program {:?}: {}", prog.name, e))?;
+            }
+            if let Some(phase) = &prog.phase {
+                if !sys.phases.contains(phase) {
+                    let msg = format!("program {:?} is in unknown phase {:?}", prog.name, phase);
+                    return Err(msg.into());
+                }
+            }
         }
 
+        sys.outdir_layout.validate()?;
+        sys.channels.validate()?;
+        sys.crash_loop.validate()?;
+
         if !found_starting_point {
             return Err(string_error::new_err(
                 "No valid entry point (with empty dependency list) found",
@@ -150,10 +1734,270 @@ mod tests {
     use super::*;
 
     #[test]
+    fn test_one_primary() {
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            primary = true
+
+            [[program]]
+            name = "b"
+            exec = "foo"
+            primary = PRIMARY
+            "#;
+
+        let sys = System::from_toml(toml.replace("PRIMARY", "false").as_str()).unwrap();
+        assert!(sys.program[0].primary);
+        assert!(!sys.program[1].primary);
+
+        let e = System::from_toml(toml.replace("PRIMARY", "true").as_str()).unwrap_err();
+        assert_eq!("program \"b\" is a second primary program", e.to_string());
+    }
+
+    #[test]
+    fn test_empty_output_pipe() {
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            output = {pipe = []}
+            "#;
+        let e = System::from_toml(toml).unwrap_err();
+        assert_eq!("program \"a\" has an empty output pipe", e.to_string());
+
+        let toml = r#"
+            output = {pipe = []}
+
+            [[program]]
+            name = "a"
+            exec = "foo"
+            "#;
+        assert!(System::from_toml(toml).is_err());
+    }
+
+    #[test]
+    fn test_invalid_log_rate_limit() {
+        for limit in &[
+            "{lines_per_sec = 0, burst = 10}",
+            "{lines_per_sec = -5, burst = 10}",
+            "{lines_per_sec = nan, burst = 10}",
+            "{lines_per_sec = 100, burst = 0}",
+        ] {
+            let toml = format!(
+                "[[program]]\nname = \"a\"\nexec = \"foo\"\nlog_rate_limit = {}\n",
+                limit
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", limit);
+        }
+
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            log_rate_limit = {lines_per_sec = 0.5, burst = 1}
+            "#;
+        assert!(System::from_toml(toml).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_crash_loop() {
+        for within in &["0", "-10", "nan", "inf"] {
+            let toml = format!(
+                "crash_loop = {{restarts = 3, within = {}}}\n[[program]]\nname = \"a\"\nexec = \"foo\"\n",
+                within
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", within);
+        }
+
+        let toml = r#"
+            crash_loop = {restarts = 3, within = 0.5}
+            [[program]]
+            name = "a"
+            exec = "foo"
+            "#;
+        assert!(System::from_toml(toml).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_healthcheck_method() {
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            ready = {healthcheck = {port = 8080, path = "/", method = "G ET"}}
+            "#;
+        let e = System::from_toml(toml).unwrap_err();
+        assert_eq!(
+            "program \"a\": invalid healthcheck method \"G ET\"",
+            e.to_string()
+        );
+
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            liveness = {healthcheck = {port = 8080, path = "/", method = ""}}
+            "#;
+        assert!(System::from_toml(toml).is_err());
+
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "foo"
+            ready = {healthcheck = {port = 8080, path = "/", method = "head"}}
+            "#;
+        assert!(System::from_toml(toml).is_ok());
+    }
+
+    #[test]
+    fn test_outdir_layout() {
+        let toml = r#"
+            outdir_layout = {run_dir = "run-%Y%m%d.{pid}", latest = "current", per_program = true, compress = true}
+
+            [[program]]
+            name = "a"
+            exec = "foo"
+            "#;
+
+        let sys = System::from_toml(toml).unwrap();
+        assert_eq!(
+            OutdirLayout {
+                run_dir: "run-%Y%m%d.{pid}".to_string(),
+                latest: "current".to_string(),
+                per_program: true,
+                flat: false,
+                compress: true,
+                keep_runs: None,
+                rotate: None,
+            },
+            sys.outdir_layout
+        );
+
+        let toml = r#"
+            outdir_layout = {keep_runs = 5, rotate = {max_mb = 10}}
+
+            [[program]]
+            name = "a"
+            exec = "foo"
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+        assert_eq!(Some(5), sys.outdir_layout.keep_runs);
+        assert_eq!(
+            Some(Rotation {
+                max_mb: Some(10.0),
+                max_age: None,
+                keep: 3,
+            }),
+            sys.outdir_layout.rotate
+        );
+
+        for bad in &[
+            "{keep_runs = 0}",
+            "{rotate = {keep = 2}}",
+            "{rotate = {max_age = -1}}",
+        ] {
+            let toml = format!(
+                "outdir_layout = {}\n[[program]]\nname = \"a\"\nexec = \"foo\"",
+                bad
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", bad);
+        }
+
+        let default = System::from_toml("[[program]]\nname = \"a\"\nexec = \"foo\"").unwrap();
+        assert_eq!(OutdirLayout::default(), default.outdir_layout);
+
+        for bad in &["%Q", "runs/%Y"] {
+            let toml = format!(
+                "outdir_layout = {{run_dir = {:?}}}\n[[program]]\nname = \"a\"\nexec = \"foo\"",
+                bad
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_conditional_programs() {
+        let toml = r#"
+            [[program]]
+            name = "base"
+            exec = "e"
+            only_if = "env:PATH"
+            platforms = ["linux", "macos"]
+
+            [[program]]
+            name = "kafka"
+            exec = "e"
+            only_if = "env:DECOMPOSE_TEST_SURELY_UNSET"
+
+            [[program]]
+            name = "plan9"
+            exec = "e"
+            platforms = ["plan9"]
+            "#;
+
+        let sys = System::from_toml(toml).unwrap();
+        let (base, kafka, plan9) = (&sys.program[0], &sys.program[1], &sys.program[2]);
+        assert!(!base.disabled);
+        assert_eq!(None, base.excluded);
+        assert!(kafka.disabled);
+        assert_eq!(
+            Some("only_if env:DECOMPOSE_TEST_SURELY_UNSET, which is not set"),
+            kafka.excluded.as_deref()
+        );
+        assert!(plan9.disabled);
+        assert_eq!(
+            Some(format!("platforms plan9, not {}", std::env::consts::OS)),
+            plan9.excluded
+        );
+
+        let bad = toml.replace("env:PATH", "PATH");
+        let err = System::from_toml(bad.as_str()).unwrap_err().to_string();
+        assert_eq!(
+            "program \"base\" has only_if \"PATH\", expected env:<variable>",
+            err
+        );
+    }
+
+    #[test]
+    fn test_stdin_modes() {
+        for (raw, stdin) in &[
+            ("\"null\"", Stdin::Null),
+            ("\"inherit\"", Stdin::Inherit),
+            ("\"pipe\"", Stdin::Pipe),
+            ("{file = \"in.txt\"}", Stdin::File("in.txt".to_string())),
+        ] {
+            let toml = format!("[[program]]\nname = \"a\"\nexec = \"e\"\nstdin = {}", raw);
+            let sys = System::from_toml(toml.as_str()).unwrap();
+            assert_eq!(stdin, &sys.program[0].stdin);
+        }
+    }
+
+    #[test]
+    fn test_channels_need_room() {
+        let toml = "channels = {commands = 0}\n[[program]]\nname = \"a\"\nexec = \"foo\"";
+        let err = System::from_toml(toml).unwrap_err().to_string();
+        assert_eq!("channel capacities must be at least 1", err);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_read() {
         let toml = r#"
             start_timeout = 10.2
             terminate_timeout = 0.5
+            max_concurrent_stops = 3
+            max_concurrent_probes = 8
+            port_check = "fail"
+            on_parent_death = "kill"
+            core_dumps = true
+            crash_loop = {restarts = 3, within = 10}
+            restart_limit = {restarts = 10}
+            channels = {events = 64, overflow = "fail"}
+            max_line_length = 4096
+            new_session = true
+            output = {pipe = ["./to-loki.sh", "--all"]}
+            hooks = {on_stop = ["./stopped.sh"], on_system_ready = ["./all-up.sh"]}
 
             [[program]]
             name = "prog1"
@@ -169,12 +2013,63 @@ mod tests {
             cwd = "."
             critical = true
             disabled = true
+            quiet = true
+            log_rate_limit = {lines_per_sec = 100, burst = 500}
+            max_line_length = 100
+            new_session = false
+            output = {pipe = ["./to-loki.sh"]}
+            hooks = {on_ready = ["notify-send", "ready"], on_crash = ["./crashed.sh"]}
+            build = ["make", "exec"]
+            umask = "0027"
+            cpus = [0, 2]
+            seccomp = "default"
+            drop_caps = ["ALL"]
+            keep_caps = ["NET_BIND_SERVICE"]
+            scratch_dir = true
+            keep_scratch_on_failure = true
+            watch = ["src/**/*.rs"]
+            watch_dependents = true
+            stdin = {file = "fixtures/input.jsonl"}
+            exports = {DB_URL = {from_stdout = "url=(.*)"}, TOKEN = {from_stderr = "token: (\\w+)"}}
         "#;
 
         let system = System::from_toml(toml).unwrap();
 
         assert!((system.terminate_timeout - 0.5).abs() < 0.001);
         assert!((system.start_timeout.unwrap() - 10.2).abs() < 0.001);
+        assert_eq!(Some(3), system.max_concurrent_stops);
+        assert_eq!(Some(8), system.max_concurrent_probes);
+        assert_eq!(PortCheck::Fail, system.port_check);
+        assert_eq!(ParentDeath::Kill, system.on_parent_death);
+        assert!(system.core_dumps);
+        assert_eq!(3, system.crash_loop.restarts);
+        assert_eq!(
+            Channels {
+                commands: 10,
+                events: 64,
+                output: 100,
+                overflow: Overflow::Fail,
+            },
+            system.channels
+        );
+        assert!((system.crash_loop.within - 10.0).abs() < 0.001);
+        assert_eq!(
+            Some(RestartLimit {
+                restarts: 10,
+                per: 60.0
+            }),
+            system.restart_limit
+        );
+        assert_eq!(
+            Some(OutputSink::Pipe(vec![
+                "./to-loki.sh".to_string(),
+                "--all".to_string()
+            ])),
+            system.output
+        );
+        assert_eq!(vec!["./stopped.sh"], system.hooks.on_stop);
+        assert_eq!(vec!["./all-up.sh"], system.hooks.on_system_ready);
+        assert!(system.hooks.on_ready.is_empty());
 
         let prog1 = &system.program[0];
 
@@ -186,6 +2081,20 @@ mod tests {
         assert_eq!("/tmp", prog1.cwd);
         assert_eq!(false, prog1.critical);
         assert_eq!(false, prog1.disabled);
+        assert!(!prog1.quiet);
+        assert_eq!(None, prog1.log_rate_limit);
+        assert_eq!(Some(4096), prog1.max_line_length);
+        assert_eq!(Some(true), prog1.new_session);
+        assert_eq!(None, prog1.output);
+        assert_eq!(None, prog1.umask);
+        assert!(prog1.cpus.is_empty());
+        assert_eq!(None, prog1.seccomp);
+        assert!(!prog1.scratch_dir);
+        assert!(!prog1.keep_scratch_on_failure);
+        assert!(prog1.watch.is_empty());
+        assert_eq!(Stdin::Null, prog1.stdin);
+        assert!(prog1.exports.is_empty());
+        assert_eq!(Hooks::default(), prog1.hooks);
 
         let prog2 = &system.program[1];
 
@@ -196,57 +2105,333 @@ mod tests {
         assert_eq!(".", prog2.cwd);
         assert_eq!(true, prog2.critical);
         assert_eq!(true, prog2.disabled);
+        assert!(prog2.quiet);
+        assert_eq!(Some(100), prog2.max_line_length);
+        assert_eq!(Some(false), prog2.new_session);
+        assert_eq!(
+            Some(RateLimit {
+                lines_per_sec: 100.0,
+                burst: 500
+            }),
+            prog2.log_rate_limit
+        );
+        assert_eq!(
+            Some(OutputSink::Pipe(vec!["./to-loki.sh".to_string()])),
+            prog2.output
+        );
+        assert_eq!(vec!["notify-send", "ready"], prog2.hooks.on_ready);
+        assert_eq!(vec!["./crashed.sh"], prog2.hooks.on_crash);
+        assert!(prog2.hooks.on_stop.is_empty());
+        assert_eq!(vec!["make", "exec"], prog2.build);
+        assert_eq!(Some(Umask(0o027)), prog2.umask);
+        assert_eq!(vec![0, 2], prog2.cpus);
+        assert_eq!(Some(Seccomp::Default), prog2.seccomp);
+        assert_eq!(vec!["ALL"], prog2.drop_caps);
+        assert_eq!(vec!["NET_BIND_SERVICE"], prog2.keep_caps);
+        assert!(prog2.scratch_dir);
+        assert!(prog2.keep_scratch_on_failure);
+        assert_eq!(vec!["src/**/*.rs".to_string()], prog2.watch);
+        assert!(prog2.watch_dependents);
+        assert_eq!(Stdin::File("fixtures/input.jsonl".to_string()), prog2.stdin);
+        assert_eq!(
+            Some(&Export::FromStdout("url=(.*)".to_string())),
+            prog2.exports.get("DB_URL")
+        );
+        assert_eq!(
+            Some(&Export::FromStderr("token: (\\w+)".to_string())),
+            prog2.exports.get("TOKEN")
+        );
+    }
+
+    #[test]
+    fn test_optional_values_give_defaults() {
+        let toml = r#"
+            [[program]]
+            name = "prog"
+            exec = "abc"
+        "#;
+
+        let system = System::from_toml(toml).unwrap();
+
+        assert!((system.terminate_timeout - 1.0).abs() < 0.001);
+        assert_eq!(None, system.start_timeout);
+        assert_eq!(None, system.max_concurrent_stops);
+        assert_eq!(None, system.max_concurrent_probes);
+        assert_eq!(PortCheck::Warn, system.port_check);
+        assert_eq!(ParentDeath::Term, system.on_parent_death);
+        assert!(!system.core_dumps);
+        assert_eq!(CrashLoop::default(), system.crash_loop);
+        assert_eq!(None, system.restart_limit);
+        assert_eq!(Channels::default(), system.channels);
+
+        let prog = &system.program[0];
+
+        assert_eq!(0, prog.env.len());
+        assert_eq!(default_cwd(), prog.cwd);
+        assert_eq!(ReadySignal::Nothing, prog.ready);
+        assert!(prog.build.is_empty());
+        assert_eq!(DepFailurePolicy::Abort, prog.on_dep_failure);
+    }
+
+    #[test]
+    fn test_fail_if_mandatory_are_absent() {
+        let toml = r#"
+            [[program]]
+            exec = "abc"
+        "#;
+
+        let res = System::from_toml(toml);
+        res.unwrap_err();
+
+        let toml = r#"
+            [[program]]
+            name = "prog"
+        "#;
+
+        let res = System::from_toml(toml);
+        res.unwrap_err();
+    }
+
+    #[test]
+    fn test_fail_unless_exec_is_given() {
+        let toml = r#"
+            [[program]]
+            name = "prog"
+            args = []
+        "#;
+
+        let res = System::from_toml(toml);
+        res.unwrap_err();
+    }
+
+    #[test]
+    fn test_tunnel() {
+        let toml = r#"
+            [[program]]
+            name = "db"
+            type = "tunnel"
+            via = "user@bastion"
+            local = 5432
+            remote = "db.internal:5432"
+        "#;
+
+        let sys = System::from_toml(toml).unwrap();
+        let prog = &sys.program[0];
+
+        assert_eq!(ProgramType::Tunnel, prog.kind);
+        assert_eq!("ssh", prog.exec);
+        assert_eq!(
+            vec![
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-L",
+                "5432:db.internal:5432",
+                "user@bastion"
+            ],
+            prog.args
+        );
+        assert_eq!(ReadySignal::Port(5432), prog.ready);
+
+        let incomplete = r#"
+            [[program]]
+            name = "db"
+            type = "tunnel"
+            via = "user@bastion"
+        "#;
+        System::from_toml(incomplete).unwrap_err();
     }
 
     #[test]
-    fn test_optional_values_give_defaults() {
-        let toml = r#"
+    fn test_subsystem() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("payments")).unwrap();
+        std::fs::write(
+            dir.path().join("payments/decompose.toml"),
+            r#"
             [[program]]
-            name = "prog"
-            exec = "abc"
-        "#;
+            name = "db"
+            exec = "./db"
 
-        let system = System::from_toml(toml).unwrap();
+            [[program]]
+            name = "api"
+            exec = "api"
+            cwd = "run"
+            depends = ["db"]
+            stdin = {file = "fixtures/input.jsonl"}
+            "#,
+        )
+        .unwrap();
+        let parent = dir.path().join("decompose.toml");
+        std::fs::write(
+            &parent,
+            r#"
+            [[program]]
+            name = "infra"
+            exec = "infra"
 
-        assert!((system.terminate_timeout - 1.0).abs() < 0.001);
-        assert_eq!(None, system.start_timeout);
+            [[program]]
+            name = "payments"
+            subsystem = "payments/decompose.toml"
+            depends = ["infra"]
 
-        let prog = &system.program[0];
+            [[program]]
+            name = "app"
+            exec = "app"
+            depends = ["payments"]
+            "#,
+        )
+        .unwrap();
+
+        let sys = System::from_file(parent.to_str().unwrap(), None).unwrap();
+        let names: Vec<&str> = sys.program.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            vec!["infra", "payments.db", "payments.api", "payments", "app"],
+            names
+        );
 
-        assert_eq!(0, prog.env.len());
-        assert_eq!(default_cwd(), prog.cwd);
-        assert_eq!(ReadySignal::Nothing, prog.ready);
+        let sub = dir.path().join("payments");
+        let db = &sys.program[1];
+        assert_eq!(vec!["infra"], db.depends);
+        assert_eq!(sub.join("./db").to_str().unwrap(), db.exec);
+        assert_eq!(sub.to_str().unwrap(), db.cwd);
+
+        let api = &sys.program[2];
+        assert_eq!(vec!["payments.db"], api.depends);
+        assert_eq!("api", api.exec);
+        assert_eq!(sub.join("run").to_str().unwrap(), api.cwd);
+        assert_eq!(Some(sub.join("run/fixtures/input.jsonl")), api.stdin_file());
+
+        let payments = &sys.program[3];
+        assert_eq!(ProgramType::Subsystem, payments.kind);
+        assert_eq!(vec!["payments.db", "payments.api"], payments.depends);
     }
 
     #[test]
-    fn test_fail_if_mandatory_are_absent() {
+    fn test_env_filters() {
         let toml = r#"
+            env_allow = ["PATH", "HOME", "LANG", "LC_*"]
+            env_deny = ["AWS_*"]
+
             [[program]]
-            exec = "abc"
-        "#;
+            name = "a"
+            exec = "a"
 
-        let res = System::from_toml(toml);
-        res.unwrap_err();
+            [[program]]
+            name = "b"
+            exec = "b"
+            env_allow = ["*"]
+            env_deny = ["*_TOKEN"]
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+
+        let a = &sys.program[0];
+        assert!(a.filters_env());
+        assert!(a.inherits("PATH"));
+        assert!(a.inherits("LC_ALL"));
+        assert!(!a.inherits("AWS_SECRET_ACCESS_KEY"));
+        assert!(!a.inherits("USER"));
+
+        let b = &sys.program[1];
+        assert!(b.inherits("USER"));
+        assert!(!b.inherits("AWS_PROFILE"));
+        assert!(!b.inherits("GITHUB_TOKEN"));
+
+        let sys = System::from_toml("[[program]]\nname = \"a\"\nexec = \"a\"").unwrap();
+        assert!(!sys.program[0].filters_env());
+        assert!(sys.program[0].inherits("AWS_PROFILE"));
+    }
 
+    #[test]
+    fn test_fds() {
         let toml = r#"
             [[program]]
-            name = "prog"
-        "#;
+            name = "a"
+            exec = "a"
+            sockets = [{port = 8080}]
+            fds = [{fd = 4, pipe_write = "events"}, {fd = 5, read = "input.txt"}]
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+        assert_eq!(
+            vec![
+                Fd {
+                    fd: 4,
+                    source: FdSource::PipeWrite("events".to_string())
+                },
+                Fd {
+                    fd: 5,
+                    source: FdSource::Read("input.txt".to_string())
+                },
+            ],
+            sys.program[0].fds
+        );
 
-        let res = System::from_toml(toml);
-        res.unwrap_err();
+        // taken by the socket
+        assert!(System::from_toml(toml.replace("fd = 4", "fd = 3").as_str()).is_err());
+        assert!(System::from_toml(toml.replace("fd = 4", "fd = 5").as_str()).is_err());
     }
 
     #[test]
-    fn test_fail_unless_exec_is_given() {
+    fn test_env_groups() {
         let toml = r#"
+            [envgroup.aws]
+            AWS_REGION = "eu-west-1"
+            AWS_PROFILE = "dev"
+
+            [envgroup.tracing]
+            OTEL_ENDPOINT = "localhost:4317"
+            AWS_PROFILE = "tracing"
+
             [[program]]
-            name = "prog"
-            args = []
-        "#;
+            name = "a"
+            exec = "a"
+            env_groups = ["aws", "tracing"]
+            env = {AWS_REGION = "us-east-1"}
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+        let env = &sys.program[0].env;
+        assert_eq!(3, env.len());
+        assert_eq!("us-east-1", env["AWS_REGION"]);
+        assert_eq!("tracing", env["AWS_PROFILE"]);
+        assert_eq!("localhost:4317", env["OTEL_ENDPOINT"]);
+
+        let unknown = toml.replace("\"tracing\"]", "\"nope\"]");
+        assert!(System::from_toml(unknown.as_str()).is_err());
+    }
 
-        let res = System::from_toml(toml);
-        res.unwrap_err();
+    #[test]
+    fn test_error_locations() {
+        let toml = r#"
+            [[program]]
+            name = "a"
+            exec = "a"
+
+            [[program]]
+            name = "b"
+            exec = "b"
+            ready = {port = "http"}
+            "#;
+        let e = System::from_toml(toml).unwrap_err().to_string();
+        assert!(e.starts_with("program[1].ready.port: "), "{}", e);
+        assert!(e.contains("\"http\""), "{}", e);
+        assert!(e.contains("line 9"), "{}", e);
+
+        let yaml = "program:\n  - name: a\n    exec: a\n    critical: maybe\n";
+        let e = System::from_str(yaml, Some(serde_any::Format::Yaml))
+            .unwrap_err()
+            .to_string();
+        assert!(e.starts_with("program[0].critical: "), "{}", e);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("PATH", "PATH"));
+        assert!(!wildcard_match("PATH", "PATHS"));
+        assert!(wildcard_match("AWS_*", "AWS_"));
+        assert!(wildcard_match("*_KEY", "API_KEY"));
+        assert!(wildcard_match("A*B*C", "AxxBxxC"));
+        assert!(!wildcard_match("A*B*C", "AxxCxxB"));
+        assert!(wildcard_match("*", ""));
     }
 
     #[test]
@@ -289,6 +2474,7 @@ mod tests {
             name = "port"
             exec = "foo"
             ready = {port = 123}
+            ready_debug = true
 
             [[program]]
             name = "nothing"
@@ -324,12 +2510,29 @@ mod tests {
             name = "healthcheck"
             exec = "foo"
             ready = {healthcheck={port=123, path="/health", host="localhost"}}
+
+            [[program]]
+            name = "plugin"
+            exec = "foo"
+            ready = {plugin={exec="./check-kafka.sh", args=["--topic", "events"]}}
+
+            [[program]]
+            name = "udp"
+            exec = "foo"
+            ready = {udp={port=8125, payload="ping", expect_reply=true}}
+
+            [[program]]
+            name = "dns"
+            exec = "foo"
+            ready = {dns={name="minio.local", server="127.0.0.1:8600"}}
             "#;
 
         let res = System::from_toml(toml).unwrap();
 
         assert_eq!(ReadySignal::Nothing, res.program[0].ready);
         assert_eq!(ReadySignal::Port(123), res.program[1].ready);
+        assert!(res.program[1].ready_debug);
+        assert!(!res.program[0].ready_debug);
         assert_eq!(ReadySignal::Nothing, res.program[2].ready);
         assert_eq!(ReadySignal::Manual, res.program[3].ready);
         assert_eq!(ReadySignal::Timer(0.5), res.program[4].ready);
@@ -344,13 +2547,376 @@ mod tests {
         assert_eq!(ReadySignal::Completed, res.program[7].ready);
 
         assert_eq!(
-            ReadySignal::Healthcheck(Endpoint {
+            ReadySignal::Healthcheck(Box::new(Endpoint {
                 port: 123,
                 path: "/health".to_string(),
-                host: "localhost".to_string()
-            }),
+                host: "localhost".to_string(),
+                expect_json: None,
+                method: "GET".to_string(),
+                headers: HashMap::new(),
+                body: None,
+                tls: false,
+                insecure: false,
+                status: Vec::new(),
+            })),
             res.program[8].ready
         );
+
+        assert_eq!(
+            ReadySignal::Plugin(Plugin {
+                exec: "./check-kafka.sh".to_string(),
+                args: vec!["--topic".to_string(), "events".to_string()],
+                interval: 1.0,
+            }),
+            res.program[9].ready
+        );
+
+        assert_eq!(
+            ReadySignal::Udp(Udp {
+                port: 8125,
+                host: "127.0.0.1".to_string(),
+                payload: "ping".to_string(),
+                expect_reply: true,
+                timeout: 0.1,
+            }),
+            res.program[10].ready
+        );
+
+        assert_eq!(
+            ReadySignal::Dns(Dns {
+                name: "minio.local".to_string(),
+                server: Some("127.0.0.1:8600".to_string()),
+            }),
+            res.program[11].ready
+        );
+    }
+
+    #[test]
+    fn test_liveness() {
+        let toml = r#"
+            [[program]]
+            name = "api"
+            exec = "e"
+            liveness = {healthcheck = {port = 8080, path = "/alive"}, interval = 2, on_failure = "degrade"}
+
+            [[program]]
+            name = "cache"
+            exec = "e"
+            liveness = {exec = ["redis-cli", "ping"]}
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+
+        let api = sys.program[0].liveness.as_ref().unwrap();
+        assert_eq!(8080, api.healthcheck.as_ref().unwrap().port);
+        assert_eq!(2.0, api.interval);
+        assert_eq!(3, api.failure_threshold);
+        assert_eq!(LivenessFailure::Degrade, api.on_failure);
+
+        let cache = sys.program[1].liveness.as_ref().unwrap();
+        assert_eq!(
+            vec!["redis-cli", "ping"],
+            cache.exec.as_ref().unwrap().command
+        );
+        assert_eq!(5.0, cache.interval);
+        assert_eq!(LivenessFailure::Restart, cache.on_failure);
+        assert!(sys.program[1].ready == ReadySignal::Nothing);
+
+        for liveness in &[
+            "{interval = 1}",
+            "{port = 1, exec = [\"true\"]}",
+            "{port = 1, failure_threshold = 0}",
+            "{port = 1, interval = 0}",
+        ] {
+            let toml = format!(
+                "[[program]]\nname = \"a\"\nexec = \"e\"\nliveness = {}\n",
+                liveness
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", liveness);
+        }
+    }
+
+    #[test]
+    fn test_stop_signals() {
+        use nix::sys::signal::Signal::{SIGINT, SIGKILL, SIGTERM, SIGUSR2};
+
+        let toml = r#"
+            terminate_timeout = 2.0
+
+            [[program]]
+            name = "default"
+            exec = "e"
+
+            [[program]]
+            name = "int"
+            exec = "e"
+            stop_signal = "SIGINT"
+
+            [[program]]
+            name = "drain"
+            exec = "e"
+            stop_escalation = [{signal = "usr2", timeout = 30}, {signal = "TERM", timeout = 5}]
+            "#;
+        let sys = System::from_toml(toml).unwrap();
+
+        let steps = |i: usize| -> Vec<(nix::sys::signal::Signal, f64)> {
+            sys.program[i]
+                .stop_steps(sys.terminate_timeout)
+                .into_iter()
+                .map(|step| (step.signal.0, step.timeout))
+                .collect()
+        };
+        assert_eq!(vec![(SIGTERM, 2.0)], steps(0));
+        assert_eq!(vec![(SIGINT, 2.0)], steps(1));
+        assert_eq!(vec![(SIGUSR2, 30.0), (SIGTERM, 5.0)], steps(2));
+        assert_eq!(Ok(Signal(SIGKILL)), "kill".parse());
+
+        for stop in &[
+            "stop_signal = \"SIGNOPE\"",
+            "stop_escalation = [{signal = \"INT\", timeout = -1}]",
+            "stop_signal = \"INT\"\nstop_escalation = [{signal = \"INT\", timeout = 1}]",
+        ] {
+            let toml = format!("[[program]]\nname = \"a\"\nexec = \"e\"\n{}\n", stop);
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", stop);
+        }
+    }
+
+    #[test]
+    fn test_exec_ready_signal() {
+        let toml = r#"
+            [[program]]
+            name = "db"
+            exec = "postgres"
+            ready = {exec = ["pg_isready", "-h", "localhost"]}
+
+            [[program]]
+            name = "cache"
+            exec = "redis-server"
+            ready = {exec = {command = ["redis-cli", "ping"], interval = 0.2}}
+            "#;
+        let res = System::from_toml(toml).unwrap();
+
+        assert_eq!(
+            ReadySignal::Exec(Exec {
+                command: vec![
+                    "pg_isready".to_string(),
+                    "-h".to_string(),
+                    "localhost".to_string()
+                ],
+                interval: 1.0,
+            }),
+            res.program[0].ready
+        );
+        assert_eq!(
+            "exec pg_isready -h localhost",
+            res.program[0].ready.describe()
+        );
+        assert_eq!(
+            ReadySignal::Exec(Exec {
+                command: vec!["redis-cli".to_string(), "ping".to_string()],
+                interval: 0.2,
+            }),
+            res.program[1].ready
+        );
+
+        for ready in &[
+            "{exec = []}",
+            "{exec = {command = [\"true\"], interval = 0}}",
+        ] {
+            let toml = format!(
+                "[[program]]\nname = \"db\"\nexec = \"postgres\"\nready = {}\n",
+                ready
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", ready);
+        }
+    }
+
+    #[test]
+    fn test_invalid_plugin_ready_signal() {
+        for ready in &[
+            "{plugin = {exec = \"\"}}",
+            "{plugin = {exec = \"./check.sh\", interval = 0}}",
+            "{plugin = {exec = \"./check.sh\", interval = -1.0}}",
+            "{plugin = {exec = \"./check.sh\", interval = nan}}",
+        ] {
+            let toml = format!(
+                "[[program]]\nname = \"db\"\nexec = \"postgres\"\nready = {}\n",
+                ready
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", ready);
+        }
+    }
+
+    #[test]
+    fn test_invalid_udp_ready_signal() {
+        for timeout in &["0", "-0.5", "nan", "inf"] {
+            let toml = format!(
+                "[[program]]\nname = \"statsd\"\nexec = \"statsd\"\nready = {{udp = {{port = 8125, timeout = {}}}}}\n",
+                timeout
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", timeout);
+        }
+    }
+
+    #[test]
+    fn test_invalid_dns_ready_signal() {
+        let long = "a".repeat(64);
+        for name in &["", ".", "a..b", ".minio", long.as_str()] {
+            let toml = format!(
+                "[[program]]\nname = \"minio\"\nexec = \"minio\"\nready = {{dns = {{name = {:?}}}}}\n",
+                name
+            );
+            assert!(System::from_toml(toml.as_str()).is_err(), "{}", name);
+        }
+
+        let toml = r#"
+            [[program]]
+            name = "minio"
+            exec = "minio"
+            ready = {dns = {name = "minio.local."}}
+            "#;
+        assert!(System::from_toml(toml).is_ok());
+    }
+
+    #[test]
+    fn test_stable_ready_signal() {
+        let toml = r#"
+            [[program]]
+            name = "port"
+            exec = "foo"
+            ready = {port = 8080, stable_for = 3.0}
+
+            [[program]]
+            name = "plugin"
+            exec = "foo"
+            ready = {stable_for = 1, plugin = {exec = "./check.sh"}}
+            "#;
+        let res = System::from_toml(toml).unwrap();
+
+        let port = &res.program[0].ready;
+        assert_eq!(
+            ReadySignal::Stable(Box::new(ReadySignal::Port(8080)), 3.0),
+            *port
+        );
+        assert_eq!(&ReadySignal::Port(8080), port.signal());
+        assert_eq!("port", port.kind());
+        assert_eq!("port 8080, stable for 3s", port.describe());
+        assert_eq!(vec![8080], res.program[0].ports());
+        assert_eq!("plugin", res.program[1].ready.kind());
+
+        let toml = r#"
+            [[program]]
+            name = "stdout"
+            exec = "foo"
+            ready = {stdout = "^ready$", stable_for = 3.0}
+            "#;
+        assert!(System::from_toml(toml).is_err());
+
+        let toml = r#"
+            [[program]]
+            name = "twice"
+            exec = "foo"
+            ready = {port = 8080, udp = {port = 8080}}
+            "#;
+        assert!(System::from_toml(toml).is_err());
+    }
+
+    #[test]
+    fn test_healthcheck_request() {
+        let toml = r#"
+            [[program]]
+            name = "healthcheck"
+            exec = "foo"
+            ready = {healthcheck={port=123, path="/ready", method="POST", headers={Authorization="Bearer abc"}, body="{}", tls=true, insecure=true, status=["200-299", "401"]}}
+            "#;
+
+        let res = System::from_toml(toml).unwrap();
+
+        match &res.program[0].ready {
+            ReadySignal::Healthcheck(endpoint) => {
+                assert_eq!("POST", endpoint.method);
+                assert_eq!("Bearer abc", endpoint.headers["Authorization"]);
+                assert_eq!(Some("{}".to_string()), endpoint.body);
+                assert!(endpoint.tls);
+                assert!(endpoint.insecure);
+                assert_eq!(
+                    vec![
+                        StatusRange { from: 200, to: 299 },
+                        StatusRange { from: 401, to: 401 }
+                    ],
+                    endpoint.status
+                );
+            }
+            r => panic!("unexpected ready signal {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_status_range() {
+        let range: StatusRange = "200-299".parse().unwrap();
+        assert!(range.contains(200));
+        assert!(range.contains(299));
+        assert!(!range.contains(300));
+
+        let single: StatusRange = "401".parse().unwrap();
+        assert!(single.contains(401));
+        assert!(!single.contains(400));
+
+        "abc".parse::<StatusRange>().unwrap_err();
+        "300-200".parse::<StatusRange>().unwrap_err();
+    }
+
+    #[test]
+    fn test_healthcheck_expect_json() {
+        let toml = r#"
+            [[program]]
+            name = "healthcheck"
+            exec = "foo"
+            ready = {healthcheck={port=123, path="/health", expect_json={pointer="/status", equals="UP"}}}
+            "#;
+
+        let res = System::from_toml(toml).unwrap();
+
+        match &res.program[0].ready {
+            ReadySignal::Healthcheck(endpoint) => assert_eq!(
+                Some(JsonExpectation {
+                    pointer: "/status".to_string(),
+                    equals: serde_json::Value::String("UP".to_string()),
+                }),
+                endpoint.expect_json
+            ),
+            r => panic!("unexpected ready signal {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_ports() {
+        let toml = r#"
+            [[program]]
+            name = "port"
+            exec = "foo"
+            ready = {port = 123}
+
+            [[program]]
+            name = "local"
+            exec = "foo"
+            ready = {healthcheck={port=456, path="/health"}}
+
+            [[program]]
+            name = "remote"
+            exec = "foo"
+            ready = {healthcheck={port=789, path="/health", host="example.com"}}
+
+            [[program]]
+            name = "nothing"
+            exec = "foo"
+            "#;
+
+        let res = System::from_toml(toml).unwrap();
+
+        assert_eq!(vec![123], res.program[0].ports());
+        assert_eq!(vec![456], res.program[1].ports());
+        assert!(res.program[2].ports().is_empty());
+        assert!(res.program[3].ports().is_empty());
     }
 
     #[test]
@@ -372,6 +2938,81 @@ mod tests {
         assert_eq!(vec!["default"], res.program[1].depends);
     }
 
+    #[test]
+    fn test_on_dep_failure() {
+        let toml = r#"
+            [[program]]
+            name = "abort"
+            exec = "foo"
+            on_dep_failure = "abort"
+
+            [[program]]
+            name = "skip"
+            exec = "foo"
+            on_dep_failure = "skip"
+
+            [[program]]
+            name = "start_anyway"
+            exec = "foo"
+            on_dep_failure = "start_anyway"
+            "#;
+
+        let res = System::from_toml(toml).unwrap();
+
+        assert_eq!(DepFailurePolicy::Abort, res.program[0].on_dep_failure);
+        assert_eq!(DepFailurePolicy::Skip, res.program[1].on_dep_failure);
+        assert_eq!(DepFailurePolicy::StartAnyway, res.program[2].on_dep_failure);
+    }
+
+    #[test]
+    fn test_program_from_spec() {
+        let prog = Program::from_spec(
+            r#"name=debugger exec=dlv args="attach 123" depends=api,db critical=true ready='{"port": 2345}'"#,
+        )
+        .unwrap();
+
+        assert_eq!("debugger", prog.name);
+        assert_eq!("dlv", prog.exec);
+        assert_eq!(vec!["attach", "123"], prog.args);
+        assert_eq!(vec!["api", "db"], prog.depends);
+        assert!(prog.critical);
+        assert_eq!(ReadySignal::Port(2345), prog.ready);
+
+        assert!(Program::from_spec("exec=x").is_err());
+        assert!(Program::from_spec("name=x exec").is_err());
+        assert!(Program::from_spec("name=x exec='y").is_err());
+    }
+
+    #[test]
+    fn test_add_program() {
+        let toml = r#"
+            [[program]]
+            name = "api"
+            exec = "foo"
+            "#;
+
+        let sys = System::from_toml(toml).unwrap();
+        let dup = Program::from_spec("name=api exec=bar").unwrap();
+        assert!(System::from_toml(toml).unwrap().add(dup).is_err());
+
+        let sys = sys
+            .add(Program::from_spec("name=debugger exec=dlv depends=api").unwrap())
+            .unwrap();
+        assert_eq!(2, sys.program.len());
+
+        let no_exec = Program::from_spec("name=x").unwrap();
+        assert!(sys.add(no_exec).is_err());
+    }
+
+    #[test]
+    fn test_umask() {
+        assert_eq!(Ok(Umask(0o022)), "0022".parse());
+        assert_eq!(Ok(Umask(0o7)), "7".parse());
+        assert!("0888".parse::<Umask>().is_err());
+        assert!("1777".parse::<Umask>().is_err());
+        assert!("u=rwx".parse::<Umask>().is_err());
+    }
+
     #[test]
     fn test_env_vars_are_expanded() {
         use std::env::set_var;
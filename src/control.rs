@@ -0,0 +1,248 @@
+extern crate log;
+extern crate tokio;
+
+use super::config;
+use super::executor::Tracker;
+use super::graph::{Graph, NodeHandle};
+use super::process::{self, mpsc, Control, Event};
+
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// the socket of the instance running against outdir, which also holds its lock
+pub fn path(outdir: &Path) -> PathBuf {
+    outdir.join("decompose.sock")
+}
+
+// replaces a socket left behind by an instance that is gone, which it has to be
+// as the lock is ours
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path)
+}
+
+// answers requests like "status" and "restart api", one per connection. starting,
+// stopping and restarting is up to the executor, to which they are passed on
+#[derive(Clone)]
+pub struct Server {
+    programs: Vec<(String, NodeHandle)>,
+    tracker: Tracker,
+    events: mpsc::Sender<Event>,
+    overflow: config::Overflow,
+}
+
+impl Server {
+    pub fn new(
+        sys: &config::System,
+        tracker: Tracker,
+        events: mpsc::Sender<Event>,
+    ) -> Result<Server> {
+        let graph = Graph::from_config(sys)?;
+        let programs = graph
+            .all()
+            .map(|h| (graph.node(h).name.clone(), h))
+            .collect();
+
+        Ok(Server {
+            programs,
+            tracker,
+            events,
+            overflow: sys.channels.overflow,
+        })
+    }
+
+    // the lines to answer with, an error is a single line starting with "error: "
+    async fn handle(&mut self, request: &str) -> String {
+        let words: Vec<&str> = request.split_whitespace().collect();
+        let (action, program) = match words.as_slice() {
+            ["status"] => return self.status(),
+            [action, program] => (*action, *program),
+            _ => {
+                return "error: expected status, or start, stop, restart or reload and a program\n"
+                    .to_string()
+            }
+        };
+
+        let action = match Control::parse(action) {
+            Some(action) => action,
+            None => return format!("error: unknown action {}\n", action),
+        };
+        let handle = match self.programs.iter().find(|(name, _)| name == program) {
+            Some((_, h)) => *h,
+            None => return format!("error: No such program: {}\n", program),
+        };
+
+        match process::send(
+            &mut self.events,
+            Event::Control(action, handle),
+            self.overflow,
+        )
+        .await
+        {
+            Ok(()) => format!("{} {} requested\n", action.as_str(), program),
+            Err(e) => format!("error: {}\n", e),
+        }
+    }
+
+    fn status(&self) -> String {
+        let width = self
+            .programs
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("phase".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut out = format!(
+            "{:width$}  {}\n",
+            "phase",
+            self.tracker.phase(),
+            width = width
+        );
+        for (name, _) in self.programs.iter() {
+            let state = self.tracker.node(name);
+            out += &format!("{:width$}  {}\n", name, state.as_str(), width = width);
+        }
+        out
+    }
+}
+
+pub async fn serve(mut listener: UnixListener, server: Server) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream, server.clone()));
+            }
+            Err(e) => log::warn!("control socket: {}", e),
+        }
+    }
+}
+
+async fn respond(mut stream: UnixStream, mut server: Server) {
+    let (reader, mut writer) = stream.split();
+    let mut request = String::new();
+    if let Err(e) = BufReader::new(reader).read_line(&mut request).await {
+        log::debug!("control socket: {}", e);
+        return;
+    }
+
+    log::debug!("control request {:?}", request.trim());
+    let response = server.handle(request.trim()).await;
+    if let Err(e) = writer.write_all(response.as_bytes()).await {
+        log::debug!("control socket: {}", e);
+    }
+}
+
+// sends the request to the instance running against outdir, and gives its answer
+pub fn request(outdir: &Path, request: &str) -> Result<String> {
+    use std::io::{Read, Write};
+
+    let path = path(outdir);
+    let mut stream = std::os::unix::net::UnixStream::connect(&path).map_err(|e| {
+        format!(
+            "no decompose running with outdir {}: {}",
+            outdir.display(),
+            e
+        )
+    })?;
+    writeln!(stream, "{}", request)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    match response.strip_prefix("error: ") {
+        Some(e) => Err(e.trim_end().into()),
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[program]]
+        name = "db"
+        exec = "e"
+
+        [[program]]
+        name = "api"
+        exec = "e"
+        depends = ["db"]
+        "#;
+
+    fn server() -> (Server, mpsc::Receiver<Event>) {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let (tx, rx) = mpsc::channel(10);
+        (Server::new(&sys, Tracker::default(), tx).unwrap(), rx)
+    }
+
+    #[tokio::test]
+    async fn requests_are_passed_on() {
+        let (mut server, mut rx) = server();
+
+        assert_eq!(
+            "restart api requested\n",
+            server.handle("restart api").await
+        );
+        match rx.recv().await {
+            Some(Event::Control(Control::Restart, _)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(server.handle("restart").await.starts_with("error: "));
+        assert_eq!(
+            "error: unknown action kill\n",
+            server.handle("kill api").await
+        );
+        assert_eq!(
+            "error: No such program: web\n",
+            server.handle("stop web").await
+        );
+        let nothing = tokio::time::timeout(std::time::Duration::from_millis(5), rx.recv());
+        assert!(nothing.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn status_lists_every_program() {
+        let (mut server, _rx) = server();
+
+        assert_eq!(
+            "phase  init\ndb     pending\napi    pending\n",
+            server.handle("status").await
+        );
+    }
+
+    // the client blocks, so it gets a thread of its own
+    async fn request_from_thread(outdir: &Path, line: &'static str) -> Result<String> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let outdir = outdir.to_path_buf();
+        std::thread::spawn(move || {
+            let result = request(&outdir, line).map_err(|e| e.to_string());
+            let _ = tx.send(result);
+        });
+        rx.await.unwrap().map_err(|e| e.into())
+    }
+
+    #[tokio::test]
+    async fn over_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, mut rx) = server();
+        let listener = bind(&path(dir.path())).unwrap();
+        tokio::spawn(serve(listener, server));
+
+        let response = request_from_thread(dir.path(), "stop db").await.unwrap();
+        assert_eq!("stop db requested\n", response);
+        assert!(matches!(
+            rx.recv().await,
+            Some(Event::Control(Control::Stop, _))
+        ));
+
+        let e = request_from_thread(dir.path(), "stop web")
+            .await
+            .unwrap_err();
+        assert_eq!("No such program: web", e.to_string());
+    }
+}
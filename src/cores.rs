@@ -0,0 +1,175 @@
+extern crate log;
+extern crate nix;
+
+use super::config::Program;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+// allows the child to dump core of any size, whatever decompose's own limit is
+pub fn raise_limit(cmd: &mut tokio::process::Command) {
+    use nix::libc;
+
+    let unlimited = || {
+        let limit = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        // fall back to the hard limit when not allowed to raise it
+        if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+            let mut current = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            unsafe {
+                libc::getrlimit(libc::RLIMIT_CORE, &mut current);
+                current.rlim_cur = current.rlim_max;
+                libc::setrlimit(libc::RLIMIT_CORE, &current);
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(unlimited);
+    }
+}
+
+pub fn is_crash(status: &ExitStatus) -> bool {
+    use nix::sys::signal::Signal;
+
+    const CRASHES: [Signal; 5] = [
+        Signal::SIGSEGV,
+        Signal::SIGABRT,
+        Signal::SIGBUS,
+        Signal::SIGFPE,
+        Signal::SIGILL,
+    ];
+
+    status.core_dumped()
+        || status
+            .signal()
+            .map(|s| CRASHES.iter().any(|c| *c as i32 == s))
+            .unwrap_or(false)
+}
+
+// copies the core of a crashed program into dir, next to a file naming the binary
+pub fn collect(dir: &Path, prog: &Program, pid: u32, status: &ExitStatus) {
+    if !is_crash(status) {
+        return;
+    }
+
+    let pattern = std::fs::read_to_string("/proc/sys/kernel/core_pattern").unwrap_or_default();
+    let pattern = pattern.trim();
+    if let Some(handler) = pattern.strip_prefix('|') {
+        log::warn!(
+            "{}:{} crashed, {}; its core went to {}",
+            prog.name,
+            pid,
+            status,
+            handler
+        );
+        return;
+    }
+
+    let uses_pid = std::fs::read_to_string("/proc/sys/kernel/core_uses_pid")
+        .map(|s| s.trim() == "1")
+        .unwrap_or(false);
+    let core = Path::new(&prog.cwd).join(core_file(pattern, pid, &prog.exec, uses_pid));
+
+    match save(dir, prog, pid, &core) {
+        Ok(target) => log::warn!(
+            "{}:{} crashed, {}; core saved to {:?}",
+            prog.name,
+            pid,
+            status,
+            target
+        ),
+        Err(e) => log::warn!(
+            "{}:{} crashed, {}; no core collected from {:?}: {}",
+            prog.name,
+            pid,
+            status,
+            core,
+            e
+        ),
+    }
+}
+
+fn save(dir: &Path, prog: &Program, pid: u32, core: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+
+    let target = dir.join(format!("{}.{}.core", prog.name, pid));
+    std::fs::copy(core, &target)?;
+
+    let binary = std::fs::canonicalize(&prog.exec).unwrap_or_else(|_| PathBuf::from(&prog.exec));
+    std::fs::write(
+        dir.join(format!("{}.{}.binary", prog.name, pid)),
+        format!("{}\n", binary.display()),
+    )?;
+    Ok(target)
+}
+
+// expands the kernel's core pattern for the bits we know, the pid and executable
+fn core_file(pattern: &str, pid: u32, exec: &str, uses_pid: bool) -> String {
+    // the kernel truncates the executable name like it does for comm
+    let exe: String = Path::new(exec)
+        .file_name()
+        .map(|f| f.to_string_lossy().chars().take(15).collect())
+        .unwrap_or_default();
+
+    let mut result = String::new();
+    let mut has_pid = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('p') | Some('P') => {
+                has_pid = true;
+                result += &pid.to_string();
+            }
+            Some('e') => result += &exe,
+            Some('%') => result.push('%'),
+            _ => (),
+        }
+    }
+
+    if uses_pid && !has_pid {
+        result += &format!(".{}", pid);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_core_pattern() {
+        assert_eq!("core", core_file("core", 12, "./bin/server", false));
+        assert_eq!("core.12", core_file("core", 12, "./bin/server", true));
+        assert_eq!(
+            "/tmp/cores/server.12",
+            core_file("/tmp/cores/%e.%p", 12, "./bin/server", true)
+        );
+        assert_eq!(
+            "core-averyveryverylo-%",
+            core_file("core-%e-%%", 12, "averyveryverylongname", false)
+        );
+    }
+
+    #[test]
+    fn crashes_are_recognised() {
+        use nix::sys::signal::Signal;
+
+        // raw wait statuses: killed by the signal in the low bits, exit code above
+        let killed = |sig: Signal| ExitStatus::from_raw(sig as i32);
+
+        assert!(is_crash(&killed(Signal::SIGSEGV)));
+        assert!(is_crash(&killed(Signal::SIGABRT)));
+        assert!(!is_crash(&killed(Signal::SIGTERM)));
+        assert!(!is_crash(&ExitStatus::from_raw(1 << 8)));
+    }
+}
@@ -0,0 +1,471 @@
+extern crate colored;
+extern crate regex;
+extern crate serde_json;
+
+use super::config;
+use super::graph;
+use super::preflight;
+use colored::Colorize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    Ok,
+    Warning,
+    Problem,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Problem => "problem",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub level: Level,
+    pub message: String,
+    // what it is about, when known: the program, and the key in the config like
+    // program[1].ready.port, or within the program like cwd
+    pub program: Option<String>,
+    pub path: Option<String>,
+}
+
+impl Finding {
+    fn new<S: Into<String>>(level: Level, message: S) -> Finding {
+        Finding {
+            level,
+            message: message.into(),
+            program: None,
+            path: None,
+        }
+    }
+
+    fn ok<S: Into<String>>(message: S) -> Finding {
+        Finding::new(Level::Ok, message)
+    }
+
+    fn warning<S: Into<String>>(message: S) -> Finding {
+        Finding::new(Level::Warning, message)
+    }
+
+    fn problem<S: Into<String>>(message: S) -> Finding {
+        Finding::new(Level::Problem, message)
+    }
+
+    fn about(mut self, program: &str) -> Finding {
+        self.program = Some(program.to_string());
+        self
+    }
+
+    // for findings about a program, the key within it
+    fn at(mut self, path: &str) -> Finding {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "severity": self.level.name(),
+            "program": self.program,
+            "path": self.path,
+            "message": self.message,
+        })
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let label = match self.level {
+            Level::Ok => self.level.name().green(),
+            Level::Warning => self.level.name().yellow(),
+            Level::Problem => self.level.name().red(),
+        };
+        write!(f, "[{}] {}", label, self.message)
+    }
+}
+
+// prints what it finds, as text or a json array, an error means at least one problem
+pub fn run(
+    config: Option<&str>,
+    outdir: &str,
+    format: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let findings = diagnose(config, outdir);
+    match format {
+        "json" => {
+            let findings: Vec<serde_json::Value> = findings.iter().map(Finding::json).collect();
+            println!("{}", serde_json::to_string_pretty(&findings)?);
+        }
+        _ => {
+            for f in findings.iter() {
+                println!("{}", f);
+            }
+        }
+    }
+
+    match findings
+        .iter()
+        .filter(|f| f.level == Level::Problem)
+        .count()
+    {
+        0 => Ok(()),
+        n => Err(format!("doctor found {} problem(s)", n).into()),
+    }
+}
+
+pub fn diagnose(config: Option<&str>, outdir: &str) -> Vec<Finding> {
+    let mut findings = vec![check_outdir(Path::new(outdir)), check_open_files()];
+    findings.extend(check_clock());
+
+    if let Some(config) = config {
+        findings.extend(check_config(config));
+    }
+    findings
+}
+
+fn check_config(filename: &str) -> Vec<Finding> {
+    let raw = match std::fs::read_to_string(filename) {
+        Ok(raw) => raw,
+        Err(e) => return vec![Finding::problem(format!("can't read {}: {}", filename, e))],
+    };
+
+    let missing = missing_env_vars(raw.as_str(), |v| std::env::var_os(v).is_some());
+    if !missing.is_empty() {
+        return missing
+            .iter()
+            .map(|v| {
+                Finding::problem(format!(
+                    "{} references ${} which is not set, export it or use ${{{}:-default}}",
+                    filename, v, v
+                ))
+            })
+            .collect();
+    }
+
+    let sys = match config::System::from_file(filename, None) {
+        Ok(sys) => sys,
+        Err(e) => return vec![invalid(filename, e.to_string().as_str())],
+    };
+    if let Err(e) = graph::Graph::from_config(&sys) {
+        return vec![invalid(filename, e.to_string().as_str())];
+    }
+
+    let mut findings = vec![Finding::ok(format!("{} is valid", filename))];
+    for prog in sys.program.iter().filter(|p| !p.disabled) {
+        findings.extend(check_program(prog));
+    }
+
+    findings.extend(
+        preflight::disabled_dependencies(&sys)
+            .into_iter()
+            .map(Finding::warning),
+    );
+
+    let busy = preflight::busy_ports(&sys);
+    if busy.is_empty() {
+        findings.push(Finding::ok("ports are free"));
+    }
+    findings.extend(busy.iter().map(|b| {
+        Finding::problem(format!("{}, stop it or pick another port", b)).about(&b.program)
+    }));
+
+    findings
+}
+
+// an invalid config, with the key and program it is about if the error names them
+fn invalid(filename: &str, error: &str) -> Finding {
+    let path_re = regex::Regex::new(
+        r"^([A-Za-z_][A-Za-z0-9_]*(\[[0-9]+\])?(\.[A-Za-z0-9_]+(\[[0-9]+\])?)*): ",
+    )
+    .expect("path regex");
+    let program_re = regex::Regex::new(r#"program "([^"]+)""#).expect("program regex");
+
+    let error = error
+        .strip_prefix(filename)
+        .and_then(|e| e.strip_prefix(": "))
+        .unwrap_or(error);
+    let mut finding = Finding::problem(format!("invalid config {}: {}", filename, error));
+    finding.path = path_re.captures(error).map(|c| c[1].to_string());
+    finding.program = program_re.captures(error).map(|c| c[1].to_string());
+    finding
+}
+
+fn check_program(prog: &config::Program) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if !prog.create_cwd && !Path::new(&prog.cwd).is_dir() {
+        findings.push(
+            Finding::problem(format!(
+                "{}: working directory {} does not exist",
+                prog.name, prog.cwd
+            ))
+            .at("cwd"),
+        );
+    }
+
+    let execs =
+        std::iter::once(("exec", &prog.exec)).chain(prog.build.first().map(|b| ("build", b)));
+    for (key, exec) in execs {
+        match find_executable(exec) {
+            Some(path) if is_executable(&path) => (),
+            Some(path) => findings.push(
+                Finding::problem(format!(
+                    "{}: {} is not executable, try chmod +x {}",
+                    prog.name,
+                    exec,
+                    path.display()
+                ))
+                .at(key),
+            ),
+            None => findings.push(
+                Finding::problem(format!(
+                    "{}: {} not found, check the path or install it",
+                    prog.name, exec
+                ))
+                .at(key),
+            ),
+        }
+    }
+
+    if let config::Stdin::File(path) = &prog.stdin {
+        if !Path::new(path).is_file() {
+            findings.push(
+                Finding::problem(format!("{}: stdin file {} does not exist", prog.name, path))
+                    .at("stdin"),
+            );
+        }
+    }
+
+    if findings.is_empty() {
+        findings.push(Finding::ok(format!("{} can be started", prog.name)));
+    }
+    findings.into_iter().map(|f| f.about(&prog.name)).collect()
+}
+
+// like the shell, only names without a slash are looked up in PATH
+pub fn find_executable(exec: &str) -> Option<PathBuf> {
+    if exec.contains('/') {
+        let path = PathBuf::from(exec);
+        return if path.exists() { Some(path) } else { None };
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(exec))
+            .find(|path| path.is_file())
+    })
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+// variables used without a ${VAR:-default} fallback, in order of appearance
+fn missing_env_vars<F: Fn(&str) -> bool>(raw: &str, is_set: F) -> Vec<String> {
+    let re = regex::Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)(:-)?|([A-Za-z_][A-Za-z0-9_]*))")
+        .expect("env var regex");
+
+    let mut missing: Vec<String> = Vec::new();
+    for caps in re.captures_iter(raw) {
+        if caps.get(2).is_some() {
+            continue;
+        }
+        let name = caps.get(1).or_else(|| caps.get(3)).unwrap().as_str();
+        if !is_set(name) && !missing.iter().any(|m| m == name) {
+            missing.push(name.to_string());
+        }
+    }
+    missing
+}
+
+// without creating it, a run does that. whatever of it exists has to take new
+// entries, which is the directory itself or the nearest ancestor that is there
+fn check_outdir(outdir: &Path) -> Finding {
+    let existing = outdir
+        .ancestors()
+        .map(|p| {
+            if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            }
+        })
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("/"));
+    let probe = existing.join(format!(".doctor.{}", std::process::id()));
+    let result = std::fs::write(&probe, "probe").and_then(|_| std::fs::remove_file(&probe));
+
+    match result {
+        Ok(()) => Finding::ok(format!("output directory {} is writable", outdir.display())),
+        Err(e) => Finding::problem(format!(
+            "output directory {} is not writable ({}), fix its permissions or pass --outdir",
+            outdir.display(),
+            e
+        )),
+    }
+}
+
+// every program holds a few pipes and sockets, plus whatever it opens itself
+const MIN_OPEN_FILES: u64 = 4096;
+
+fn check_open_files() -> Finding {
+    use nix::libc;
+
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Finding::warning("could not read the open files limit");
+    }
+
+    let soft = limit.rlim_cur;
+    if soft < MIN_OPEN_FILES {
+        Finding::warning(format!(
+            "open files limit is {}, raise it with ulimit -n {}",
+            soft, MIN_OPEN_FILES
+        ))
+    } else {
+        Finding::ok(format!("open files limit is {}", soft))
+    }
+}
+
+fn check_clock() -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    // anything before this is a clock that was never set
+    const SANE: i64 = 1_577_836_800; // 2020-01-01
+    let now = chrono::Utc::now().timestamp();
+    if now < SANE {
+        findings.push(Finding::problem(
+            "system clock is in the past, enable time synchronisation (e.g. timedatectl set-ntp true)",
+        ));
+    }
+
+    let tz = std::env::var("TZ").ok();
+    match tz {
+        Some(tz) if !tz.is_empty() && !tz.starts_with(':') && !zone_exists(tz.as_str()) => findings
+            .push(Finding::warning(format!(
+                "TZ={} is not a known time zone, timestamps will be in UTC",
+                tz
+            ))),
+        None if !Path::new("/etc/localtime").exists() => findings.push(Finding::warning(
+            "no local time zone configured, timestamps will be in UTC; set TZ or /etc/localtime",
+        )),
+        _ => (),
+    }
+
+    if findings.is_empty() {
+        findings.push(Finding::ok("clock and time zone look sane"));
+    }
+    findings
+}
+
+fn zone_exists(tz: &str) -> bool {
+    // offsets like UTC or EST5EDT are fine without a zone file
+    tz.chars().any(|c| c.is_ascii_digit())
+        || tz == "UTC"
+        || Path::new("/usr/share/zoneinfo").join(tz).is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_missing_env_vars() {
+        let raw = r#"
+            name = "$NAME"
+            exec = "${EXEC}"
+            args = ["${ARG:-default}", "$NAME", "$SET"]
+        "#;
+
+        let missing = missing_env_vars(raw, |v| v == "SET");
+        assert_eq!(vec!["NAME", "EXEC"], missing);
+    }
+
+    #[test]
+    fn finds_executables() {
+        assert!(find_executable("sh").is_some());
+        assert!(find_executable("/bin/sh").is_some());
+        assert!(find_executable("surely-not-installed-anywhere").is_none());
+        assert!(is_executable(Path::new("/bin/sh")));
+
+        let plain = tempfile::NamedTempFile::new().unwrap();
+        assert!(!is_executable(plain.path()));
+    }
+
+    #[test]
+    fn reports_program_problems() {
+        let toml = r#"
+            [[program]]
+            name = "good"
+            exec = "/bin/sh"
+
+            [[program]]
+            name = "bad"
+            exec = "surely-not-installed-anywhere"
+            cwd = "/no/such/dir"
+        "#;
+        let sys = config::System::from_toml(toml).unwrap();
+
+        let good = check_program(&sys.program[0]);
+        assert_eq!(
+            vec![Level::Ok],
+            good.iter().map(|f| f.level).collect::<Vec<_>>()
+        );
+
+        let bad = check_program(&sys.program[1]);
+        assert_eq!(2, bad.len());
+        assert!(bad.iter().all(|f| f.level == Level::Problem));
+        assert!(bad.iter().all(|f| f.program.as_deref() == Some("bad")));
+        assert_eq!(
+            vec![Some("cwd"), Some("exec")],
+            bad.iter().map(|f| f.path.as_deref()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn locates_config_errors() {
+        let finding = invalid(
+            "decompose.toml",
+            "decompose.toml: program[1].ready.port: invalid type: string \"http\"",
+        );
+        assert_eq!(Some("program[1].ready.port".to_string()), finding.path);
+        assert_eq!(None, finding.program);
+        assert_eq!(
+            "invalid config decompose.toml: program[1].ready.port: invalid type: string \"http\"",
+            finding.message
+        );
+
+        let finding = invalid("decompose.toml", "program \"api\" has no exec");
+        assert_eq!(None, finding.path);
+        assert_eq!(Some("api".to_string()), finding.program);
+
+        let json = finding.json();
+        assert_eq!("problem", json["severity"]);
+        assert_eq!("api", json["program"]);
+        assert!(json["path"].is_null());
+    }
+
+    #[test]
+    fn unwritable_outdir() {
+        assert_eq!(Level::Problem, check_outdir(Path::new("/proc/nope")).level);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Level::Ok, check_outdir(dir.path()).level);
+
+        let missing = dir.path().join("runs/today");
+        assert_eq!(Level::Ok, check_outdir(&missing).level);
+        assert!(!dir.path().join("runs").exists());
+    }
+}
@@ -0,0 +1,115 @@
+extern crate serde_json;
+
+use super::config;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// what a program inherits of the given environment after filtering, then its own
+// variables, which loading the config already merged with its env groups and expanded.
+// what is only known once it runs, like exported captures, its scratch dir and
+// LISTEN_PID, is not in here
+pub fn resolve(
+    prog: &config::Program,
+    own: impl Iterator<Item = (String, String)>,
+) -> BTreeMap<String, String> {
+    let mut env: BTreeMap<String, String> = own
+        .filter(|(k, _)| !prog.filters_env() || prog.inherits(k))
+        .collect();
+    env.extend(prog.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    env
+}
+
+// the environment of the named program, as shell exports to eval or as a json object
+pub fn environment(sys: &config::System, name: &str, format: &str) -> Result<String> {
+    let prog = sys
+        .program
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| format!("No such program: {}", name))?;
+    let env = resolve(prog, std::env::vars());
+
+    match format {
+        "json" => Ok(serde_json::to_string_pretty(&env)? + "\n"),
+        _ => {
+            let mut out = String::new();
+            for (k, v) in env.iter() {
+                writeln!(out, "export {}={}", k, shell_quote(v))?;
+            }
+            Ok(out)
+        }
+    }
+}
+
+// single quotes keep everything literal, except single quotes themselves
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sys() -> config::System {
+        let toml = r#"
+            env_allow = ["PATH", "HOME"]
+
+            [envgroup.aws]
+            AWS_REGION = "eu-west-1"
+
+            [[program]]
+            name = "api"
+            exec = "api"
+            env_groups = ["aws"]
+            env = {GREETING = "it's me", HOME = "/home/api"}
+            "#;
+        config::System::from_toml(toml).unwrap()
+    }
+
+    #[test]
+    fn resolves_filtered_and_own_variables() {
+        let sys = sys();
+        let own = vec![
+            ("PATH".to_string(), "/bin".to_string()),
+            ("HOME".to_string(), "/home/dev".to_string()),
+            ("SECRET".to_string(), "hunter2".to_string()),
+        ];
+
+        let env = resolve(&sys.program[0], own.into_iter());
+        let env: Vec<(&str, &str)> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            vec![
+                ("AWS_REGION", "eu-west-1"),
+                ("GREETING", "it's me"),
+                ("HOME", "/home/api"),
+                ("PATH", "/bin"),
+            ],
+            env
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let sys = sys();
+
+        let shell = environment(&sys, "api", "shell").unwrap();
+        assert!(
+            shell.contains("export GREETING='it'\\''s me'\n"),
+            "{}",
+            shell
+        );
+        assert!(
+            shell.contains("export AWS_REGION='eu-west-1'\n"),
+            "{}",
+            shell
+        );
+
+        let json = environment(&sys, "api", "json").unwrap();
+        let json: serde_json::Value = serde_json::from_str(json.as_str()).unwrap();
+        assert_eq!("eu-west-1", json["AWS_REGION"]);
+
+        assert!(environment(&sys, "nope", "shell").is_err());
+    }
+}
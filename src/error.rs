@@ -0,0 +1,96 @@
+extern crate thiserror;
+
+use std::process::ExitStatus;
+use std::time::Duration;
+
+// the ways a run can fail, for the exit code and anyone embedding the executor
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    Graph(String),
+    #[error("{program} can't be started: {source}")]
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    #[error("{program} not ready after {after:?}")]
+    ReadyTimeout { program: String, after: Duration },
+    #[error("{name} failed: {status}")]
+    Exit { name: String, status: ExitStatus },
+    // decompose exits with the code of the primary program
+    #[error("{name} exited: {status}")]
+    PrimaryExit { name: String, status: ExitStatus },
+    #[error(transparent)]
+    Runtime(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn config<E: std::fmt::Display>(e: E) -> Error {
+        Error::Config(e.to_string())
+    }
+
+    // errors from the process manager come as io errors, typed ones are unwrapped
+    pub fn from_event(e: std::io::Error) -> Error {
+        if e.get_ref().map(|inner| inner.is::<Error>()) != Some(true) {
+            return Error::Runtime(e);
+        }
+        let inner = e.into_inner().expect("checked above");
+        *inner.downcast::<Error>().expect("checked above")
+    }
+
+    // the exit code of decompose, 128 plus the signal for a killed primary program
+    // as a shell would have it
+    pub fn code(&self) -> i32 {
+        use std::os::unix::process::ExitStatusExt;
+
+        match self {
+            Error::Config(_) | Error::Graph(_) => 2,
+            Error::Spawn { .. } => 3,
+            Error::ReadyTimeout { .. } => 4,
+            Error::PrimaryExit { status, .. } => match (status.code(), status.signal()) {
+                (Some(code), _) => code,
+                (None, Some(signal)) => 128 + signal,
+                (None, None) => 1,
+            },
+            Error::Exit { .. } | Error::Runtime(_) => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn survives_the_event_channel() {
+        let timeout = Error::ReadyTimeout {
+            program: "db".to_string(),
+            after: Duration::from_secs(5),
+        };
+        let e = Error::from_event(std::io::Error::other(timeout));
+        assert!(matches!(&e, Error::ReadyTimeout { program, .. } if program == "db"));
+        assert_eq!("db not ready after 5s", e.to_string());
+        assert_eq!(4, e.code());
+
+        let e = Error::from_event(std::io::Error::other("boom"));
+        assert!(matches!(e, Error::Runtime(_)));
+        assert_eq!(1, e.code());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let primary = |raw| Error::PrimaryExit {
+            name: "tests".to_string(),
+            status: ExitStatus::from_raw(raw),
+        };
+        assert_eq!(3, primary(3 << 8).code());
+        assert_eq!(128 + 9, primary(9).code());
+        assert_eq!(2, Error::config("bad").code());
+    }
+}
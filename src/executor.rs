@@ -2,31 +2,133 @@ extern crate nix;
 extern crate tokio;
 
 use super::config;
+use super::error::{Error, Result};
 
 use super::graph::{Graph, NodeHandle};
 use super::process;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use process::mpsc;
 use process::Command;
 use process::Event;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+// where the system as a whole is. init until everything is started, running
+// until shutdown starts, done once the executor returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Phase {
+    #[default]
+    Init,
+    Running,
+    ShuttingDown,
+    Done,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Init => "init",
+            Phase::Running => "running",
+            Phase::ShuttingDown => "shutting_down",
+            Phase::Done => "done",
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// where a single program is, as far as the executor knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Pending,
+    Running,
+    // running, but failing its liveness probe
+    Degraded,
+    Stopping,
+    // its restart is held back by the restart limit
+    Held,
+    Quarantined,
+    Stopped,
+}
+
+impl NodeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeState::Pending => "pending",
+            NodeState::Running => "running",
+            NodeState::Degraded => "degraded",
+            NodeState::Stopping => "stopping",
+            NodeState::Held => "held",
+            NodeState::Quarantined => "quarantined",
+            NodeState::Stopped => "stopped",
+        }
+    }
+}
+
+// the phase and program states as last published by the executor, for others
+// to look at while it runs
+#[derive(Clone, Default)]
+pub struct Tracker {
+    state: Arc<Mutex<Tracked>>,
+}
+
+#[derive(Default)]
+struct Tracked {
+    phase: Phase,
+    nodes: HashMap<String, NodeState>,
+}
+
+impl Tracker {
+    pub fn phase(&self) -> Phase {
+        self.state.lock().expect("tracker lock").phase
+    }
+
+    // pending until the executor says otherwise
+    pub fn node(&self, name: &str) -> NodeState {
+        let state = self.state.lock().expect("tracker lock");
+        state.nodes.get(name).copied().unwrap_or(NodeState::Pending)
+    }
+}
 
 pub struct Executor {
     dependency_graph: Graph,
     tx: process::mpsc::Sender<Command>,
     rx: process::mpsc::Receiver<Event>,
 
-    // todo: this tracks a lot of state, in a fiddly way. Partially
-    // because of different required behavior when starting up, shutting down..
-    // refactoring ideas:
-    //   1. turn this into a state machine (init->run->shutdown)
-    //   2. similar, but each state in just one (private) method. Keep state variables on the function scope
+    phase: Phase,
+    tracker: Tracker,
     running: HashSet<NodeHandle>,
     pending: HashSet<NodeHandle>,
-    shutting_down: bool,
+    restart: HashSet<NodeHandle>,
+    restarts: HashMap<NodeHandle, VecDeque<Instant>>,
+    crash_loop: config::CrashLoop,
+    quarantined: HashSet<NodeHandle>,
+    restart_limit: Option<config::RestartLimit>,
+    restart_tokens: f64,
+    refilled: Instant,
+    // restarts held back since the restart limit tripped, until resumed
+    held: Vec<NodeHandle>,
+    stopping: HashSet<NodeHandle>,
+    stop_queue: VecDeque<NodeHandle>,
+    max_concurrent_stops: Option<usize>,
+    // stopped over the control socket, which doesn't count as them ending, and
+    // those of them to start again once stopped
+    stop_requested: HashSet<NodeHandle>,
+    restart_requested: HashSet<NodeHandle>,
+    // reloaded together, stopping from the dependents inward and starting again
+    // once all of them stopped
+    reloading: HashSet<NodeHandle>,
+    // running, but not alive as far as their liveness probe is concerned
+    degraded: HashSet<NodeHandle>,
+    overflow: config::Overflow,
+    // the first command that couldn't be sent, ending the run
+    unsent: Option<Error>,
     status: Option<ExitStatus>,
+    primary: Option<ExitStatus>,
 }
 
 impl Executor {
@@ -35,19 +137,55 @@ impl Executor {
         tx: process::mpsc::Sender<Command>,
         rx: process::mpsc::Receiver<Event>,
     ) -> Result<Executor> {
-        let graph = Graph::from_config(&cfg)?;
+        let graph = Graph::from_config(cfg)?;
 
         Ok(Executor {
             dependency_graph: graph,
             tx,
             rx,
+            phase: Phase::Init,
+            tracker: Tracker::default(),
             running: HashSet::new(),
             pending: HashSet::new(),
-            shutting_down: false,
+            restart: HashSet::new(),
+            restarts: HashMap::new(),
+            crash_loop: cfg.crash_loop.clone(),
+            quarantined: HashSet::new(),
+            restart_limit: cfg.restart_limit.clone(),
+            restart_tokens: cfg
+                .restart_limit
+                .as_ref()
+                .map_or(0.0, |l| l.restarts as f64),
+            refilled: Instant::now(),
+            held: Vec::new(),
+            stopping: HashSet::new(),
+            stop_queue: VecDeque::new(),
+            max_concurrent_stops: cfg.max_concurrent_stops,
+            stop_requested: HashSet::new(),
+            restart_requested: HashSet::new(),
+            reloading: HashSet::new(),
+            degraded: HashSet::new(),
+            overflow: cfg.channels.overflow,
+            unsent: None,
             status: None,
+            primary: None,
         })
     }
 
+    pub fn tracker(&self) -> Tracker {
+        self.tracker.clone()
+    }
+
+    // restart the program whenever it exits, until the system shuts down
+    pub fn restart_on_exit(&mut self, name: &str) -> Result<()> {
+        let h = self
+            .dependency_graph
+            .find(name)
+            .ok_or_else(|| Error::Graph(format!("No such program: {}", name)))?;
+        self.restart.insert(h);
+        Ok(())
+    }
+
     pub async fn run(mut self) -> Result<()> {
         log::info!("starting execution");
 
@@ -61,17 +199,98 @@ impl Executor {
         log::debug!("broken from event loop");
 
         self.shutdown().await?;
+        self.enter(Phase::Done).await;
 
         log::info!("stopping execution");
-        match self.status {
-            None => Ok(()),
-            Some(status) => status.into_result(),
+        self.exit()
+    }
+
+    // moves on to the next phase, doing what entering it takes
+    async fn enter(&mut self, phase: Phase) {
+        log::info!("entering phase {}", phase);
+        self.phase = phase;
+        // nobody needs to be listening anymore once done
+        let _ = process::send(&mut self.tx, Command::Phase(phase), self.overflow).await;
+
+        match phase {
+            Phase::Init => {
+                let roots: Vec<NodeHandle> = self.dependency_graph.roots().collect();
+                for h in roots {
+                    self.send_start(h).await;
+                }
+            }
+            Phase::Running => self.send(Command::SystemReady).await,
+            Phase::ShuttingDown => {
+                // held programs won't be started anymore
+                for h in self.held.drain(..) {
+                    self.pending.remove(&h);
+                }
+                self.stop_leaves().await;
+            }
+            Phase::Done => (),
+        }
+        self.publish();
+    }
+
+    fn shutting_down(&self) -> bool {
+        self.phase >= Phase::ShuttingDown
+    }
+
+    fn node_state(&self, handle: NodeHandle) -> NodeState {
+        if self.stopping.contains(&handle) || self.stop_queue.contains(&handle) {
+            NodeState::Stopping
+        } else if self.degraded.contains(&handle) {
+            NodeState::Degraded
+        } else if self.running.contains(&handle) {
+            NodeState::Running
+        } else if self.held.contains(&handle) {
+            NodeState::Held
+        } else if self.pending.contains(&handle) {
+            NodeState::Pending
+        } else if self.quarantined.contains(&handle) {
+            NodeState::Quarantined
+        } else {
+            NodeState::Stopped
+        }
+    }
+
+    fn publish(&self) {
+        let nodes = self
+            .dependency_graph
+            .all()
+            .map(|h| {
+                (
+                    self.dependency_graph.node(h).name.clone(),
+                    self.node_state(h),
+                )
+            })
+            .collect();
+        let mut tracked = self.tracker.state.lock().expect("tracker lock");
+        tracked.phase = self.phase;
+        tracked.nodes = nodes;
+    }
+
+    // the primary program's status wins, whatever else went wrong
+    fn exit(&mut self) -> Result<()> {
+        match (self.primary.take(), self.status.take()) {
+            (Some(primary), _) => primary.into_primary_result(),
+            (None, Some(status)) => status.into_result(),
+            (None, None) => Ok(()),
         }
     }
 
     async fn process(&mut self, event: Event) -> Result<bool> {
         log::debug!("processing event");
 
+        let result = self.dispatch(event).await;
+        self.publish();
+        match self.unsent.take() {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+
+    async fn dispatch(&mut self, event: Event) -> Result<bool> {
         match event {
             Event::Started(h) => {
                 self.on_started(h).await;
@@ -81,13 +300,29 @@ impl Executor {
                 self.on_stopped(h, s).await;
                 Ok(true)
             }
+            Event::Failed(h, s) => {
+                self.on_failed(h, s).await?;
+                Ok(true)
+            }
             Event::Shutdown => {
                 self.shutdown().await?;
                 Ok(true)
             }
+            Event::Resume => {
+                self.resume().await;
+                Ok(true)
+            }
+            Event::Control(action, h) => {
+                self.control(action, h).await;
+                Ok(true)
+            }
+            Event::Liveness(h, alive) => {
+                self.liveness(h, alive).await;
+                Ok(true)
+            }
             Event::Err(e) => {
                 log::error!("{}", e);
-                Err(e.into())
+                Err(Error::from_event(e))
             }
         }
     }
@@ -101,47 +336,399 @@ impl Executor {
         self.pending = self.dependency_graph.all().collect();
         self.status = None;
 
-        for h in self.dependency_graph.roots() {
-            self.send_start(h).await;
-        }
-        Ok(())
+        self.enter(Phase::Init).await;
+        self.unsent.take().map_or(Ok(()), Err)
     }
 
     async fn shutdown(&mut self) -> Result<()> {
         log::debug!("initiating shutdown");
 
-        self.shutting_down = true;
+        match self.shutting_down() {
+            true => self.stop_leaves().await,
+            false => self.enter(Phase::ShuttingDown).await,
+        }
+        Ok(())
+    }
 
+    async fn stop_leaves(&mut self) {
         if self.is_alive() {
-            for h in self.dependency_graph.leaves() {
+            let leaves: Vec<NodeHandle> = self.dependency_graph.leaves().collect();
+            for h in leaves {
                 self.send_stop(h).await;
             }
         }
-        Ok(())
     }
 
     async fn on_started(&mut self, handle: NodeHandle) {
         self.pending.remove(&handle);
         self.running.insert(handle);
 
-        for h in self.dependency_graph.expand(handle, |n| {
-            self.running.contains(&n) || !self.pending.contains(&n)
-        }) {
+        self.start_unlocked(handle).await;
+        self.check_system_ready().await;
+    }
+
+    // startup is over the first time nothing is left to start
+    async fn check_system_ready(&mut self) {
+        if self.pending.is_empty() && self.phase == Phase::Init {
+            self.enter(Phase::Running).await;
+        }
+    }
+
+    async fn start_unlocked(&mut self, handle: NodeHandle) {
+        let unlocked: Vec<NodeHandle> = self
+            .dependency_graph
+            .expand(handle, |n| {
+                self.running.contains(&n) || !self.pending.contains(&n)
+            })
+            .filter(|n| self.pending.contains(n))
+            .collect();
+
+        for h in unlocked {
+            self.send_start(h).await;
+        }
+    }
+
+    async fn on_failed(&mut self, handle: NodeHandle, status: process::ExitStatus) -> Result<()> {
+        use config::DepFailurePolicy;
+
+        self.pending.remove(&handle);
+        self.running.remove(&handle);
+        self.degraded.remove(&handle);
+
+        let p = self.dependency_graph.node(handle);
+        let critical = p.critical && !p.disabled;
+        let name = p.name.clone();
+        let failed = Error::Exit {
+            name: name.clone(),
+            status,
+        };
+
+        if p.primary && !p.disabled {
+            log::warn!("{}", failed);
+            self.primary = Some(ExitStatus { name, status });
+            return self.shutdown().await;
+        }
+
+        let dependees: Vec<NodeHandle> = self.dependency_graph.dependees(handle).collect();
+        let abort = dependees.is_empty()
+            || dependees
+                .iter()
+                .any(|h| self.dependency_graph.node(*h).on_dep_failure == DepFailurePolicy::Abort);
+        if abort {
+            log::error!("{}", failed);
+            return Err(failed);
+        }
+        log::warn!("{}", failed);
+
+        for h in dependees {
+            if self.dependency_graph.node(h).on_dep_failure == DepFailurePolicy::Skip {
+                self.skip(h);
+            }
+        }
+
+        if critical {
+            log::info!("critical task {} failed", name);
+            if self.status.is_none() {
+                self.status = Some(ExitStatus { name, status });
+            }
+            return self.shutdown().await;
+        }
+
+        self.start_unlocked(handle).await;
+        self.check_system_ready().await;
+        Ok(())
+    }
+
+    // records a restart, unless there have been too many recently
+    fn crash_looping(&mut self, handle: NodeHandle) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs_f64(self.crash_loop.within);
+
+        let restarts = self.restarts.entry(handle).or_default();
+        while restarts
+            .front()
+            .map(|t| now.duration_since(*t) > window)
+            .unwrap_or(false)
+        {
+            restarts.pop_front();
+        }
+
+        if restarts.len() >= self.crash_loop.restarts {
+            return true;
+        }
+        restarts.push_back(now);
+        false
+    }
+
+    // takes a restart from the system wide bucket. once empty it trips, and every
+    // restart is held until resumed
+    fn restart_allowed(&mut self) -> bool {
+        let limit = match &self.restart_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        if !self.held.is_empty() {
+            return false;
+        }
+
+        let now = Instant::now();
+        let rate = limit.restarts as f64 / limit.per;
+        let refill = now.duration_since(self.refilled).as_secs_f64() * rate;
+        self.restart_tokens = (self.restart_tokens + refill).min(limit.restarts as f64);
+        self.refilled = now;
+
+        if self.restart_tokens >= 1.0 {
+            self.restart_tokens -= 1.0;
+            return true;
+        }
+
+        let msg = format!(
+            "restart limit of {} per {}s reached, holding restarts until resumed with SIGUSR1",
+            limit.restarts, limit.per
+        );
+        log::error!("{}", msg);
+        eprintln!("==> {}", msg);
+        false
+    }
+
+    // restarts what was held, with a full bucket
+    async fn resume(&mut self) {
+        if self.held.is_empty() {
+            log::info!("resume requested, but no restarts are held");
+            return;
+        }
+        log::info!("resuming {} held restarts", self.held.len());
+        if let Some(limit) = &self.restart_limit {
+            self.restart_tokens = limit.restarts as f64;
+            self.refilled = Instant::now();
+        }
+        for h in std::mem::take(&mut self.held) {
             self.send_start(h).await;
         }
     }
 
+    // a single program started, stopped or restarted on request, leaving the rest
+    // of the system as it is
+    async fn control(&mut self, action: process::Control, handle: NodeHandle) {
+        use process::Control;
+
+        let name = self.dependency_graph.node(handle).name.clone();
+        if self.shutting_down() {
+            log::warn!(
+                "{} {} requested while shutting down, ignored",
+                action.as_str(),
+                name
+            );
+            return;
+        }
+        log::info!("{} {} requested", action.as_str(), name);
+
+        match (action, self.node_state(handle)) {
+            (Control::Stop, NodeState::Running)
+            | (Control::Restart, NodeState::Running)
+            | (Control::Stop, NodeState::Degraded)
+            | (Control::Restart, NodeState::Degraded) => {
+                self.stop_requested.insert(handle);
+                if action == Control::Restart {
+                    self.restart_requested.insert(handle);
+                }
+                self.send_stop(handle).await;
+            }
+            (Control::Reload, NodeState::Running) | (Control::Reload, NodeState::Degraded)
+                if !self.reloading.contains(&handle) =>
+            {
+                self.reload(handle).await;
+            }
+            (Control::Start, NodeState::Stopped)
+            | (Control::Start, NodeState::Quarantined)
+            | (Control::Restart, NodeState::Stopped)
+            | (Control::Restart, NodeState::Quarantined)
+            | (Control::Reload, NodeState::Stopped)
+            | (Control::Reload, NodeState::Quarantined) => {
+                self.quarantined.remove(&handle);
+                self.pending.insert(handle);
+                self.send_start(handle).await;
+            }
+            (_, state) => log::warn!(
+                "{} is {}, nothing to {}",
+                name,
+                state.as_str(),
+                action.as_str()
+            ),
+        }
+    }
+
+    // stops a program and whatever running depends on it, those first, to start them
+    // all again the way startup does once they are stopped
+    async fn reload(&mut self, handle: NodeHandle) {
+        let mut group = vec![handle];
+        let mut i = 0;
+        while i < group.len() {
+            let next: Vec<NodeHandle> = self
+                .dependency_graph
+                .dependees(group[i])
+                .filter(|h| self.running.contains(h) && !group.contains(h))
+                .collect();
+            group.extend(next);
+            i += 1;
+        }
+
+        for h in group {
+            self.stop_requested.insert(h);
+            self.reloading.insert(h);
+        }
+        self.continue_reload().await;
+    }
+
+    // stops what nothing running depends on anymore, and starts everything again in
+    // dependency order once the last one stopped
+    async fn continue_reload(&mut self) {
+        let stoppable: Vec<NodeHandle> = self
+            .reloading
+            .iter()
+            .copied()
+            .filter(|h| self.node_state(*h) != NodeState::Stopping && self.running.contains(h))
+            .filter(|h| {
+                self.dependency_graph
+                    .dependees(*h)
+                    .all(|d| !self.running.contains(&d))
+            })
+            .collect();
+        for h in stoppable {
+            self.send_stop(h).await;
+        }
+
+        if self.reloading.iter().any(|h| self.running.contains(h)) {
+            return;
+        }
+        let group: Vec<NodeHandle> = self.reloading.drain().collect();
+        self.pending.extend(group.iter().copied());
+        for h in group {
+            let unlocked = self
+                .dependency_graph
+                .dependencies(h)
+                .all(|d| !self.pending.contains(&d));
+            if unlocked {
+                self.send_start(h).await;
+            }
+        }
+    }
+
+    // a running program stopped answering its liveness probe, or answers again. it
+    // is restarted like on request, or left running with the system degraded
+    async fn liveness(&mut self, handle: NodeHandle, alive: bool) {
+        use config::LivenessFailure;
+
+        let p = self.dependency_graph.node(handle);
+        let name = p.name.clone();
+        let on_failure = p.liveness.as_ref().map(|l| l.on_failure);
+
+        if alive {
+            if self.degraded.remove(&handle) {
+                log::info!("{} alive again, no longer degraded", name);
+            }
+            return;
+        }
+        if self.shutting_down() || self.node_state(handle) != NodeState::Running {
+            return;
+        }
+
+        match on_failure.unwrap_or_default() {
+            LivenessFailure::Restart => {
+                log::warn!("{} not alive, restarting", name);
+                self.control(process::Control::Restart, handle).await;
+            }
+            LivenessFailure::Degrade => {
+                log::error!("{} not alive, system degraded", name);
+                self.degraded.insert(handle);
+            }
+        }
+    }
+
+    // never start this program, nor anything that depends on it
+    fn skip(&mut self, handle: NodeHandle) {
+        let mut todo = vec![handle];
+        while let Some(h) = todo.pop() {
+            if self.pending.remove(&h) {
+                log::warn!("skipping {}", self.dependency_graph.node(h).name);
+                todo.extend(self.dependency_graph.dependees(h));
+            }
+        }
+    }
+
     async fn on_stopped(&mut self, handle: NodeHandle, status: Option<process::ExitStatus>) {
+        self.degraded.remove(&handle);
+        if self.stopping.remove(&handle) {
+            self.release_stop_slot().await;
+        }
+
+        if self.stop_requested.remove(&handle) {
+            self.running.remove(&handle);
+            let name = self.dependency_graph.node(handle).name.clone();
+            if self.reloading.contains(&handle) && !self.shutting_down() {
+                log::info!("{} stopped for reloading", name);
+                self.continue_reload().await;
+            } else if self.restart_requested.remove(&handle) && !self.shutting_down() {
+                log::info!("{} stopped on request, restarting", name);
+                self.pending.insert(handle);
+                self.send_start(handle).await;
+            } else {
+                log::info!("{} stopped on request", name);
+            }
+            if self.shutting_down() {
+                self.stop_unlocked(handle).await;
+            }
+            return;
+        }
+
+        if !self.shutting_down() && self.restart.contains(&handle) {
+            let name = self.dependency_graph.node(handle).name.clone();
+
+            if !self.crash_looping(handle) {
+                self.running.remove(&handle);
+                self.pending.insert(handle);
+                if self.restart_allowed() {
+                    log::info!("{} exited, restarting", name);
+                    self.send_start(handle).await;
+                } else {
+                    log::warn!("{} exited, holding its restart", name);
+                    self.held.push(handle);
+                }
+                return;
+            }
+
+            // from here on it is treated like any program that stopped
+            log::error!(
+                "{} quarantined: restarted {} times within {}s, not restarting again",
+                name,
+                self.crash_loop.restarts,
+                self.crash_loop.within
+            );
+            self.restart.remove(&handle);
+            self.quarantined.insert(handle);
+        }
+
         if let Some(h) = self.running.take(&handle) {
             let p = self.dependency_graph.node(h);
             log::debug!("on stopped for {} {}", p.name, p.critical);
-            if p.critical && !p.disabled {
+            if p.primary && !p.disabled {
+                log::info!("primary program {} stopped", p.name);
+
+                if let (None, Some(status)) = (&self.primary, status) {
+                    self.primary = Some(ExitStatus {
+                        name: p.name.clone(),
+                        status,
+                    });
+                }
+
+                let _ = self.shutdown().await;
+            } else if p.critical && !p.disabled {
                 log::info!("critical task {} stopped", p.name);
 
-                if self.status.is_none() && status.is_some() {
+                if let (None, Some(status)) = (&self.status, status) {
                     self.status = Some(ExitStatus {
                         name: p.name.clone(),
-                        status: status.unwrap(),
+                        status,
                     });
                 }
 
@@ -149,25 +736,63 @@ impl Executor {
             }
         }
 
-        if self.shutting_down {
-            for h in self
-                .dependency_graph
-                .expand_back(handle, |n| !self.running.contains(&n))
-            {
-                self.send_stop(h).await;
-            }
+        if self.shutting_down() {
+            self.stop_unlocked(handle).await;
         }
     }
 
-    async fn send_start(&self, handle: NodeHandle) {
-        let p = self.dependency_graph.node(handle).clone();
+    // what can stop now that nothing depending on it runs anymore
+    async fn stop_unlocked(&mut self, handle: NodeHandle) {
+        let expanded: Vec<NodeHandle> = self
+            .dependency_graph
+            .expand_back(handle, |n| !self.running.contains(&n))
+            .collect();
+        for h in expanded {
+            self.send_stop(h).await;
+        }
+    }
+
+    async fn send_start(&mut self, handle: NodeHandle) {
+        let p = self.dependency_graph.program(handle);
 
         log::info!("starting program {}", p.name);
         let cmd = Command::Start((handle, p));
         self.send(cmd).await;
     }
 
-    async fn send_stop(&self, handle: NodeHandle) {
+    async fn send_stop(&mut self, handle: NodeHandle) {
+        // only running programs will report back, so only those take up a slot
+        if self.running.contains(&handle) && !self.stopping.contains(&handle) {
+            if self.stop_queue.contains(&handle) {
+                return;
+            }
+            if let Some(max) = self.max_concurrent_stops {
+                if self.stopping.len() >= max {
+                    log::debug!(
+                        "queueing stop for {}",
+                        self.dependency_graph.node(handle).name
+                    );
+                    self.stop_queue.push_back(handle);
+                    return;
+                }
+            }
+            self.stopping.insert(handle);
+        }
+
+        self.do_send_stop(handle).await;
+    }
+
+    async fn release_stop_slot(&mut self) {
+        while let Some(h) = self.stop_queue.pop_front() {
+            if self.running.contains(&h) {
+                self.stopping.insert(h);
+                self.do_send_stop(h).await;
+                return;
+            }
+        }
+    }
+
+    async fn do_send_stop(&mut self, handle: NodeHandle) {
         let p = self.dependency_graph.node(handle);
 
         log::info!("stopping program {}", p.name);
@@ -176,12 +801,13 @@ impl Executor {
         self.send(cmd).await;
     }
 
-    async fn send(&self, cmd: Command) {
-        Self::send_tx(self.tx.clone(), cmd).await;
-    }
-
-    async fn send_tx(mut tx: mpsc::Sender<Command>, cmd: Command) {
-        tx.send(cmd).await.expect("channel error");
+    async fn send(&mut self, cmd: Command) {
+        if let Err(e) = process::send(&mut self.tx, cmd, self.overflow).await {
+            log::error!("can't send command: {}", e);
+            if self.unsent.is_none() {
+                self.unsent = Some(e.into());
+            }
+        }
     }
 }
 
@@ -194,31 +820,27 @@ impl ExitStatus {
     fn into_result(self) -> Result<()> {
         match self.status.success() {
             true => Ok(()),
-            false => Err(Box::new(ExitStatusError {
+            false => Err(Error::Exit {
                 name: self.name,
                 status: self.status,
-            })),
+            }),
         }
     }
-}
-
-#[derive(Debug)]
-struct ExitStatusError {
-    name: String,
-    status: process::ExitStatus,
-}
 
-impl std::fmt::Display for ExitStatusError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        assert!(!self.status.success());
-        write!(f, "{} failed: {}", self.name, self.status)
+    fn into_primary_result(self) -> Result<()> {
+        match self.status.success() {
+            true => Ok(()),
+            false => Err(Error::PrimaryExit {
+                name: self.name,
+                status: self.status,
+            }),
+        }
     }
 }
 
-impl std::error::Error for ExitStatusError {}
-
 #[cfg(test)]
 mod tests {
+    use super::super::process::mpsc;
     use super::super::tokio_utils;
     use super::*;
 
@@ -227,6 +849,9 @@ mod tests {
     struct Fixture {
         rx: mpsc::Receiver<Command>,
         exec: Executor,
+        // system ready and phase commands are noted here, not returned
+        system_ready: bool,
+        phases: Vec<Phase>,
     }
 
     impl Fixture {
@@ -237,21 +862,36 @@ mod tests {
             let (cmd_tx, cmd_rx) = mpsc::channel(10);
 
             let exec = Executor::from_config(&cfg, cmd_tx, status_rx)?;
-            Ok(Fixture { rx: cmd_rx, exec })
+            Ok(Fixture {
+                rx: cmd_rx,
+                exec,
+                system_ready: false,
+                phases: Vec::new(),
+            })
         }
 
         async fn recv(&mut self) -> Command {
-            tokio::select! {
-                _ = tokio::time::delay_for(TIMEOUT) => {
-                    panic!("timeout");
-                },
-                x = self.rx.recv() => {
-                    match x {
-                        None => {
-                            panic!("channel error")
-                        },
-                        Some(cmd) => cmd,
+            loop {
+                let cmd = tokio::select! {
+                    _ = tokio::time::delay_for(TIMEOUT) => {
+                        panic!("timeout");
+                    },
+                    x = self.rx.recv() => {
+                        match x {
+                            None => {
+                                panic!("channel error")
+                            },
+                            Some(cmd) => cmd,
+                        }
                     }
+                };
+                match cmd {
+                    Command::SystemReady => {
+                        assert!(!self.system_ready, "system ready twice");
+                        self.system_ready = true;
+                    }
+                    Command::Phase(phase) => self.phases.push(phase),
+                    cmd => return cmd,
                 }
             }
         }
@@ -276,12 +916,20 @@ mod tests {
         }
 
         async fn expect_nothing(&mut self) {
-            tokio::select! {
-                _ = tokio::time::delay_for(TIMEOUT) => (),
-                _ = self.rx.recv() => {
-                    panic!("unexpected message")
-                }
-            };
+            loop {
+                tokio::select! {
+                    _ = tokio::time::delay_for(TIMEOUT) => return,
+                    cmd = self.rx.recv() => {
+                        match cmd {
+                            Some(Command::SystemReady) if !self.system_ready => {
+                                self.system_ready = true;
+                            }
+                            Some(Command::Phase(phase)) => self.phases.push(phase),
+                            _ => panic!("unexpected message"),
+                        }
+                    }
+                };
+            }
         }
     }
 
@@ -332,6 +980,19 @@ mod tests {
         fixture.expect_nothing().await;
     }
 
+    #[tokio::test]
+    async fn lost_process_manager_is_an_error() {
+        let toml = r#"
+        [[program]]
+        name = "single"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        drop(fixture.rx);
+        fixture.exec.init().await.expect_err("expect err");
+    }
+
     #[tokio::test]
     async fn error_stops_process() {
         let toml = r#"
@@ -534,4 +1195,649 @@ mod tests {
         fixture.exec.process(Event::Started(b)).await.unwrap();
         fixture.expect_start("c").await;
     }
+
+    #[tokio::test]
+    async fn restart_on_exit_restarts_until_shutdown() {
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.restart_on_exit("b").unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.expect_nothing().await;
+        assert!(fixture.exec.is_alive());
+
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        fixture.exec.shutdown().await.unwrap();
+        fixture.expect_stop(b).await;
+
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_stop(a).await;
+        fixture.expect_nothing().await;
+    }
+
+    #[tokio::test]
+    async fn programs_are_controlled_on_request() {
+        use process::Control;
+
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+        critical = true
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        // a critical program stopped on request doesn't take the system down
+        let stop = Event::Control(Control::Stop, b);
+        fixture.exec.process(stop).await.unwrap();
+        fixture.expect_stop(b).await;
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_nothing().await;
+        assert_eq!(NodeState::Stopped, fixture.exec.node_state(b));
+        assert!(!fixture.exec.shutting_down());
+
+        let start = Event::Control(Control::Start, b);
+        fixture.exec.process(start).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        let restart = Event::Control(Control::Restart, b);
+        fixture.exec.process(restart).await.unwrap();
+        fixture.expect_stop(b).await;
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        // already running
+        let start = Event::Control(Control::Start, a);
+        fixture.exec.process(start).await.unwrap();
+        fixture.expect_nothing().await;
+
+        fixture.exec.shutdown().await.unwrap();
+        fixture.expect_stop(b).await;
+        let restart = Event::Control(Control::Restart, a);
+        fixture.exec.process(restart).await.unwrap();
+        fixture.expect_nothing().await;
+    }
+
+    #[tokio::test]
+    async fn programs_reload_with_their_dependents_in_order() {
+        use process::Control;
+
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+
+        [[program]]
+        name = "c"
+        exec = "e"
+        depends = ["b"]
+
+        [[program]]
+        name = "d"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        let d = fixture.expect_start("d").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Started(d)).await.unwrap();
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        let c = fixture.expect_start("c").await;
+        fixture.exec.process(Event::Started(c)).await.unwrap();
+        fixture.expect_nothing().await;
+
+        // dependents stop first, leaves inward
+        let reload = Event::Control(Control::Reload, a);
+        fixture.exec.process(reload).await.unwrap();
+        fixture.expect_stop(c).await;
+        fixture.expect_nothing().await;
+        fixture.exec.process(Event::Stopped(c, None)).await.unwrap();
+        fixture.expect_stop(b).await;
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_stop(a).await;
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+
+        // and start again once what they depend on is up
+        fixture.expect_start("a").await;
+        fixture.expect_nothing().await;
+        assert_eq!(NodeState::Pending, fixture.exec.node_state(c));
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        fixture.expect_start("c").await;
+        fixture.exec.process(Event::Started(c)).await.unwrap();
+        fixture.expect_nothing().await;
+
+        assert_eq!(NodeState::Running, fixture.exec.node_state(d));
+        assert!(!fixture.exec.shutting_down());
+    }
+
+    #[tokio::test]
+    async fn programs_failing_liveness_restart_or_degrade() {
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+        liveness = {port = 8080}
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        liveness = {port = 8081, on_failure = "degrade"}
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        fixture
+            .exec
+            .process(Event::Liveness(a, false))
+            .await
+            .unwrap();
+        fixture.expect_stop(a).await;
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        assert!(!fixture.exec.shutting_down());
+
+        fixture
+            .exec
+            .process(Event::Liveness(b, false))
+            .await
+            .unwrap();
+        fixture.expect_nothing().await;
+        assert_eq!(NodeState::Degraded, fixture.exec.node_state(b));
+        assert_eq!("degraded", fixture.exec.tracker().node("b").as_str());
+
+        fixture
+            .exec
+            .process(Event::Liveness(b, true))
+            .await
+            .unwrap();
+        assert_eq!(NodeState::Running, fixture.exec.node_state(b));
+
+        // too late once shutting down
+        fixture.exec.shutdown().await.unwrap();
+        fixture.expect_stop(a).await;
+        fixture.expect_stop(b).await;
+        fixture
+            .exec
+            .process(Event::Liveness(b, false))
+            .await
+            .unwrap();
+        fixture.expect_nothing().await;
+    }
+
+    #[tokio::test]
+    async fn crash_looping_program_is_quarantined() {
+        let toml = r#"
+        crash_loop = {restarts = 2, within = 30}
+
+        [[program]]
+        name = "a"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.restart_on_exit("a").unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        for _ in 0..2 {
+            fixture.exec.process(Event::Started(a)).await.unwrap();
+            fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+            fixture.expect_start("a").await;
+        }
+
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_nothing().await;
+
+        assert!(fixture.exec.quarantined.contains(&a));
+        assert!(!fixture.exec.is_alive());
+    }
+
+    #[tokio::test]
+    async fn restart_limit_holds_restarts_until_resumed() {
+        let toml = r#"
+        restart_limit = {restarts = 2, per = 3600}
+
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.restart_on_exit("a").unwrap();
+        fixture.exec.restart_on_exit("b").unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        // the bucket takes two restarts, across programs
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_nothing().await;
+        assert!(fixture.exec.is_alive());
+
+        fixture.exec.process(Event::Resume).await.unwrap();
+        fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_start("a").await;
+    }
+
+    #[tokio::test]
+    async fn held_restarts_are_dropped_on_shutdown() {
+        let toml = r#"
+        restart_limit = {restarts = 0}
+
+        [[program]]
+        name = "a"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.restart_on_exit("a").unwrap();
+        fixture.exec.init().await.unwrap();
+
+        let a = fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        fixture.exec.process(Event::Stopped(a, None)).await.unwrap();
+        fixture.expect_nothing().await;
+        assert!(fixture.exec.is_alive());
+
+        fixture.exec.process(Event::Shutdown).await.unwrap();
+        assert!(!fixture.exec.is_alive());
+    }
+
+    #[tokio::test]
+    async fn restart_on_exit_fails_for_unknown_program() {
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.restart_on_exit("b").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_stops_limits_outstanding_stops() {
+        let toml = r#"
+        max_concurrent_stops = 1
+
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+
+        [[program]]
+        name = "c"
+        exec = "e"
+        depends = ["a"]
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.init().await.unwrap();
+        let a = fixture.expect_start("a").await;
+        let b = fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        let c = fixture.expect_start("c").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        fixture.exec.process(Event::Started(c)).await.unwrap();
+
+        fixture.exec.shutdown().await.unwrap();
+        fixture.expect_stop(b).await;
+        fixture.expect_nothing().await;
+
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_stop(c).await;
+        fixture.expect_nothing().await;
+
+        fixture.exec.process(Event::Stopped(c, None)).await.unwrap();
+        fixture.expect_stop(a).await;
+        fixture.expect_nothing().await;
+    }
+
+    #[tokio::test]
+    async fn system_ready_once_everything_started() {
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        fixture.exec.init().await.unwrap();
+        let a = fixture.expect_start("a").await;
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        let b = fixture.expect_start("b").await;
+        assert!(!fixture.system_ready);
+
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        fixture.expect_nothing().await;
+        assert!(fixture.system_ready);
+
+        // restarts don't make it ready again
+        fixture.exec.restart_on_exit("b").unwrap();
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_start("b").await;
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        fixture.expect_nothing().await;
+    }
+
+    #[tokio::test]
+    async fn skipped_programs_dont_hold_up_system_ready() {
+        let toml = DEP_FAILURE.replace("POLICY", "skip");
+
+        let mut fixture = Fixture::new(toml.as_str()).unwrap();
+        fixture.exec.init().await.unwrap();
+        let seed = fixture.expect_start("seed").await;
+        let server = fixture.expect_start("server").await;
+        fixture.exec.process(Event::Started(server)).await.unwrap();
+        fixture
+            .exec
+            .process(Event::Failed(seed, failure()))
+            .await
+            .unwrap();
+        fixture.expect_nothing().await;
+        assert!(fixture.system_ready);
+    }
+
+    #[tokio::test]
+    async fn phases_and_node_states_are_tracked() {
+        let toml = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+        "#;
+
+        let mut fixture = Fixture::new(toml).unwrap();
+        let tracker = fixture.exec.tracker();
+        fixture.exec.init().await.unwrap();
+        let a = fixture.expect_start("a").await;
+        assert_eq!(Phase::Init, tracker.phase());
+        assert_eq!(NodeState::Pending, tracker.node("a"));
+
+        fixture.exec.process(Event::Started(a)).await.unwrap();
+        let b = fixture.expect_start("b").await;
+        assert_eq!(NodeState::Running, tracker.node("a"));
+
+        fixture.exec.process(Event::Started(b)).await.unwrap();
+        assert_eq!(Phase::Running, tracker.phase());
+
+        fixture.exec.process(Event::Shutdown).await.unwrap();
+        fixture.expect_stop(b).await;
+        assert_eq!(Phase::ShuttingDown, tracker.phase());
+        assert_eq!(NodeState::Stopping, tracker.node("b"));
+        assert_eq!(NodeState::Running, tracker.node("a"));
+
+        fixture.exec.process(Event::Stopped(b, None)).await.unwrap();
+        fixture.expect_stop(a).await;
+        assert_eq!(NodeState::Stopped, tracker.node("b"));
+        assert_eq!(NodeState::Stopping, tracker.node("a"));
+
+        assert_eq!(
+            vec![Phase::Init, Phase::Running, Phase::ShuttingDown],
+            fixture.phases
+        );
+    }
+
+    const PRIMARY: &str = r#"
+        [[program]]
+        name = "db"
+        exec = "e"
+
+        [[program]]
+        name = "tests"
+        exec = "e"
+        depends = ["db"]
+        primary = true
+        "#;
+
+    #[tokio::test]
+    async fn primary_exit_code_is_kept() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut fixture = Fixture::new(PRIMARY).unwrap();
+        fixture.exec.init().await.unwrap();
+        let db = fixture.expect_start("db").await;
+        fixture.exec.process(Event::Started(db)).await.unwrap();
+        let tests = fixture.expect_start("tests").await;
+        fixture.exec.process(Event::Started(tests)).await.unwrap();
+
+        let status = process::ExitStatus::from_raw(3 << 8);
+        fixture
+            .exec
+            .process(Event::Stopped(tests, Some(status)))
+            .await
+            .unwrap();
+        fixture.expect_stop(tests).await;
+        fixture.expect_stop(db).await;
+
+        // supporting programs failing on the way down don't change the outcome
+        fixture
+            .exec
+            .process(Event::Stopped(db, Some(failure())))
+            .await
+            .unwrap();
+
+        let err = fixture.exec.exit().unwrap_err();
+        assert!(matches!(err, Error::PrimaryExit { .. }), "{:?}", err);
+        assert_eq!(3, err.code());
+    }
+
+    #[tokio::test]
+    async fn primary_success_is_success() {
+        let mut fixture = Fixture::new(PRIMARY).unwrap();
+        fixture.exec.init().await.unwrap();
+        let db = fixture.expect_start("db").await;
+        fixture.exec.process(Event::Started(db)).await.unwrap();
+        let tests = fixture.expect_start("tests").await;
+        fixture.exec.process(Event::Started(tests)).await.unwrap();
+        fixture
+            .exec
+            .process(Event::Stopped(tests, Some(process::ExitStatus::default())))
+            .await
+            .unwrap();
+        fixture.expect_stop(tests).await;
+        fixture.expect_stop(db).await;
+
+        fixture.exec.exit().unwrap();
+    }
+
+    #[tokio::test]
+    async fn failing_primary_task_shuts_down() {
+        let toml = PRIMARY.replace(
+            "primary = true",
+            "primary = true\n        ready = {completed={}}",
+        );
+        let mut fixture = Fixture::new(toml.as_str()).unwrap();
+        fixture.exec.init().await.unwrap();
+        let db = fixture.expect_start("db").await;
+        fixture.exec.process(Event::Started(db)).await.unwrap();
+        let tests = fixture.expect_start("tests").await;
+
+        fixture
+            .exec
+            .process(Event::Failed(tests, failure()))
+            .await
+            .unwrap();
+        fixture.expect_stop(tests).await;
+        fixture
+            .exec
+            .process(Event::Stopped(tests, None))
+            .await
+            .unwrap();
+        fixture.expect_stop(db).await;
+
+        let err = fixture.exec.exit().unwrap_err();
+        assert!(matches!(err, Error::PrimaryExit { .. }), "{:?}", err);
+        assert_eq!(1, err.code());
+    }
+
+    fn failure() -> process::ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        process::ExitStatus::from_raw(1 << 8)
+    }
+
+    const DEP_FAILURE: &str = r#"
+        [[program]]
+        name = "seed"
+        exec = "e"
+        ready = {completed={}}
+
+        [[program]]
+        name = "server"
+        exec = "e"
+
+        [[program]]
+        name = "demo"
+        exec = "e"
+        depends = ["seed", "server"]
+        on_dep_failure = "POLICY"
+
+        [[program]]
+        name = "frontend"
+        exec = "e"
+        depends = ["demo"]
+        "#;
+
+    #[tokio::test]
+    async fn failed_dependency_aborts_by_default() {
+        let toml = DEP_FAILURE.replace("on_dep_failure = \"POLICY\"", "");
+
+        let mut fixture = Fixture::new(toml.as_str()).unwrap();
+        fixture.exec.init().await.unwrap();
+        let seed = fixture.expect_start("seed").await;
+        fixture.expect_start("server").await;
+
+        fixture
+            .exec
+            .process(Event::Failed(seed, failure()))
+            .await
+            .expect_err("abort");
+    }
+
+    #[tokio::test]
+    async fn failed_dependency_skips_dependees() {
+        let toml = DEP_FAILURE.replace("POLICY", "skip");
+
+        let mut fixture = Fixture::new(toml.as_str()).unwrap();
+        fixture.exec.init().await.unwrap();
+        let seed = fixture.expect_start("seed").await;
+        let server = fixture.expect_start("server").await;
+
+        fixture
+            .exec
+            .process(Event::Failed(seed, failure()))
+            .await
+            .unwrap();
+        fixture.exec.process(Event::Started(server)).await.unwrap();
+        fixture.expect_nothing().await;
+
+        fixture
+            .exec
+            .process(Event::Stopped(server, None))
+            .await
+            .unwrap();
+        assert!(!fixture.exec.is_alive());
+    }
+
+    #[tokio::test]
+    async fn failed_dependency_starts_dependees_anyway() {
+        let toml = DEP_FAILURE.replace("POLICY", "start_anyway");
+
+        let mut fixture = Fixture::new(toml.as_str()).unwrap();
+        fixture.exec.init().await.unwrap();
+        let seed = fixture.expect_start("seed").await;
+        let server = fixture.expect_start("server").await;
+
+        fixture.exec.process(Event::Started(server)).await.unwrap();
+        fixture.expect_nothing().await;
+
+        fixture
+            .exec
+            .process(Event::Failed(seed, failure()))
+            .await
+            .unwrap();
+        let demo = fixture.expect_start("demo").await;
+
+        fixture.exec.process(Event::Started(demo)).await.unwrap();
+        fixture.expect_start("frontend").await;
+    }
 }
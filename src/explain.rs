@@ -0,0 +1,217 @@
+use super::config;
+use super::graph::{Graph, NodeHandle};
+use super::session::{Entry, Record};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// everything decompose makes of one program, and with a recorded session, what
+// happened to it and why
+pub fn explain(sys: &config::System, name: &str, session: Option<&[Record]>) -> Result<String> {
+    let graph = Graph::from_config(sys)?;
+    let h = graph
+        .find(name)
+        .ok_or_else(|| format!("No such program: {}", name))?;
+    let prog = graph.node(h);
+    let names = |hs: Vec<NodeHandle>| -> String {
+        let mut names: Vec<&str> = hs.iter().map(|h| graph.node(*h).name.as_str()).collect();
+        if names.is_empty() {
+            return "-".to_string();
+        }
+        names.sort_unstable();
+        names.join(", ")
+    };
+
+    let mut out = String::new();
+    writeln!(out, "{}", prog.name)?;
+    writeln!(out, "  ready:       {:?}", prog.ready)?;
+    if let Some(reason) = &prog.excluded {
+        writeln!(out, "  excluded:    {}", reason)?;
+    }
+    writeln!(
+        out,
+        "  depends on:  {}",
+        names(graph.dependencies(h).collect())
+    )?;
+    writeln!(
+        out,
+        "  needed by:   {}",
+        names(graph.dependees(h).collect())
+    )?;
+    let chain: Vec<&str> = start_order(&graph, h)
+        .into_iter()
+        .map(|h| graph.node(h).name.as_str())
+        .collect();
+    writeln!(out, "  start chain: {}", chain.join(" -> "))?;
+
+    writeln!(out, "  configuration:")?;
+    for line in format!("{:#?}", prog).lines() {
+        writeln!(out, "    {}", line)?;
+    }
+
+    if let Some(records) = session {
+        let dependencies: Vec<String> = graph
+            .dependencies(h)
+            .map(|h| graph.node(h).name.clone())
+            .collect();
+        writeln!(out, "  timeline:")?;
+        for (at, what) in timeline(name, &dependencies, records) {
+            writeln!(out, "    +{:.3}s {}", at, what)?;
+        }
+    }
+    Ok(out)
+}
+
+// the program and its transitive dependencies, each after what it depends on
+fn start_order(graph: &Graph, h: NodeHandle) -> Vec<NodeHandle> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    visit(graph, h, &mut seen, &mut order);
+    order
+}
+
+fn visit(
+    graph: &Graph,
+    h: NodeHandle,
+    seen: &mut HashSet<NodeHandle>,
+    order: &mut Vec<NodeHandle>,
+) {
+    if !seen.insert(h) {
+        return;
+    }
+    let mut dependencies: Vec<NodeHandle> = graph.dependencies(h).collect();
+    dependencies.sort_by(|a, b| graph.node(*a).name.cmp(&graph.node(*b).name));
+    for d in dependencies {
+        visit(graph, d, seen, order);
+    }
+    order.push(h);
+}
+
+// the records about the program, described with what led up to them
+fn timeline(name: &str, dependencies: &[String], records: &[Record]) -> Vec<(f64, String)> {
+    let mut ready_at: HashMap<&str, f64> = HashMap::new();
+    let mut started_at = None;
+    let mut lines = Vec::new();
+
+    for record in records {
+        let what = match &record.entry {
+            Entry::Started { program } => {
+                ready_at.insert(program.as_str(), record.at);
+                match (program == name, started_at) {
+                    (true, Some(start)) => {
+                        Some(format!("ready, {:.3}s after start", record.at - start))
+                    }
+                    (true, None) => Some("ready".to_string()),
+                    (false, _) => None,
+                }
+            }
+            Entry::Start { program } if program == name => {
+                started_at = Some(record.at);
+                let last = dependencies
+                    .iter()
+                    .filter_map(|d| ready_at.get(d.as_str()).map(|at| (at, d)))
+                    .max_by(|a, b| a.0.partial_cmp(b.0).expect("times are numbers"));
+                Some(match last {
+                    Some((_, d)) => format!("start, unlocked by {} being ready", d),
+                    None => "start, nothing to wait for".to_string(),
+                })
+            }
+            Entry::Stop { program } if program == name => Some("stop requested".to_string()),
+            Entry::Stopped { program, status } if program == name => Some(match status {
+                Some(status) => format!("stopped, {}", ExitStatus::from_raw(*status)),
+                None => "stopped".to_string(),
+            }),
+            Entry::Failed { program, status } if program == name => {
+                Some(format!("failed, {}", ExitStatus::from_raw(*status)))
+            }
+            Entry::Shutdown => Some("shutdown of the system requested".to_string()),
+            _ => None,
+        };
+        if let Some(what) = what {
+            lines.push((record.at, what));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[program]]
+        name = "db"
+        exec = "db"
+        ready = {port = 5432}
+
+        [[program]]
+        name = "migrate"
+        exec = "migrate"
+        ready = {completed = {}}
+        depends = ["db"]
+
+        [[program]]
+        name = "api"
+        exec = "api"
+        depends = ["db", "migrate"]
+
+        [[program]]
+        name = "frontend"
+        exec = "frontend"
+        depends = ["api"]
+        "#;
+
+    #[test]
+    fn explains_the_configuration() {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let out = explain(&sys, "api", None).unwrap();
+
+        assert!(out.starts_with("api\n  ready:       Nothing\n"), "{}", out);
+        assert!(out.contains("  depends on:  db, migrate\n"), "{}", out);
+        assert!(out.contains("  needed by:   frontend\n"), "{}", out);
+        assert!(
+            out.contains("  start chain: db -> migrate -> api\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("    Program {\n"), "{}", out);
+        assert!(!out.contains("timeline"), "{}", out);
+
+        assert!(explain(&sys, "nope", None).is_err());
+    }
+
+    #[test]
+    fn explains_a_session() {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let session: Vec<Record> = r#"
+            {"at":0.0,"type":"start","program":"db"}
+            {"at":0.2,"type":"started","program":"db"}
+            {"at":0.2,"type":"start","program":"migrate"}
+            {"at":0.9,"type":"started","program":"migrate"}
+            {"at":0.9,"type":"start","program":"api"}
+            {"at":1.4,"type":"started","program":"api"}
+            {"at":3.0,"type":"shutdown"}
+            {"at":3.0,"type":"stop","program":"api"}
+            {"at":3.1,"type":"stopped","program":"api","status":256}
+            "#
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+        let out = explain(&sys, "api", Some(&session)).unwrap();
+        let timeline = out.split("  timeline:\n").nth(1).unwrap();
+        assert_eq!(
+            "    +0.900s start, unlocked by migrate being ready\n\
+             \x20   +1.400s ready, 0.500s after start\n\
+             \x20   +3.000s shutdown of the system requested\n\
+             \x20   +3.000s stop requested\n\
+             \x20   +3.100s stopped, exit status: 1\n",
+            timeline
+        );
+    }
+}
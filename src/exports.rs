@@ -0,0 +1,187 @@
+extern crate log;
+extern crate regex;
+extern crate tokio;
+
+use super::config::Program;
+use super::output::Receiver;
+use super::tokio_utils::{make_err, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// values programs exported to their dependents, by program name and key
+#[derive(Clone, Default)]
+pub struct Exports {
+    values: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
+}
+
+impl Exports {
+    pub fn new() -> Exports {
+        Exports::default()
+    }
+
+    pub fn set(&self, program: &str, key: &str, value: String) {
+        let mut values = self.values.lock().expect("exports lock");
+        values
+            .entry(program.to_string())
+            .or_default()
+            .insert(key.to_string(), value);
+    }
+
+    // replaces references like {{ exports.setup.DB_URL }} in args and env values. the
+    // program is only copied if it has any
+    pub fn resolve(&self, prog: &mut Arc<Program>) -> Result<()> {
+        let re = reference();
+        let refers = |s: &String| s.contains("{{") && re.is_match(s);
+        if !prog.args.iter().any(refers) && !prog.env.values().any(refers) {
+            return Ok(());
+        }
+
+        let prog = Arc::make_mut(prog);
+        for value in prog.args.iter_mut().chain(prog.env.values_mut()) {
+            if refers(value) {
+                *value = self.substitute(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn substitute(&self, s: &str) -> Result<String> {
+        let re = reference();
+        let values = self.values.lock().expect("exports lock");
+
+        let mut result = String::new();
+        let mut last = 0;
+        for caps in re.captures_iter(s) {
+            let whole = caps.get(0).unwrap();
+            let (program, key) = (&caps[1], &caps[2]);
+            let value = values
+                .get(program)
+                .and_then(|v| v.get(key))
+                .ok_or_else(|| {
+                    make_err(format!(
+                        "{} has not exported {}, is it a dependency?",
+                        program, key
+                    ))
+                })?;
+
+            result += &s[last..whole.start()];
+            result += value;
+            last = whole.end();
+        }
+        result += &s[last..];
+        Ok(result)
+    }
+}
+
+// {{ exports.<program>.<key> }}, with or without the spaces
+fn reference() -> regex::Regex {
+    regex::Regex::new(r"\{\{\s*exports\.([^\s}]+)\.([^.\s}]+)\s*\}\}").expect("exports regex")
+}
+
+// the first capture group of the first matching line, or the whole match if the
+// regex has no groups. none if the output ends without a match
+pub async fn capture(mut rx: Receiver, re: &str) -> Result<Option<String>> {
+    use tokio::sync::broadcast::RecvError;
+
+    let re = regex::Regex::new(re).map_err(make_err)?;
+
+    loop {
+        match rx.recv().await {
+            Err(RecvError::Closed) => return Ok(None),
+            // the line that was wanted may still come, or may have been among these
+            Err(RecvError::Lagged(n)) => log::warn!("{} lines missed while capturing an export", n),
+            Ok(line) => {
+                if let Some(caps) = re.captures(line.trim_end_matches(&['\r', '\n'][..])) {
+                    let m = caps.get(1).or_else(|| caps.get(0)).unwrap();
+                    return Ok(Some(m.as_str().to_string()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::System;
+    use super::*;
+
+    #[test]
+    fn resolves_args_and_env() {
+        let toml = r#"
+            [[program]]
+            name = "api"
+            exec = "api"
+            args = ["--db", "{{ exports.setup.DB_URL }}", "--port={{exports.setup.PORT}}"]
+            env = {TOKEN = "{{ exports.auth.TOKEN }}", PLAIN = "plain"}
+            "#;
+        let mut prog = Arc::new(System::from_toml(toml).unwrap().program.remove(0));
+        let unresolved = prog.clone();
+
+        let exports = Exports::new();
+        exports.set("setup", "DB_URL", "postgres://localhost".to_string());
+        exports.set("setup", "PORT", "1234".to_string());
+        exports.set("auth", "TOKEN", "secret".to_string());
+
+        exports.resolve(&mut prog).unwrap();
+        assert_eq!(
+            vec!["--db", "postgres://localhost", "--port=1234"],
+            prog.args
+        );
+        assert_eq!("secret", prog.env["TOKEN"]);
+        assert_eq!("plain", prog.env["PLAIN"]);
+        assert_eq!("{{ exports.auth.TOKEN }}", unresolved.env["TOKEN"]);
+
+        // nothing to resolve, nothing copied
+        let mut resolved = prog.clone();
+        exports.resolve(&mut resolved).unwrap();
+        assert!(Arc::ptr_eq(&prog, &resolved));
+    }
+
+    #[test]
+    fn missing_export_is_an_error() {
+        let exports = Exports::new();
+        exports.set("setup", "DB_URL", "postgres://localhost".to_string());
+
+        assert!(exports.substitute("{{ exports.setup.PORT }}").is_err());
+        assert!(exports.substitute("{{ exports.other.DB_URL }}").is_err());
+        assert_eq!(
+            "{{ nothing }}",
+            exports.substitute("{{ nothing }}").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn captures_first_match() {
+        let (tx, rx) = tokio::sync::broadcast::channel(10);
+        for line in &["starting\n", "url=postgres://localhost\n", "url=other\n"] {
+            tx.send(line.to_string()).unwrap();
+        }
+
+        let value = capture(rx, "url=(.*)").await.unwrap();
+        assert_eq!(Some("postgres://localhost".to_string()), value);
+    }
+
+    #[tokio::test]
+    async fn capture_after_lagging() {
+        let (tx, rx) = tokio::sync::broadcast::channel(2);
+        for line in &[
+            "starting\n",
+            "still starting\n",
+            "url=postgres://localhost\n",
+        ] {
+            tx.send(line.to_string()).unwrap();
+        }
+
+        let value = capture(rx, "url=(.*)").await.unwrap();
+        assert_eq!(Some("postgres://localhost".to_string()), value);
+    }
+
+    #[tokio::test]
+    async fn capture_without_match() {
+        let (tx, rx) = tokio::sync::broadcast::channel(10);
+        tx.send("nothing here\n".to_string()).unwrap();
+        drop(tx);
+
+        assert_eq!(None, capture(rx, "url=(.*)").await.unwrap());
+    }
+}
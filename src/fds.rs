@@ -0,0 +1,171 @@
+extern crate nix;
+
+use super::config;
+use super::sockets::Sockets;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use tokio::process;
+
+// the pipes between programs and the listening sockets, shared by everything
+// started. both ends of a pipe stay open here, so either side can be restarted
+#[derive(Clone, Default)]
+pub struct Descriptors {
+    sockets: Sockets,
+    pipes: Arc<Mutex<HashMap<String, (File, File)>>>,
+}
+
+// fds to map into a child, as (ours, theirs). opened files are closed once dropped
+pub struct Opened {
+    pub fds: Vec<(RawFd, RawFd)>,
+    _files: Vec<File>,
+}
+
+impl Descriptors {
+    pub fn new() -> Descriptors {
+        Descriptors::default()
+    }
+
+    // the socket activation listeners at 3 and up, then the program's own fds
+    pub fn open(&self, prog: &config::Program) -> std::io::Result<Opened> {
+        let mut fds: Vec<(RawFd, RawFd)> =
+            self.sockets.listeners(prog)?.into_iter().zip(3..).collect();
+        let mut files = Vec::new();
+
+        for fd in &prog.fds {
+            let err = |e: std::io::Error| {
+                let msg = format!("{} fd {}: {}", prog.name, fd.fd, e);
+                std::io::Error::new(e.kind(), msg)
+            };
+            let ours = match &fd.source {
+                config::FdSource::Read(path) => {
+                    let file = File::open(path).map_err(err)?;
+                    let raw = file.as_raw_fd();
+                    files.push(file);
+                    raw
+                }
+                config::FdSource::Write(path) => {
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .map_err(err)?;
+                    let raw = file.as_raw_fd();
+                    files.push(file);
+                    raw
+                }
+                config::FdSource::PipeRead(name) => self.pipe(name).map_err(err)?.0,
+                config::FdSource::PipeWrite(name) => self.pipe(name).map_err(err)?.1,
+                config::FdSource::Listen(port) => {
+                    self.sockets.bind("127.0.0.1", *port).map_err(err)?
+                }
+            };
+            fds.push((ours, fd.fd));
+        }
+
+        Ok(Opened { fds, _files: files })
+    }
+
+    // the read and write end of the named pipe, created on first use
+    fn pipe(&self, name: &str) -> std::io::Result<(RawFd, RawFd)> {
+        let mut pipes = self.pipes.lock().unwrap();
+        if !pipes.contains_key(name) {
+            pipes.insert(name.to_string(), pipe()?);
+        }
+        let (r, w) = &pipes[name];
+        Ok((r.as_raw_fd(), w.as_raw_fd()))
+    }
+}
+
+// the read and write end of a fresh pipe
+pub fn pipe() -> std::io::Result<(File, File)> {
+    use nix::fcntl::OFlag;
+
+    let (r, w) = nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(std::io::Error::other)?;
+    Ok(unsafe { (File::from_raw_fd(r), File::from_raw_fd(w)) })
+}
+
+// have the child see our fds under their numbers
+pub fn remap(cmd: &mut process::Command, fds: Vec<(RawFd, RawFd)>) {
+    use nix::libc;
+
+    if fds.is_empty() {
+        return;
+    }
+
+    let above = fds.iter().map(|(_, theirs)| *theirs).max().unwrap_or(2) + 1;
+    let mut moved = vec![0; fds.len()];
+    let remap = move || {
+        // only async-signal-safe calls in here, we are between fork and exec.
+        // ours are moved out of the way first, they might sit on the targets
+        for (i, (ours, _)) in fds.iter().enumerate() {
+            moved[i] = unsafe { libc::fcntl(*ours, libc::F_DUPFD, above) };
+            if moved[i] < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        for (i, (_, theirs)) in fds.iter().enumerate() {
+            if unsafe { libc::dup2(moved[i], *theirs) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            unsafe { libc::close(moved[i]) };
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(remap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prog(spec: &str) -> config::Program {
+        config::Program::from_spec(spec).unwrap()
+    }
+
+    async fn run(fds: &Descriptors, prog: &config::Program, script: &str) -> String {
+        let opened = fds.open(prog).unwrap();
+        let mut cmd = process::Command::new("/bin/sh");
+        cmd.arg("-c").arg(script);
+        remap(&mut cmd, opened.fds.clone());
+        let output = cmd.output().await.unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    #[tokio::test]
+    async fn pipes_between_programs() {
+        let fds = Descriptors::new();
+        let producer = prog(r#"name=producer exec=sh fds='[{"fd":5,"pipe_write":"events"}]'"#);
+        let consumer = prog(r#"name=consumer exec=sh fds='[{"fd":3,"pipe_read":"events"}]'"#);
+
+        run(&fds, &producer, "echo hello >&5").await;
+        assert_eq!(
+            "hello\n",
+            run(&fds, &consumer, "read line <&3; echo $line").await
+        );
+    }
+
+    #[tokio::test]
+    async fn files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        let output = dir.path().join("out");
+        std::fs::write(&input, "aap\n").unwrap();
+
+        let spec = format!(
+            r#"name=p exec=sh fds='[{{"fd":3,"read":"{}"}},{{"fd":4,"write":"{}"}}]'"#,
+            input.display(),
+            output.display()
+        );
+        let fds = Descriptors::new();
+        run(&fds, &prog(spec.as_str()), "cat <&3 >&4").await;
+        assert_eq!("aap\n", std::fs::read_to_string(&output).unwrap());
+
+        let missing = prog(r#"name=p exec=sh fds='[{"fd":3,"read":"/no/such/file"}]'"#);
+        assert!(fds.open(&missing).is_err());
+    }
+}
@@ -1,17 +1,18 @@
 extern crate petgraph;
-extern crate string_error;
+extern crate serde_json;
 
 use super::config;
+use super::error::{Error, Result};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use petgraph::dot::{Config, Dot};
 use petgraph::Direction::{Incoming, Outgoing};
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
 pub struct Graph {
-    graph: petgraph::Graph<config::Program, ()>,
+    // shared with the commands starting them, programs are never copied
+    graph: petgraph::Graph<Arc<config::Program>, ()>,
 }
 
 pub type NodeHandle = petgraph::prelude::NodeIndex<u32>;
@@ -23,7 +24,7 @@ impl Graph {
         let mut mapping = HashMap::new();
 
         for prog in sys.program.iter() {
-            let n = graph.add_node(prog.clone());
+            let n = graph.add_node(Arc::new(prog.clone()));
             mapping.insert(prog.name.as_str(), n);
         }
 
@@ -31,12 +32,33 @@ impl Graph {
             for dep in prog.depends.iter() {
                 let from = mapping
                     .get(dep.as_str())
-                    .ok_or_else(|| string_error::into_err(format!("No such program: {}", dep)))?;
+                    .ok_or_else(|| Error::Graph(format!("No such program: {}", dep)))?;
                 let to = mapping.get(prog.name.as_str()).unwrap();
                 graph.add_edge(*from, *to, ());
             }
         }
 
+        // each phase waits for the one before it, which in turn waits for its predecessor
+        let mut members: HashMap<&str, Vec<NodeHandle>> = HashMap::new();
+        for prog in sys.program.iter() {
+            if let Some(phase) = &prog.phase {
+                let n = mapping[prog.name.as_str()];
+                members.entry(phase.as_str()).or_default().push(n);
+            }
+        }
+        let phases: Vec<&Vec<NodeHandle>> = sys
+            .phases
+            .iter()
+            .filter_map(|phase| members.get(phase.as_str()))
+            .collect();
+        for pair in phases.windows(2) {
+            for from in pair[0] {
+                for to in pair[1] {
+                    graph.update_edge(*from, *to, ());
+                }
+            }
+        }
+
         Graph::validate(&graph)?;
 
         Ok(Graph { graph })
@@ -46,6 +68,10 @@ impl Graph {
         &self.graph[h]
     }
 
+    pub fn program(&self, h: NodeHandle) -> Arc<config::Program> {
+        self.graph[h].clone()
+    }
+
     pub fn roots(&self) -> impl Iterator<Item = NodeHandle> + '_ {
         self.graph.externals(Incoming)
     }
@@ -58,6 +84,29 @@ impl Graph {
         self.graph.node_indices()
     }
 
+    pub fn find(&self, name: &str) -> Option<NodeHandle> {
+        self.all().find(|h| self.node(*h).name == name)
+    }
+
+    // the named programs, together with everything they (transitively) depend on
+    pub fn closure(&self, names: &[&str]) -> Result<HashSet<NodeHandle>> {
+        let mut todo = Vec::new();
+        for name in names {
+            let h = self
+                .find(name)
+                .ok_or_else(|| Error::Graph(format!("No such program: {}", name)))?;
+            todo.push(h);
+        }
+
+        let mut result = HashSet::new();
+        while let Some(h) = todo.pop() {
+            if result.insert(h) {
+                todo.extend(self.dependencies(h));
+            }
+        }
+        Ok(result)
+    }
+
     pub fn expand<'a, F>(
         &'a self,
         h: NodeHandle,
@@ -82,33 +131,111 @@ impl Graph {
             .filter(move |i| self.dependees(*i).all(&visited))
     }
 
+    // one-shot tasks are boxes, services ellipses. disabled programs are greyed out,
+    // critical ones outlined twice, and dependencies on tasks that may fail are
+    // labelled with what happens then
     pub fn dot(&self, w: &mut impl std::io::Write) {
+        use petgraph::visit::EdgeRef;
+
         let m = self.graph.map(|_, n| n.name.as_str(), |_, _| 0);
 
+        let node_attributes = |_, (h, _): (NodeHandle, _)| {
+            let prog = self.node(h);
+            let mut attrs = vec![match prog.ready {
+                config::ReadySignal::Completed => "shape = box",
+                _ => "shape = ellipse",
+            }];
+            if prog.disabled {
+                attrs.push("color = grey, fontcolor = grey, style = dashed");
+            }
+            let excluded = prog
+                .excluded
+                .as_ref()
+                .map(|reason| format!("xlabel = {:?}", reason));
+            attrs.extend(excluded.as_deref());
+            if prog.critical {
+                attrs.push("peripheries = 2");
+            }
+            attrs.join(", ")
+        };
+        let edge_attributes = |_, e: petgraph::graph::EdgeReference<'_, i32>| {
+            let dependency = self.node(e.source());
+            let dependent = self.node(e.target());
+            match (&dependency.ready, dependent.on_dep_failure) {
+                (config::ReadySignal::Completed, config::DepFailurePolicy::Skip) => {
+                    "label = \"skip on failure\"".to_string()
+                }
+                (config::ReadySignal::Completed, config::DepFailurePolicy::StartAnyway) => {
+                    "label = \"start anyway on failure\"".to_string()
+                }
+                _ => String::new(),
+            }
+        };
+
         w.write_fmt(format_args!(
             "{}",
-            Dot::with_config(&m, &[Config::EdgeNoLabel])
+            Dot::with_attr_getters(
+                &m,
+                &[Config::EdgeNoLabel],
+                &edge_attributes,
+                &node_attributes
+            )
         ))
         .expect("write");
     }
 
-    fn dependencies(&self, h: NodeHandle) -> impl Iterator<Item = NodeHandle> + '_ {
+    // {"nodes": [{"name", "critical", "disabled", "ready"}], "edges": [{"from", "to"}]},
+    // where ready is the kind of ready signal and edges go from dependency to dependent
+    pub fn json(&self, w: &mut impl std::io::Write) {
+        use serde_json::json;
+
+        let nodes: Vec<serde_json::Value> = self
+            .all()
+            .map(|h| {
+                let prog = self.node(h);
+                json!({
+                    "name": prog.name,
+                    "critical": prog.critical,
+                    "disabled": prog.disabled,
+                    "excluded": prog.excluded,
+                    "ready": prog.ready.kind(),
+                })
+            })
+            .collect();
+        let edges: Vec<serde_json::Value> = self
+            .graph
+            .raw_edges()
+            .iter()
+            .map(|e| {
+                json!({
+                    "from": self.node(e.source()).name,
+                    "to": self.node(e.target()).name,
+                })
+            })
+            .collect();
+
+        let doc = json!({"nodes": nodes, "edges": edges});
+        serde_json::to_writer_pretty(&mut *w, &doc).expect("write");
+        writeln!(w).expect("write");
+    }
+
+    pub fn dependencies(&self, h: NodeHandle) -> impl Iterator<Item = NodeHandle> + '_ {
         self.graph.neighbors_directed(h, Incoming)
     }
 
-    fn dependees(&self, h: NodeHandle) -> impl Iterator<Item = NodeHandle> + '_ {
+    pub fn dependees(&self, h: NodeHandle) -> impl Iterator<Item = NodeHandle> + '_ {
         self.graph.neighbors(h)
     }
 
-    fn validate(graph: &petgraph::Graph<config::Program, ()>) -> Result<()> {
+    fn validate(graph: &petgraph::Graph<Arc<config::Program>, ()>) -> Result<()> {
         if graph.externals(Incoming).next().is_none() {
-            return Err(string_error::static_err(
-                "system graph has no dependency-free root nodes",
+            return Err(Error::Graph(
+                "system graph has no dependency-free root nodes".to_string(),
             ));
         }
 
         if petgraph::algo::is_cyclic_directed(graph) {
-            return Err(string_error::static_err("system graph contains cycles"));
+            return Err(Error::Graph("system graph contains cycles".to_string()));
         }
 
         Ok(())
@@ -118,7 +245,6 @@ impl Graph {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
 
     fn make(toml: &str) -> Graph {
         let cfg = config::System::from_toml(toml).unwrap();
@@ -162,8 +288,7 @@ mod tests {
         let first_neigbours: Vec<_> = graph
             .graph
             .externals(Incoming)
-            .map(|i| graph.graph.neighbors(i))
-            .flatten()
+            .flat_map(|i| graph.graph.neighbors(i))
             .map(|h| graph.node(h).name.clone())
             .collect();
         assert_eq!(first_neigbours, vec!["proxy"]);
@@ -172,8 +297,7 @@ mod tests {
         let first_neigbours: Vec<_> = graph
             .graph
             .externals(Outgoing)
-            .map(|i| graph.graph.neighbors_directed(i, Incoming))
-            .flatten()
+            .flat_map(|i| graph.graph.neighbors_directed(i, Incoming))
             .map(|h| graph.node(h).name.clone())
             .collect();
         assert_eq!(first_neigbours, vec!["server"]);
@@ -337,4 +461,169 @@ mod tests {
 
         assert_eq!(vec!["a", "b", "c", "d", "e"], nodes);
     }
+
+    #[test]
+    fn closure_contains_transitive_dependencies() {
+        let cfg = r#"
+        [[program]]
+        name = "a"
+        exec = "a"
+
+        [[program]]
+        name = "b"
+        exec = "b"
+
+        [[program]]
+        name = "c"
+        exec = "c"
+        depends = ["a"]
+
+        [[program]]
+        name = "d"
+        exec = "d"
+        depends = ["c"]
+
+        [[program]]
+        name = "e"
+        exec = "e"
+        depends = ["d"]
+        "#;
+
+        let graph = make(cfg);
+
+        let closure = graph.closure(&["d"]).unwrap();
+        let mut names: Vec<&str> = closure
+            .iter()
+            .map(|h| graph.node(*h).name.as_str())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(vec!["a", "c", "d"], names);
+
+        assert!(graph.closure(&["nope"]).is_err());
+    }
+
+    #[test]
+    fn phases_wait_for_earlier_phases() {
+        let cfg = r#"
+        phases = ["infra", "services", "app"]
+
+        [[program]]
+        name = "db"
+        exec = "db"
+        phase = "infra"
+
+        [[program]]
+        name = "queue"
+        exec = "queue"
+        phase = "infra"
+
+        [[program]]
+        name = "api"
+        exec = "api"
+        phase = "services"
+        depends = ["db"]
+
+        [[program]]
+        name = "web"
+        exec = "web"
+        phase = "app"
+
+        [[program]]
+        name = "tool"
+        exec = "tool"
+        "#;
+
+        let graph = make(cfg);
+        let dependencies = |name: &str| {
+            let mut names: Vec<&str> = graph
+                .dependencies(graph.find(name).unwrap())
+                .map(|h| graph.node(h).name.as_str())
+                .collect();
+            names.sort_unstable();
+            names
+        };
+
+        assert!(dependencies("db").is_empty());
+        assert_eq!(vec!["db", "queue"], dependencies("api"));
+        assert_eq!(vec!["api"], dependencies("web"));
+        assert!(dependencies("tool").is_empty());
+
+        let unknown = cfg.replace("phase = \"app\"", "phase = \"later\"");
+        assert!(config::System::from_toml(unknown.as_str()).is_err());
+    }
+
+    #[test]
+    fn json_export() {
+        let cfg = r#"
+        [[program]]
+        name = "db"
+        exec = "db"
+        critical = true
+        ready = {port = 5432}
+
+        [[program]]
+        name = "api"
+        exec = "api"
+        disabled = true
+        depends = ["db"]
+        "#;
+
+        let mut out = Vec::new();
+        make(cfg).json(&mut out);
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "nodes": [
+                    {"name": "db", "critical": true, "disabled": false, "excluded": null, "ready": "port"},
+                    {"name": "api", "critical": false, "disabled": true, "excluded": null, "ready": "nothing"},
+                ],
+                "edges": [{"from": "db", "to": "api"}],
+            }),
+            doc
+        );
+    }
+
+    #[test]
+    fn dot_styles() {
+        let cfg = r#"
+        [[program]]
+        name = "migrate"
+        exec = "migrate"
+        ready = {completed = {}}
+
+        [[program]]
+        name = "api"
+        exec = "api"
+        critical = true
+        depends = ["migrate"]
+        on_dep_failure = "skip"
+
+        [[program]]
+        name = "debugger"
+        exec = "dlv"
+        platforms = ["plan9"]
+        depends = ["api"]
+        "#;
+
+        let mut out = Vec::new();
+        make(cfg).dot(&mut out);
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.contains("label = \"migrate\" shape = box"), "{}", dot);
+        assert!(
+            dot.contains("label = \"api\" shape = ellipse, peripheries = 2"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("color = grey"), "{}", dot);
+        let excluded = format!("xlabel = \"platforms plan9, not {}\"", std::env::consts::OS);
+        assert!(dot.contains(excluded.as_str()), "{}", dot);
+        assert!(
+            dot.contains("0 -> 1 [ label = \"skip on failure\"]"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("1 -> 2 [ ]"), "{}", dot);
+    }
 }
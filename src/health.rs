@@ -0,0 +1,204 @@
+extern crate log;
+extern crate serde_json;
+extern crate tokio;
+
+use super::config;
+use super::executor::{NodeState, Tracker};
+use super::process::Running;
+use super::report::Report;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// the state of the system as a whole, for tooling to wait on
+#[derive(Clone)]
+pub struct Health {
+    // names, and whether being done counts as ready
+    programs: Vec<(String, bool)>,
+    report: Report,
+    running: Running,
+    tracker: Tracker,
+}
+
+impl Health {
+    pub fn new(sys: &config::System, report: Report, running: Running, tracker: Tracker) -> Health {
+        let programs = sys
+            .program
+            .iter()
+            .map(|p| {
+                let done_is_ready = p.ready == config::ReadySignal::Completed
+                    || p.kind == config::ProgramType::Subsystem;
+                (p.name.clone(), done_is_ready)
+            })
+            .collect();
+
+        Health {
+            programs,
+            report,
+            running,
+            tracker,
+        }
+    }
+
+    fn state(&self, name: &str, done_is_ready: bool) -> &'static str {
+        let outcome = self.report.outcome(name);
+        if self.tracker.node(name) == NodeState::Degraded {
+            "degraded"
+        } else if self.running.contains(name) {
+            "ready"
+        } else if outcome.skipped {
+            "disabled"
+        } else if outcome.failed {
+            "failed"
+        } else if outcome.ready && done_is_ready {
+            "completed"
+        } else if outcome.ready {
+            "stopped"
+        } else {
+            "starting"
+        }
+    }
+
+    // whether everything is up, and {"ready": .., "phase": .., "programs": [{"name",
+    // "state", "executor"}]}, the last being where the executor has it
+    pub fn check(&self) -> (bool, serde_json::Value) {
+        let states: Vec<(&str, &str)> = self
+            .programs
+            .iter()
+            .map(|(name, done_is_ready)| (name.as_str(), self.state(name, *done_is_ready)))
+            .collect();
+        let ready = states
+            .iter()
+            .all(|(_, state)| ["ready", "completed", "disabled"].contains(state));
+
+        let programs: Vec<serde_json::Value> = states
+            .iter()
+            .map(|(name, state)| {
+                let executor = self.tracker.node(name).as_str();
+                serde_json::json!({"name": name, "state": state, "executor": executor})
+            })
+            .collect();
+        let phase = self.tracker.phase().as_str();
+        (
+            ready,
+            serde_json::json!({"ready": ready, "phase": phase, "programs": programs}),
+        )
+    }
+}
+
+// answers every request with 200 if all programs are ready, 503 otherwise
+pub async fn serve(mut listener: TcpListener, health: Health) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream, health.clone()));
+            }
+            Err(e) => log::warn!("health endpoint: {}", e),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, health: Health) {
+    // the request itself doesn't matter, but it has to be read before answering
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let (ready, body) = health.check();
+    let status = match ready {
+        true => "200 OK",
+        false => "503 Service Unavailable",
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        log::debug!("health endpoint: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[program]]
+        name = "db"
+        exec = "e"
+
+        [[program]]
+        name = "seed"
+        exec = "e"
+        ready = {completed={}}
+
+        [[program]]
+        name = "debugger"
+        exec = "e"
+        disabled = true
+        "#;
+
+    fn health() -> Health {
+        let sys = config::System::from_toml(TOML).unwrap();
+        Health::new(&sys, Report::new(), Running::default(), Tracker::default())
+    }
+
+    #[test]
+    fn ready_once_everything_is() {
+        let health = health();
+        health.report.skipped("debugger");
+        health.report.started("seed");
+        health.report.started("db");
+
+        let (ready, body) = health.check();
+        assert!(!ready);
+        assert_eq!(
+            serde_json::json!({"ready": false, "phase": "init", "programs": [
+                {"name": "db", "state": "starting", "executor": "pending"},
+                {"name": "seed", "state": "starting", "executor": "pending"},
+                {"name": "debugger", "state": "disabled", "executor": "pending"},
+            ]}),
+            body
+        );
+
+        health.report.ready("seed");
+        health.report.ready("db");
+        health.running.add("db", 123);
+        assert!(health.check().0);
+
+        health.running.remove("db");
+        let (ready, body) = health.check();
+        assert!(!ready);
+        assert_eq!("stopped", body["programs"][0]["state"]);
+        assert_eq!("completed", body["programs"][1]["state"]);
+    }
+
+    #[tokio::test]
+    async fn serves_the_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let health = health();
+        tokio::spawn(serve(listener, health.clone()));
+
+        let url = format!("http://127.0.0.1:{}/", port);
+        let response = reqwest::get(url.as_str()).await.unwrap();
+        assert_eq!(503, response.status().as_u16());
+        let body: serde_json::Value =
+            serde_json::from_str(response.text().await.unwrap().as_str()).unwrap();
+        assert_eq!(false, body["ready"]);
+
+        health.report.skipped("debugger");
+        health.report.ready("seed");
+        health.running.add("db", 123);
+        let response = reqwest::get(url.as_str()).await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+    }
+}
@@ -0,0 +1,116 @@
+extern crate log;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// spawn to ready durations of earlier runs, kept in the output directory
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    samples: HashMap<String, Vec<f64>>,
+}
+
+// enough to even out a few odd runs, recent enough to follow real changes
+const KEEP: usize = 20;
+const MIN_SAMPLES: usize = 3;
+
+impl History {
+    pub fn load(outdir: &Path) -> History {
+        let path = outdir.join("startup-history.json");
+        let samples = match std::fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(raw.as_str()).unwrap_or_else(|e| {
+                log::debug!("ignoring startup history {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        History { path, samples }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let raw = serde_json::to_string_pretty(&self.samples)?;
+        std::fs::write(&self.path, raw)
+    }
+
+    pub fn record(&mut self, name: &str, took: Duration) {
+        let samples = self.samples.entry(name.to_string()).or_default();
+        samples.push(took.as_secs_f64());
+        if samples.len() > KEEP {
+            samples.remove(0);
+        }
+    }
+
+    pub fn median(&self, name: &str) -> Option<f64> {
+        let mut samples = self.samples.get(name)?.clone();
+        if samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).expect("durations are numbers"));
+        let mid = samples.len() / 2;
+        match samples.len() % 2 {
+            0 => Some((samples[mid - 1] + samples[mid]) / 2.0),
+            _ => Some(samples[mid]),
+        }
+    }
+
+    // a warning if this startup is a lot slower than usual. small absolute
+    // differences are noise, however large relatively
+    pub fn check(&self, name: &str, took: Duration) -> Option<String> {
+        let median = self.median(name)?;
+        let took = took.as_secs_f64();
+        match took > 2.0 * median && took - median > 0.5 {
+            true => Some(format!(
+                "{} took {:.3}s to become ready, the median is {:.3}s",
+                name, took, median
+            )),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn warns_on_slow_startup() {
+        let mut history = History::default();
+        history.record("api", secs(1.0));
+        history.record("api", secs(1.2));
+        assert_eq!(None, history.check("api", secs(10.0)));
+
+        history.record("api", secs(0.9));
+        assert_eq!(Some(1.0), history.median("api"));
+        assert_eq!(None, history.check("api", secs(1.9)));
+        assert!(history.check("api", secs(2.1)).is_some());
+
+        history.record("fast", secs(0.01));
+        history.record("fast", secs(0.01));
+        history.record("fast", secs(0.01));
+        assert_eq!(None, history.check("fast", secs(0.1)));
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut history = History::load(dir.path());
+        for _ in 0..KEEP + 5 {
+            history.record("api", secs(1.0));
+        }
+        history.save().unwrap();
+
+        let history = History::load(dir.path());
+        assert_eq!(KEEP, history.samples["api"].len());
+        assert_eq!(Some(1.0), history.median("api"));
+    }
+}
@@ -0,0 +1,166 @@
+extern crate log;
+extern crate tokio;
+
+use super::config;
+use super::tokio_utils;
+use std::collections::HashMap;
+use std::process::ExitStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    Ready,
+    Crash,
+    Stop,
+}
+
+impl HookEvent {
+    fn command(self, hooks: &config::Hooks) -> &[String] {
+        match self {
+            HookEvent::Ready => &hooks.on_ready,
+            HookEvent::Crash => &hooks.on_crash,
+            HookEvent::Stop => &hooks.on_stop,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Ready => "ready",
+            HookEvent::Crash => "crash",
+            HookEvent::Stop => "stop",
+        }
+    }
+}
+
+// runs the matching command of each set of hooks in the background
+pub fn fire(
+    hooks: &[&config::Hooks],
+    event: HookEvent,
+    name: &str,
+    pid: u32,
+    status: Option<ExitStatus>,
+) {
+    let env = environment(event, name, pid, status);
+
+    for cmd in hooks.iter().map(|h| event.command(h)) {
+        if cmd.is_empty() {
+            continue;
+        }
+
+        let what = format!("{} {}", name, event.name());
+        spawn(cmd.to_vec(), env.clone(), what);
+    }
+}
+
+// runs the on_system_ready hook in the background, once everything is ready
+pub fn fire_system_ready(hooks: &config::Hooks) {
+    if hooks.on_system_ready.is_empty() {
+        return;
+    }
+
+    let mut env = HashMap::new();
+    env.insert("DECOMPOSE_EVENT".to_string(), "system_ready".to_string());
+    spawn(
+        hooks.on_system_ready.clone(),
+        env,
+        "system ready".to_string(),
+    );
+}
+
+fn spawn(cmd: Vec<String>, env: HashMap<String, String>, what: String) {
+    tokio::spawn(async move {
+        if let Err(e) = run(&cmd, &env).await {
+            log::warn!("{} hook failed: {}", what, e);
+        }
+    });
+}
+
+fn environment(
+    event: HookEvent,
+    name: &str,
+    pid: u32,
+    status: Option<ExitStatus>,
+) -> HashMap<String, String> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut env = HashMap::new();
+    env.insert("DECOMPOSE_EVENT".to_string(), event.name().to_string());
+    env.insert("DECOMPOSE_PROGRAM".to_string(), name.to_string());
+    env.insert("DECOMPOSE_PID".to_string(), pid.to_string());
+
+    if let Some(status) = status {
+        if let Some(code) = status.code() {
+            env.insert("DECOMPOSE_EXIT_CODE".to_string(), code.to_string());
+        }
+        if let Some(signal) = status.signal() {
+            env.insert("DECOMPOSE_SIGNAL".to_string(), signal.to_string());
+        }
+    }
+    env
+}
+
+pub async fn run(cmd: &[String], env: &HashMap<String, String>) -> tokio_utils::Result<()> {
+    let status = tokio::process::Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .envs(env)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(tokio_utils::make_err(format!("{}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn environment_describes_the_event() {
+        let status = ExitStatus::from_raw(3 << 8);
+        let env = environment(HookEvent::Crash, "prog", 123, Some(status));
+
+        assert_eq!("crash", env["DECOMPOSE_EVENT"]);
+        assert_eq!("prog", env["DECOMPOSE_PROGRAM"]);
+        assert_eq!("123", env["DECOMPOSE_PID"]);
+        assert_eq!("3", env["DECOMPOSE_EXIT_CODE"]);
+        assert!(!env.contains_key("DECOMPOSE_SIGNAL"));
+
+        let killed = ExitStatus::from_raw(9);
+        let env = environment(HookEvent::Stop, "prog", 123, Some(killed));
+        assert_eq!("9", env["DECOMPOSE_SIGNAL"]);
+        assert!(!env.contains_key("DECOMPOSE_EXIT_CODE"));
+
+        let env = environment(HookEvent::Ready, "prog", 123, None);
+        assert!(!env.contains_key("DECOMPOSE_EXIT_CODE"));
+    }
+
+    #[tokio::test]
+    async fn run_passes_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("hook.txt");
+        let cmd = vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "echo $DECOMPOSE_PROGRAM $DECOMPOSE_EVENT > {}",
+                target.display()
+            ),
+        ];
+
+        let env = environment(HookEvent::Ready, "prog", 123, None);
+        run(&cmd, &env).await.unwrap();
+
+        let content = std::fs::read_to_string(target).unwrap();
+        assert_eq!("prog ready\n", content);
+    }
+
+    #[tokio::test]
+    async fn failing_hook_is_an_error() {
+        let cmd = vec!["/bin/false".to_string()];
+        run(&cmd, &HashMap::new()).await.unwrap_err();
+    }
+}
@@ -0,0 +1,321 @@
+extern crate serde_any;
+extern crate serde_json;
+
+use serde_json::Value;
+use std::path::Path;
+
+// a program guessed from the project layout
+#[derive(Debug, PartialEq)]
+struct Candidate {
+    name: String,
+    exec: String,
+    args: Vec<String>,
+    ready: Option<String>,
+    depends: Vec<String>,
+    source: String,
+}
+
+// a starter configuration for the project in dir, in toml format
+pub fn scaffold(dir: &Path) -> String {
+    let mut candidates = Vec::new();
+    candidates.extend(cargo(dir));
+    candidates.extend(npm(dir));
+    candidates.extend(compose(dir));
+    candidates.extend(procfile(dir));
+
+    render(candidates)
+}
+
+fn render(candidates: Vec<Candidate>) -> String {
+    let mut out = String::from(
+        "# generated by decompose init, review the ready signals before relying on them\n",
+    );
+
+    if candidates.is_empty() {
+        out += "# nothing recognised, add programs like this:\n\
+                #\n\
+                # [[program]]\n\
+                # name = \"server\"\n\
+                # exec = \"./server\"\n\
+                # ready = {port = 8080}\n";
+        return out;
+    }
+
+    let mut names: Vec<String> = Vec::new();
+    for c in candidates {
+        // the same name from different sources would fail validation
+        let mut name = c.name.clone();
+        let mut n = 1;
+        while names.contains(&name) {
+            n += 1;
+            name = format!("{}-{}", c.name, n);
+        }
+        names.push(name.clone());
+
+        out += &format!("\n# from {}\n[[program]]\n", c.source);
+        out += &format!("name = {}\n", quote(&name));
+        out += &format!("exec = {}\n", quote(&c.exec));
+        if !c.args.is_empty() {
+            out += &format!("args = {}\n", list(&c.args));
+        }
+        if !c.depends.is_empty() {
+            out += &format!("depends = {}\n", list(&c.depends));
+        }
+        match c.ready {
+            Some(ready) => out += &format!("ready = {}\n", ready),
+            None => out += "# ready = {port = 8080}\n",
+        }
+    }
+    out
+}
+
+// json string literals are valid toml basic strings
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).expect("quote")
+}
+
+fn list(items: &[String]) -> String {
+    let quoted: Vec<String> = items.iter().map(|i| quote(i)).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
+fn read(path: &Path) -> Option<Value> {
+    let format = serde_any::guess_format(path)?;
+    let raw = std::fs::read_to_string(path).ok()?;
+    serde_any::from_str(raw.as_str(), format).ok()
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.canonicalize()
+        .ok()
+        .and_then(|d| d.file_name().map(|f| f.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "app".to_string())
+}
+
+// binaries in the package or workspace members, run through cargo
+fn cargo(dir: &Path) -> Vec<Candidate> {
+    let manifest = match read(&dir.join("Cargo.toml")) {
+        Some(m) => m,
+        None => return Vec::new(),
+    };
+
+    let mut packages = vec![(dir.to_path_buf(), manifest.clone())];
+    if let Some(members) = manifest
+        .pointer("/workspace/members")
+        .and_then(Value::as_array)
+    {
+        for member in members.iter().filter_map(Value::as_str) {
+            let member = dir.join(member);
+            if let Some(m) = read(&member.join("Cargo.toml")) {
+                packages.push((member, m));
+            }
+        }
+    }
+
+    packages
+        .into_iter()
+        .filter(|(path, m)| m.get("bin").is_some() || path.join("src/main.rs").is_file())
+        .filter_map(|(path, m)| {
+            let name = m.pointer("/package/name")?.as_str()?.to_string();
+            Some(Candidate {
+                name: name.clone(),
+                exec: "cargo".to_string(),
+                args: vec!["run".to_string(), "-p".to_string(), name],
+                ready: None,
+                depends: Vec::new(),
+                source: path.join("Cargo.toml").display().to_string(),
+            })
+        })
+        .collect()
+}
+
+// the dev or start script of package.json
+fn npm(dir: &Path) -> Vec<Candidate> {
+    let path = dir.join("package.json");
+    let package = match read(&path) {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+
+    let script = ["dev", "start"]
+        .iter()
+        .find(|s| package.pointer(&format!("/scripts/{}", s)).is_some());
+
+    match script {
+        Some(script) => vec![Candidate {
+            name: package
+                .get("name")
+                .and_then(Value::as_str)
+                .map(String::from)
+                .unwrap_or_else(|| dir_name(dir)),
+            exec: "npm".to_string(),
+            args: vec!["run".to_string(), script.to_string()],
+            ready: None,
+            depends: Vec::new(),
+            source: path.display().to_string(),
+        }],
+        None => Vec::new(),
+    }
+}
+
+// compose services, ready once their first published port accepts connections
+fn compose(dir: &Path) -> Vec<Candidate> {
+    let files = [
+        "docker-compose.yml",
+        "docker-compose.yaml",
+        "compose.yml",
+        "compose.yaml",
+    ];
+    let (path, compose) = match files
+        .iter()
+        .map(|f| dir.join(f))
+        .find_map(|p| read(&p).map(|c| (p, c)))
+    {
+        Some(found) => found,
+        None => return Vec::new(),
+    };
+
+    let services = match compose.get("services").and_then(Value::as_object) {
+        Some(s) => s,
+        None => return Vec::new(),
+    };
+
+    services
+        .iter()
+        .map(|(name, service)| {
+            let port = service
+                .get("ports")
+                .and_then(Value::as_array)
+                .and_then(|ports| ports.first())
+                .and_then(published_port);
+            let depends = match service.get("depends_on") {
+                Some(Value::Array(deps)) => deps
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect(),
+                Some(Value::Object(deps)) => deps.keys().cloned().collect(),
+                _ => Vec::new(),
+            };
+
+            Candidate {
+                name: name.clone(),
+                exec: "docker".to_string(),
+                args: vec!["compose".to_string(), "up".to_string(), name.clone()],
+                ready: port.map(|p| format!("{{port = {}}}", p)),
+                depends,
+                source: path.display().to_string(),
+            }
+        })
+        .collect()
+}
+
+// the host side of "8080:80", "127.0.0.1:8080:80" or {published = 8080}
+fn published_port(port: &Value) -> Option<u16> {
+    match port {
+        Value::String(s) => {
+            let parts: Vec<&str> = s.split(':').collect();
+            match parts.len() {
+                1 => None,
+                n => parts[n - 2].parse().ok(),
+            }
+        }
+        Value::Object(o) => o.get("published").and_then(|p| match p {
+            Value::Number(n) => n.as_u64().map(|n| n as u16),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+// one program per "name: command" line
+fn procfile(dir: &Path) -> Vec<Candidate> {
+    let path = dir.join("Procfile");
+    let raw = match std::fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(_) => return Vec::new(),
+    };
+
+    raw.lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_once(':'))
+        .map(|(name, command)| Candidate {
+            name: name.trim().to_string(),
+            exec: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), command.trim().to_string()],
+            ready: None,
+            depends: Vec::new(),
+            source: path.display().to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::System;
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn empty_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = scaffold(dir.path());
+        assert!(config.contains("nothing recognised"));
+    }
+
+    #[test]
+    fn recognises_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path();
+
+        write(
+            d,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"api\", \"lib\"]\n",
+        );
+        write(d, "api/Cargo.toml", "[package]\nname = \"api\"\n");
+        write(d, "api/src/main.rs", "fn main() {}\n");
+        write(d, "lib/Cargo.toml", "[package]\nname = \"lib\"\n");
+        write(
+            d,
+            "package.json",
+            r#"{"name": "web", "scripts": {"start": "node .", "dev": "vite"}}"#,
+        );
+        write(
+            d,
+            "docker-compose.yml",
+            "services:\n  db:\n    image: postgres\n    ports:\n      - \"5432:5432\"\n  cache:\n    image: redis\n    depends_on:\n      - db\n",
+        );
+        write(d, "Procfile", "worker: ./worker --queue \"jobs\"\n");
+
+        let config = scaffold(d);
+        let sys = System::from_toml(config.as_str()).expect(config.as_str());
+        let names: Vec<&str> = sys.program.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(vec!["api", "web", "cache", "db", "worker"], names);
+
+        assert_eq!(vec!["run", "-p", "api"], sys.program[0].args);
+        assert_eq!(vec!["run", "dev"], sys.program[1].args);
+        assert_eq!(vec!["db"], sys.program[2].depends);
+        assert_eq!(vec![5432], sys.program[3].ports());
+        assert_eq!(vec!["-c", "./worker --queue \"jobs\""], sys.program[4].args);
+    }
+
+    #[test]
+    fn published_ports() {
+        use serde_json::json;
+
+        assert_eq!(Some(8080), published_port(&json!("8080:80")));
+        assert_eq!(Some(8080), published_port(&json!("127.0.0.1:8080:80")));
+        assert_eq!(None, published_port(&json!("80")));
+        assert_eq!(
+            Some(8080),
+            published_port(&json!({"published": 8080, "target": 80}))
+        );
+    }
+}
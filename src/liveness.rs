@@ -0,0 +1,186 @@
+extern crate log;
+extern crate tokio;
+
+use super::config;
+use super::graph::NodeHandle;
+use super::process::{self, mpsc, Event};
+use super::readysignals::{self, Probes};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// the one probe a liveness section asks for, set up once
+enum Probe {
+    Port(u16),
+    Http(readysignals::Http, config::Endpoint),
+    Exec(tokio::process::Command),
+}
+
+impl Probe {
+    fn new(liveness: &config::Liveness) -> std::io::Result<Probe> {
+        if let Some(port) = liveness.port {
+            return Ok(Probe::Port(port));
+        }
+        if let Some(endpoint) = &liveness.healthcheck {
+            let http = readysignals::Http::new(endpoint)?;
+            return Ok(Probe::Http(http, endpoint.clone()));
+        }
+        let exec = liveness.exec.as_ref().expect("validated liveness probe");
+        Ok(Probe::Exec(readysignals::exec_command(exec)))
+    }
+
+    async fn run(&mut self, name: &str, pid: u32) -> Result<(), String> {
+        match self {
+            Probe::Port(port) => readysignals::port_once(*port).await,
+            Probe::Http(http, endpoint) => http.probe(endpoint).await,
+            Probe::Exec(command) => readysignals::run_once(command, "liveness", name, pid)
+                .await
+                .map_err(|e| e.to_string())?,
+        }
+    }
+}
+
+// what a program being probed needs to tell the executor about it
+pub struct Check {
+    pub handle: NodeHandle,
+    pub name: String,
+    pub pid: u32,
+    pub probes: Probes,
+    pub events: mpsc::Sender<Event>,
+    pub overflow: config::Overflow,
+    // set once it is asked to stop, after which failing probes say nothing
+    pub stopped: Arc<AtomicBool>,
+}
+
+// probes a ready program until it goes, telling the executor once it failed threshold
+// times in a row and again once it answers after that. a probe taking longer than the
+// interval counts as failed, a hung program may well accept and never answer
+pub async fn check(liveness: config::Liveness, mut check: Check) {
+    let mut probe = match Probe::new(&liveness) {
+        Ok(probe) => probe,
+        Err(e) => {
+            log::error!("{} liveness: {}", check.name, e);
+            return;
+        }
+    };
+    let interval = Duration::from_secs_f64(liveness.interval);
+    let mut failures = 0;
+
+    loop {
+        tokio::time::delay_for(interval).await;
+        if check.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let outcome = check
+            .probes
+            .run(tokio::time::timeout(
+                interval,
+                probe.run(check.name.as_str(), check.pid),
+            ))
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+        let alive = match outcome {
+            Ok(()) => {
+                let recovered = failures >= liveness.failure_threshold;
+                failures = 0;
+                match recovered {
+                    true => {
+                        log::info!("{} alive again", check.name);
+                        Some(true)
+                    }
+                    false => None,
+                }
+            }
+            Err(outcome) => {
+                failures += 1;
+                log::warn!(
+                    "{} liveness probe failed ({}/{}): {}",
+                    check.name,
+                    failures,
+                    liveness.failure_threshold,
+                    outcome
+                );
+                match failures == liveness.failure_threshold {
+                    true => {
+                        log::error!("{} not alive", check.name);
+                        Some(false)
+                    }
+                    false => None,
+                }
+            }
+        };
+
+        if let Some(alive) = alive {
+            let event = Event::Liveness(check.handle, alive);
+            if let Err(e) = process::send(&mut check.events, event, check.overflow).await {
+                log::debug!("{} liveness: {}", check.name, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn liveness(port: u16) -> config::Liveness {
+        config::Liveness {
+            port: Some(port),
+            healthcheck: None,
+            exec: None,
+            interval: 0.01,
+            failure_threshold: 2,
+            on_failure: config::LivenessFailure::Restart,
+        }
+    }
+
+    fn checking(port: u16) -> (mpsc::Receiver<Event>, Arc<AtomicBool>) {
+        let (tx, rx) = mpsc::channel(10);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let check = Check {
+            handle: NodeHandle::new(0),
+            name: "api".to_string(),
+            pid: 123,
+            probes: Probes::default(),
+            events: tx,
+            overflow: config::Overflow::Block,
+            stopped: stopped.clone(),
+        };
+        tokio::spawn(super::check(liveness(port), check));
+        (rx, stopped)
+    }
+
+    #[tokio::test]
+    async fn failing_and_recovering_is_told_once() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (mut rx, _stopped) = checking(port);
+
+        let quiet = Duration::from_millis(50);
+        assert!(tokio::time::timeout(quiet, rx.recv()).await.is_err());
+
+        drop(listener);
+        assert!(matches!(rx.recv().await, Some(Event::Liveness(_, false))));
+        assert!(tokio::time::timeout(quiet, rx.recv()).await.is_err());
+
+        let _listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap();
+        assert!(matches!(rx.recv().await, Some(Event::Liveness(_, true))));
+        assert!(tokio::time::timeout(quiet, rx.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nothing_once_stopped() {
+        let port = {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap().port()
+        };
+        let (mut rx, stopped) = checking(port);
+        stopped.store(true, Ordering::SeqCst);
+
+        // the sender goes with the check
+        assert!(rx.recv().await.is_none());
+    }
+}
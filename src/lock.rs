@@ -0,0 +1,151 @@
+extern crate log;
+extern crate nix;
+
+use nix::fcntl::{flock, FlockArg};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// how long the instance taken over gets to shut down
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// one decompose per outdir, held for as long as the file is open
+pub struct InstanceLock {
+    _file: File,
+}
+
+// locks the outdir, failing if another instance has it. with takeover that
+// instance is terminated instead, and the lock taken once it lets go
+pub fn acquire(outdir: &Path, takeover: bool) -> Result<InstanceLock> {
+    std::fs::create_dir_all(outdir).map_err(|e| format!("{}: {}", outdir.display(), e))?;
+    let path = outdir.join("decompose.lock");
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if !try_lock(&file)? {
+        let holder = holder(&mut file);
+        let who = match holder {
+            Some(pid) => format!("another decompose (pid {})", pid),
+            None => "another decompose".to_string(),
+        };
+        let pid = match (takeover, holder) {
+            (true, Some(pid)) => pid,
+            (true, None) => {
+                return Err(format!(
+                    "{} has {}, can't tell which to take over",
+                    who,
+                    outdir.display()
+                )
+                .into())
+            }
+            (false, _) => {
+                return Err(format!(
+                    "{} is running against {}, stop it first or use --takeover",
+                    who,
+                    outdir.display()
+                )
+                .into())
+            }
+        };
+
+        log::warn!("taking over {} from pid {}", outdir.display(), pid);
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(pid),
+            nix::sys::signal::Signal::SIGTERM,
+        )?;
+        let start = Instant::now();
+        while !try_lock(&file)? {
+            if start.elapsed() > TAKEOVER_TIMEOUT {
+                let msg = format!(
+                    "pid {} didn't let go of {} within {:?}",
+                    pid,
+                    outdir.display(),
+                    TAKEOVER_TIMEOUT
+                );
+                return Err(msg.into());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", std::process::id())?;
+    Ok(InstanceLock { _file: file })
+}
+
+fn try_lock(file: &File) -> Result<bool> {
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(true),
+        Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// the pid the instance holding the lock wrote
+fn holder(file: &mut File) -> Option<i32> {
+    let mut raw = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut raw).ok()?;
+    raw.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_instance_per_outdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let outdir = dir.path().join("out");
+
+        let lock = acquire(&outdir, false).unwrap();
+        let pid = std::fs::read_to_string(outdir.join("decompose.lock")).unwrap();
+        assert_eq!(std::process::id().to_string(), pid);
+
+        let err = acquire(&outdir, false).err().unwrap().to_string();
+        assert_eq!(
+            format!(
+                "another decompose (pid {}) is running against {}, stop it first or use --takeover",
+                pid,
+                outdir.display()
+            ),
+            err
+        );
+
+        drop(lock);
+        acquire(&outdir, false).unwrap();
+    }
+
+    #[test]
+    fn takeover_terminates_the_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decompose.lock");
+        std::fs::write(&path, "").unwrap();
+
+        // the holder writes its pid like decompose would, and lets go when terminated
+        let script = format!("echo $$ > {0}; exec flock -o {0} sleep 10", path.display());
+        let mut holder = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(script)
+            .spawn()
+            .unwrap();
+        let file = File::open(&path).unwrap();
+        while try_lock(&file).unwrap() {
+            flock(file.as_raw_fd(), FlockArg::Unlock).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let _lock = acquire(dir.path(), true).unwrap();
+        assert!(!holder.wait().unwrap().success());
+    }
+}
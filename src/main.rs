@@ -2,21 +2,54 @@ extern crate clap;
 extern crate log;
 extern crate simple_logger;
 
+use std::collections::HashSet;
 use std::error::Error;
 
+mod caps;
+mod chaos;
+mod compose;
 mod config;
+mod control;
+mod cores;
+mod doctor;
+mod environment;
+mod error;
 mod executor;
+mod explain;
+mod exports;
+mod fds;
 mod graph;
+mod health;
+mod history;
+mod hooks;
+mod init;
+mod liveness;
+mod lock;
+mod migrate;
 mod output;
+mod plan;
+mod preflight;
 mod process;
+mod progress;
 mod readysignals;
+mod report;
+mod scratch;
+mod seccomp;
+mod secrets;
+mod session;
+mod sockets;
 mod tokio_utils;
+mod watch;
 
 fn main() -> Result<(), Box<dyn Error>> {
     do_main().map_err(|e| {
         log::error!("{:?}", e);
         eprintln!("{}", e);
-        std::process::exit(1);
+        let code = e
+            .downcast_ref::<error::Error>()
+            .map(error::Error::code)
+            .unwrap_or(1);
+        std::process::exit(code);
     })
 }
 
@@ -31,20 +64,24 @@ fn do_main() -> Result<(), Box<dyn Error>> {
                     "specify what to do with child processes output:
 null => the output will be ignored
 inline => output streams from the child processes will be inlined with decompose's output
-files => log files for each process will be places in --outdir",
+files => log files for each process will be places in --outdir, plus an interleaved combined.log",
                 )
                 .short("o")
                 .long("output")
                 .takes_value(true)
                 .possible_values(&["null", "inline", "files"])
-                .default_value("inline"),
+                .default_value("inline")
+                .env("DECOMPOSE_OUTPUT")
+                .global(true),
         )
         .arg(
             clap::Arg::with_name("outdir")
                 .help("output directory, used if --output=files")
                 .default_value(default_od.as_str())
                 .short("d")
-                .long("outdir"),
+                .long("outdir")
+                .env("DECOMPOSE_OUTDIR")
+                .global(true),
         )
         .arg(
             clap::Arg::with_name("loglevel")
@@ -53,65 +90,702 @@ files => log files for each process will be places in --outdir",
                 .long("log")
                 .takes_value(true)
                 .possible_values(&["off", "error", "warning", "info", "debug", "trace"])
-                .default_value("warning"),
+                .default_value("warning")
+                .env("DECOMPOSE_LOG")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("pretty-json")
+                .help(
+                    "with inline output, show json log lines as level, message and fields; \
+                     optionally only the given fields, e.g. --pretty-json=user,path",
+                )
+                .long("pretty-json")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .use_delimiter(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("timestamps")
+                .help(
+                    "wall clock times in logs, or offsets from the start of the system \
+                     like +3.241s, which are also prefixed to inline output",
+                )
+                .long("timestamps")
+                .takes_value(true)
+                .possible_values(&["wall", "relative"])
+                .default_value("wall")
+                .env("DECOMPOSE_TIMESTAMPS")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("inline-timestamps")
+                .help("prefix inline output with wall clock times too, relative ones always are")
+                .long("inline-timestamps")
+                .env("DECOMPOSE_INLINE_TIMESTAMPS")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("report")
+                .help("write a summary of the run on exit, e.g. junit=report.xml")
+                .long("report")
+                .takes_value(true)
+                .env("DECOMPOSE_REPORT")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("with")
+                .help(
+                    "add a program for this run only, e.g. \
+                     'name=debugger exec=dlv args=\"attach 123\" depends=api'",
+                )
+                .long("with")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("chaos")
+                .help(
+                    "randomly terminate programs, e.g. \
+                     '{kill_interval = 30, targets = [\"worker-*\"], signal = \"kill\"}'",
+                )
+                .long("chaos")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("takeover")
+                .help("terminate the instance already running against the outdir, and take its place")
+                .long("takeover")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("debug-readiness")
+                .help("log every ready probe of every program and what came of it")
+                .long("debug-readiness")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("print-commands")
+                .help(
+                    "print what exactly is run for every program and build: the resolved \
+                     executable, arguments, working directory and environment changes",
+                )
+                .long("print-commands")
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("health-addr")
+                .help(
+                    "serve the state of all programs as json on this address, e.g. 127.0.0.1:7001, \
+                     with status 200 once all are ready and 503 until then",
+                )
+                .long("health-addr")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("record")
+                .help(
+                    "write every command and event of the run to this file, \
+                     one json record per line, to replay later",
+                )
+                .long("record")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("key")
+                .help("age identity file for decrypting sops or age encrypted configuration")
+                .long("key")
+                .takes_value(true)
+                .env("DECOMPOSE_KEY")
+                .global(true),
         )
         .arg(
             clap::Arg::with_name("config")
                 .help("configuration file, in toml format")
+                .env("DECOMPOSE_CONFIG")
                 .required(true)
                 .index(1),
         )
+        .arg(
+            clap::Arg::with_name("target")
+                .help(
+                    "start only this program and what it depends on, can be given more than \
+                     once; programs added with --with are kept too",
+                )
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             clap::Arg::with_name("dot")
                 .help("write the system dependency graph to stdout, in dot format")
                 .long("dot"),
         )
+        .arg(
+            clap::Arg::with_name("graph-format")
+                .help(
+                    "write the system dependency graph to stdout in this format, \
+                     json has nodes with their attributes and edges from dependency to dependent",
+                )
+                .long("graph-format")
+                .takes_value(true)
+                .possible_values(&["dot", "json"]),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("ctl")
+                .about(
+                    "ask the instance running against the outdir for its status, \
+                     or to start, stop, restart or reload a program",
+                )
+                .arg(
+                    clap::Arg::with_name("action")
+                        .help("what to ask for")
+                        .required(true)
+                        .possible_values(&["status", "start", "stop", "restart", "reload"])
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("program")
+                        .help("the program to start, stop, restart or reload")
+                        .required_ifs(&[
+                            ("action", "start"),
+                            ("action", "stop"),
+                            ("action", "restart"),
+                            ("action", "reload"),
+                        ])
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("dev")
+                .about(
+                    "start only the dependencies of a program, then run the program itself, \
+                     restarting it whenever it exits",
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("program")
+                        .help("the program under development")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("doctor")
+                .about("diagnose common problems with the environment and configuration")
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file to check as well")
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("format")
+                        .help(
+                            "text, or a json array of findings with severity, program, \
+                             config path and message, for editors and ci",
+                        )
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("env")
+                .about(
+                    "print the environment a program would get, after filtering, env groups \
+                     and expansion, e.g. to eval in a shell",
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("program")
+                        .help("the program whose environment to print")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    clap::Arg::with_name("format")
+                        .help("shell exports, or a json object")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["shell", "json"])
+                        .default_value("shell"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("explain")
+                .about(
+                    "show how a program is configured and where it sits in the system, \
+                     and with --session, what happened to it in a recorded run",
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("program")
+                        .help("the program to explain")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    clap::Arg::with_name("session")
+                        .help("session file written with --record, also while still running")
+                        .long("session")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("init").about(
+                "write a starter configuration to stdout, based on the project in the current directory",
+            ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("plan")
+                .about(
+                    "print the waves the system starts in, what each waits on and \
+                     the ready signals in between, without starting anything",
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("replay")
+                .about(
+                    "feed the events of a recorded session to the executor, checking it \
+                     sends the same commands, without starting any programs",
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("session")
+                        .help("session file written with --record")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("import")
+                .about("convert another tool's configuration, writing it to stdout")
+                .arg(
+                    clap::Arg::with_name("format")
+                        .help("what the file is")
+                        .required(true)
+                        .possible_values(&["compose"])
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::with_name("file")
+                        .help("the file to convert, like docker-compose.yml")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("migrate")
+                .about("convert a config in the old argv/enabled schema, writing it to stdout")
+                .arg(
+                    clap::Arg::with_name("config")
+                        .help("the old configuration file, in toml format")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
         .get_matches();
 
-    init_logging(args.value_of("loglevel").expect("log level"))?;
+    if let ("init", Some(_)) = args.subcommand() {
+        print!("{}", init::scaffold(std::path::Path::new(".")));
+        return Ok(());
+    }
+
+    if let ("migrate", Some(sub)) = args.subcommand() {
+        let raw = std::fs::read_to_string(sub.value_of("config").expect("config"))?;
+        let (config, notes) = migrate::migrate(raw.as_str())?;
+        for note in notes {
+            eprintln!("check: {}", note);
+        }
+        print!("{}", config);
+        return Ok(());
+    }
+
+    if let ("import", Some(sub)) = args.subcommand() {
+        let raw = std::fs::read_to_string(sub.value_of("file").expect("file"))?;
+        let (config, notes) = compose::import(raw.as_str())?;
+        for note in notes {
+            eprintln!("check: {}", note);
+        }
+        print!("{}", config);
+        return Ok(());
+    }
+
+    if let ("explain", Some(sub)) = args.subcommand() {
+        let sys = config::System::from_file(
+            sub.value_of("config").expect("config"),
+            sub.value_of("key"),
+        )?;
+        let session = sub.value_of("session").map(session::read).transpose()?;
+        let name = sub.value_of("program").expect("program");
+        print!("{}", explain::explain(&sys, name, session.as_deref())?);
+        return Ok(());
+    }
+
+    if let ("env", Some(sub)) = args.subcommand() {
+        let sys = config::System::from_file(
+            sub.value_of("config").expect("config"),
+            sub.value_of("key"),
+        )?;
+        let name = sub.value_of("program").expect("program");
+        let format = sub.value_of("format").expect("format");
+        print!("{}", environment::environment(&sys, name, format)?);
+        return Ok(());
+    }
+
+    if let ("plan", Some(sub)) = args.subcommand() {
+        let sys = config::System::from_file(
+            sub.value_of("config").expect("config"),
+            sub.value_of("key"),
+        )?;
+        print!("{}", plan::plan(&sys)?);
+        return Ok(());
+    }
+
+    if let ("replay", Some(sub)) = args.subcommand() {
+        init_logging(sub.value_of("loglevel").expect("log level"))?;
+        let sys = config::System::from_file(
+            sub.value_of("config").expect("config"),
+            sub.value_of("key"),
+        )?;
+        let records = session::read(sub.value_of("session").expect("session"))?;
+        tokio_utils::run(session::replay(&sys, records))?;
+        println!("session replayed, the executor behaved the same");
+        return Ok(());
+    }
+
+    if let ("ctl", Some(sub)) = args.subcommand() {
+        let outdir = std::path::Path::new(sub.value_of("outdir").expect("outdir"));
+        let request = match sub.value_of("program") {
+            Some(program) => format!("{} {}", sub.value_of("action").expect("action"), program),
+            None => sub.value_of("action").expect("action").to_string(),
+        };
+        print!("{}", control::request(outdir, request.as_str())?);
+        return Ok(());
+    }
+
+    if let ("doctor", Some(sub)) = args.subcommand() {
+        init_logging(sub.value_of("loglevel").expect("log level"))?;
+        return doctor::run(
+            sub.value_of("config"),
+            sub.value_of("outdir").expect("outdir"),
+            sub.value_of("format").expect("format"),
+        );
+    }
+
+    let (args, dev) = match args.subcommand() {
+        ("dev", Some(sub)) => (sub, sub.value_of("program")),
+        _ => (&args, None),
+    };
+
+    let timestamps = match args.value_of("timestamps") {
+        Some("relative") => output::Timestamps::Relative(std::time::Instant::now()),
+        _ => output::Timestamps::Wall,
+    };
+    init_logging_with(args.value_of("loglevel").expect("log level"), timestamps)?;
     log::debug!("arguments are config file is {:?}", args);
 
-    let sys = config::System::from_file(args.value_of("config").unwrap())?;
+    let junit = args.value_of("report").map(junit_path).transpose()?;
+    let chaos = args
+        .value_of("chaos")
+        .map(chaos::Chaos::parse)
+        .transpose()?;
+    let record = args.value_of("record").map(String::from);
+    let health_addr = args.value_of("health-addr").map(String::from);
+    let mut sys =
+        config::System::from_file(args.value_of("config").unwrap(), args.value_of("key"))?;
+    let mut targets: Vec<String> = args
+        .values_of("target")
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect();
+    for spec in args.values_of("with").into_iter().flatten() {
+        let prog = config::Program::from_spec(spec)?;
+        if !targets.is_empty() {
+            targets.push(prog.name.clone());
+        }
+        sys = sys.add(prog)?;
+    }
+
+    if !targets.is_empty() {
+        let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+        restrict(&mut sys, &targets)?;
+    }
 
-    if args.is_present("dot") {
+    if let Some(name) = dev {
+        develop(&mut sys, name)?;
+    }
+    if args.is_present("debug-readiness") {
+        for prog in sys.program.iter_mut() {
+            prog.ready_debug = true;
+        }
+    }
+
+    let graph_format = match args.is_present("dot") {
+        true => Some("dot"),
+        false => args.value_of("graph-format"),
+    };
+    if let Some(format) = graph_format {
         let g = graph::Graph::from_config(&sys)?;
-        g.dot(&mut std::io::stdout());
+        match format {
+            "json" => g.json(&mut std::io::stdout()),
+            _ => g.dot(&mut std::io::stdout()),
+        }
         return Ok(());
     }
 
     log::debug!("system is {:?}", sys);
 
+    let outdir = args.value_of("outdir").expect("outdir");
+    // before the checks, a takeover frees the ports
+    let _lock = lock::acquire(std::path::Path::new(outdir), args.is_present("takeover"))?;
+
+    preflight::check_disabled_dependencies(&sys)?;
+    preflight::check_ports(&sys)?;
+    preflight::check_requirements(&sys, std::path::Path::new(outdir))?;
+
+    let pretty_json = match args.is_present("pretty-json") {
+        true => Some(output::PrettyJson {
+            fields: args
+                .values_of("pretty-json")
+                .map(|fields| fields.map(String::from).collect::<Vec<_>>())
+                .filter(|fields| !fields.is_empty()),
+        }),
+        false => None,
+    };
     let of = output_factory(
         args.value_of("output").expect("output"),
-        args.value_of("outdir").expect("outdir"),
+        outdir,
+        pretty_json,
+        timestamps,
+        args.is_present("inline-timestamps"),
+        &sys.outdir_layout,
+        sys.channels.output,
     )?;
+    // next to the logs of the run when there is a directory for it. the kernel's
+    // core_pattern is system wide and only root may change it, so cores are copied
+    // over from wherever it puts them rather than written here directly
+    let cores = match sys.core_dumps {
+        true => Some(
+            of.run_dir()
+                .unwrap_or_else(|| std::path::Path::new(outdir))
+                .join("cores"),
+        ),
+        false => None,
+    };
+
+    let mut history = history::History::load(std::path::Path::new(outdir));
+    let report = report::Report::new().with_history(history.clone());
+    let names: Vec<String> = sys.program.iter().map(|p| p.name.clone()).collect();
 
-    tokio_utils::run(run(sys, of))?;
+    let result = tokio_utils::run(run(
+        sys,
+        of,
+        report.clone(),
+        cores,
+        Extras {
+            dev: dev.map(String::from),
+            chaos,
+            record,
+            health_addr,
+            progress: args.value_of("output") == Some("inline") && progress::Progress::wanted(),
+            print_commands: args.is_present("print-commands"),
+            control: control::path(std::path::Path::new(outdir)),
+        },
+    ));
+
+    // the report is most interesting when things went wrong, so always write it
+    if let Some(path) = junit {
+        report.write_junit(path, &names)?;
+    }
+    for (name, took) in report.ready_times() {
+        history.record(name.as_str(), took);
+    }
+    if let Err(e) = history.save() {
+        log::warn!("can't save startup history: {}", e);
+    }
+    result?;
     Ok(())
 }
 
+// the optional parts of a run, as given on the command line
+struct Extras {
+    dev: Option<String>,
+    chaos: Option<chaos::Chaos>,
+    record: Option<String>,
+    health_addr: Option<String>,
+    progress: bool,
+    print_commands: bool,
+    control: std::path::PathBuf,
+}
+
 async fn run(
     sys: config::System,
     of: Box<dyn output::OutputFactory>,
+    report: report::Report,
+    cores: Option<std::path::PathBuf>,
+    extras: Extras,
 ) -> Result<(), Box<dyn Error>> {
-    let (cmd_tx, cmd_rx) = process::mpsc::channel(10);
-    let (status_tx, status_rx) = process::mpsc::channel(10);
+    let Extras {
+        dev,
+        chaos,
+        record,
+        health_addr,
+        progress,
+        print_commands,
+        control,
+    } = extras;
 
-    let process_manager = process::ProcessManager::new(cmd_rx, status_tx, &sys, of);
-    let exec = executor::Executor::from_config(&sys, cmd_tx, status_rx)?;
+    let (commands, events) = (sys.channels.commands, sys.channels.events);
 
-    tokio::try_join!(process_manager.run(), exec.run())?;
+    let (cmd_tx, mut cmd_rx) = process::mpsc::channel(commands);
+    let (mut status_tx, status_rx) = process::mpsc::channel(events);
+
+    // the recorder sits in between the executor and the process manager
+    if let Some(path) = record {
+        let recorder = session::Recorder::create(path.as_str(), &sys)?;
+        let (tx, rx) = process::mpsc::channel(commands);
+        tokio::spawn(recorder.clone().commands(cmd_rx, tx));
+        cmd_rx = rx;
+        let (tx, rx) = process::mpsc::channel(events);
+        tokio::spawn(recorder.events(rx, status_tx));
+        status_tx = tx;
+    }
+
+    if progress {
+        let progress = progress::Progress::new(&sys)?;
+        let (tx, rx) = process::mpsc::channel(commands);
+        tokio::spawn(progress.clone().commands(cmd_rx, tx));
+        cmd_rx = rx;
+        let (tx, rx) = process::mpsc::channel(events);
+        tokio::spawn(progress.events(rx, status_tx));
+        status_tx = tx;
+    }
+
+    let control_tx = status_tx.clone();
+    let process_manager =
+        process::ProcessManager::new(cmd_rx, status_tx, &sys, of, report.clone(), cores)
+            .with_print_commands(print_commands);
+    let mut exec = executor::Executor::from_config(&sys, cmd_tx, status_rx)?;
+    if let Some(name) = dev {
+        exec.restart_on_exit(name.as_str())?;
+    }
+
+    if let Some(addr) = health_addr {
+        let listener = tokio::net::TcpListener::bind(addr.as_str())
+            .await
+            .map_err(|e| format!("health endpoint {}: {}", addr, e))?;
+        let health = health::Health::new(&sys, report, process_manager.running(), exec.tracker());
+        tokio::spawn(health::serve(listener, health));
+    }
+
+    if let Some(chaos) = chaos {
+        tokio::spawn(chaos::run(chaos, process_manager.running()));
+    }
+
+    // running without it is fine, it only adds a way in
+    match control::bind(control.as_path()) {
+        Ok(listener) => {
+            let server = control::Server::new(&sys, exec.tracker(), control_tx.clone())?;
+            tokio::spawn(control::serve(listener, server));
+        }
+        Err(e) => log::warn!("no control socket at {}: {}", control.display(), e),
+    }
+
+    if let Some(watcher) = watch::Watcher::new(&sys, control_tx)? {
+        tokio::spawn(watch::run(watcher));
+    }
+
+    let result = tokio::try_join!(process_manager.run(), exec.run());
+    let _ = std::fs::remove_file(control.as_path());
+    result?;
 
     log::debug!("done");
     Ok(())
 }
 
+// reduce the system to the given programs and their dependencies
+fn restrict(sys: &mut config::System, names: &[&str]) -> Result<(), Box<dyn Error>> {
+    let g = graph::Graph::from_config(sys)?;
+    let keep: HashSet<String> = g
+        .closure(names)?
+        .into_iter()
+        .map(|h| g.node(h).name.clone())
+        .collect();
+
+    sys.program.retain(|p| keep.contains(&p.name));
+    Ok(())
+}
+
+// reduce the system to the program under development and its dependencies
+fn develop(sys: &mut config::System, name: &str) -> Result<(), Box<dyn Error>> {
+    restrict(sys, &[name])?;
+    for prog in sys.program.iter_mut().filter(|p| p.name == name) {
+        // running it is the whole point, even if it is normally disabled
+        prog.disabled = false;
+        prog.critical = false;
+    }
+    Ok(())
+}
+
+// only junit reports for now, given as junit=<file>
+fn junit_path(arg: &str) -> Result<&str, Box<dyn Error>> {
+    match arg.split_once('=') {
+        Some(("junit", path)) if !path.is_empty() => Ok(path),
+        _ => Err(string_error::into_err(format!(
+            "unsupported report {}, expected junit=<file>",
+            arg
+        ))),
+    }
+}
+
 fn default_outdir() -> String {
     use std::str::FromStr;
     String::from_str(".decompose").unwrap()
 }
 
 fn init_logging(arg: &str) -> Result<(), Box<dyn Error>> {
+    init_logging_with(arg, output::Timestamps::Wall)
+}
+
+fn init_logging_with(arg: &str, timestamps: output::Timestamps) -> Result<(), Box<dyn Error>> {
     let level = match arg {
         "off" => log::LevelFilter::Off,
         "error" => log::LevelFilter::Error,
@@ -122,22 +796,72 @@ fn init_logging(arg: &str) -> Result<(), Box<dyn Error>> {
         _ => panic!("invalid log level {}", arg),
     };
 
+    if let output::Timestamps::Relative(_) = timestamps {
+        log::set_boxed_logger(Box::new(RelativeLogger { level, timestamps }))?;
+        log::set_max_level(level);
+        return Ok(());
+    }
+
     simple_logger::SimpleLogger::new()
         .with_level(level)
         .init()?;
     Ok(())
 }
 
+// simple_logger's layout, with offsets from the start instead of wall clock times
+struct RelativeLogger {
+    level: log::LevelFilter,
+    timestamps: output::Timestamps,
+}
+
+impl log::Log for RelativeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            println!(
+                "{} {:<5} [{}] {}",
+                self.timestamps.now(),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
 fn output_factory(
     arg: &str,
     od_arg: &str,
+    pretty_json: Option<output::PrettyJson>,
+    timestamps: output::Timestamps,
+    inline_timestamps: bool,
+    layout: &config::OutdirLayout,
+    capacity: usize,
 ) -> Result<Box<dyn output::OutputFactory>, Box<dyn Error>> {
     let of: Box<dyn output::OutputFactory> = match arg {
-        "null" => Box::new(output::NullOutputFactory {}),
-        "inline" => Box::new(output::InlineOutputFactory::new()),
+        "null" => Box::new(output::NullOutputFactory::new(capacity)),
+        "inline" => {
+            let mut of = output::InlineOutputFactory::new()
+                .with_timestamps(timestamps)
+                .with_capacity(capacity);
+            if inline_timestamps {
+                of = of.with_wall_timestamps();
+            }
+            match pretty_json {
+                Some(pretty) => Box::new(of.with_pretty_json(pretty)),
+                None => Box::new(of),
+            }
+        }
         "files" => {
             let od_arg = std::path::Path::new(od_arg);
-            let of = output::OutputFileFactory::new(od_arg)?;
+            let of = output::OutputFileFactory::with_layout(od_arg, layout)?
+                .with_timestamps(timestamps)
+                .with_capacity(capacity);
             Box::new(of)
         }
         _ => panic!("invalid output type {}", arg),
@@ -0,0 +1,193 @@
+extern crate serde;
+extern crate toml;
+
+use super::config::Program;
+use std::error::Error;
+use toml::value::{Table, Value};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+// keys a program may have in the current schema, as its derived Deserialize
+// asks for them, so they can't fall behind the struct
+fn program_keys() -> &'static [&'static str] {
+    use serde::de::{self, Visitor};
+    use serde::forward_to_deserialize_any;
+
+    struct Probe(&'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for &mut Probe {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            self.0 = fields;
+            Err(de::Error::custom("probed"))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut probe = Probe(&[]);
+    let _ = <Program as serde::Deserialize>::deserialize(&mut probe);
+    probe.0
+}
+
+// converts a config in the old argv/env/enabled schema to the current one. the
+// notes say what could not be converted faithfully and needs a human to look at
+pub fn migrate(raw: &str) -> Result<(String, Vec<String>)> {
+    let mut config: Table = toml::from_str(raw)?;
+    let mut notes = Vec::new();
+
+    let programs = match config.get_mut("program").and_then(Value::as_array_mut) {
+        Some(programs) => programs,
+        None => return Err("no [[program]] entries found".into()),
+    };
+
+    for (i, prog) in programs.iter_mut().enumerate() {
+        let prog = prog
+            .as_table_mut()
+            .ok_or_else(|| format!("program {} is not a table", i))?;
+        let name = prog
+            .get("name")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(|| format!("#{}", i));
+
+        migrate_program(prog, name.as_str(), &mut notes)?;
+    }
+
+    Ok((toml::to_string(&config)?, notes))
+}
+
+fn migrate_program(prog: &mut Table, name: &str, notes: &mut Vec<String>) -> Result<()> {
+    if let Some(argv) = prog.remove("argv") {
+        let mut argv = match argv {
+            Value::Array(argv) if !argv.is_empty() => argv,
+            _ => return Err(format!("{}: argv must be a non-empty list", name).into()),
+        };
+        if prog.contains_key("exec") || prog.contains_key("args") {
+            notes.push(format!(
+                "{}: both argv and exec/args given, argv wins",
+                name
+            ));
+        }
+        prog.insert("exec".to_string(), argv.remove(0));
+        prog.insert("args".to_string(), Value::Array(argv));
+    }
+
+    if let Some(enabled) = prog.remove("enabled") {
+        let enabled = enabled
+            .as_bool()
+            .ok_or_else(|| format!("{}: enabled must be true or false", name))?;
+        if !enabled {
+            prog.insert("disabled".to_string(), Value::Boolean(true));
+        }
+    }
+
+    // a list of KEY=VALUE strings used to be accepted as well
+    if let Some(Value::Array(env)) = prog.get("env").cloned() {
+        let mut table = Table::new();
+        for var in env {
+            match var.as_str().and_then(|v| v.split_once('=')) {
+                Some((key, value)) => {
+                    table.insert(key.to_string(), Value::String(value.to_string()));
+                }
+                None => notes.push(format!("{}: dropped env entry {}", name, var)),
+            }
+        }
+        prog.insert("env".to_string(), Value::Table(table));
+    }
+
+    let known = program_keys();
+    for key in prog.keys() {
+        if !known.contains(&key.as_str()) {
+            notes.push(format!("{}: unknown key {}, will be ignored", name, key));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::System;
+    use super::*;
+
+    #[test]
+    fn migrates_old_schema() {
+        let old = r#"
+            [[program]]
+            name = "server"
+            argv = ["./server", "--port", "8080"]
+            env = ["DEBUG=1", "broken"]
+            enabled = true
+
+            [[program]]
+            name = "proxy"
+            argv = ["./proxy"]
+            enabled = false
+            restart = "always"
+        "#;
+
+        let (new, notes) = migrate(old).unwrap();
+        let sys = System::from_toml(new.as_str()).expect(new.as_str());
+
+        assert_eq!("./server", sys.program[0].exec);
+        assert_eq!(vec!["--port", "8080"], sys.program[0].args);
+        assert_eq!("1", sys.program[0].env["DEBUG"]);
+        assert!(!sys.program[0].disabled);
+
+        assert_eq!("./proxy", sys.program[1].exec);
+        assert!(sys.program[1].args.is_empty());
+        assert!(sys.program[1].disabled);
+
+        assert_eq!(2, notes.len(), "{:?}", notes);
+        assert!(notes[0].contains("broken"));
+        assert!(notes[1].contains("restart"));
+    }
+
+    #[test]
+    fn current_schema_is_untouched() {
+        let current = r#"
+            [[program]]
+            name = "server"
+            exec = "./server"
+            args = ["--port", "8080"]
+        "#;
+
+        let (new, notes) = migrate(current).unwrap();
+        assert!(notes.is_empty());
+        let sys = System::from_toml(new.as_str()).unwrap();
+        assert_eq!("./server", sys.program[0].exec);
+    }
+
+    #[test]
+    fn knows_the_program_keys() {
+        let keys = program_keys();
+        for key in &["name", "exec", "type", "watch_dependents"] {
+            assert!(keys.contains(key), "{}", key);
+        }
+        // set while loading, not by the config
+        assert!(!keys.contains(&"excluded"));
+    }
+
+    #[test]
+    fn invalid_argv() {
+        assert!(migrate("[[program]]\nname = \"x\"\nargv = []\n").is_err());
+        assert!(migrate("name = \"x\"\n").is_err());
+    }
+}
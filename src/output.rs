@@ -1,10 +1,14 @@
 extern crate chrono;
 extern crate colored;
+extern crate serde_json;
 extern crate tokio;
 
 use super::config;
 use colored::Color;
+use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::broadcast;
 
@@ -16,10 +20,17 @@ pub trait OutputFactory {
     fn stderr(&mut self, prog: &config::Program) -> Sender {
         self.stdout(prog)
     }
+    // the directory of this run, for whatever else belongs with its logs
+    fn run_dir(&self) -> Option<&Path> {
+        None
+    }
 }
 
-fn make_channel() -> (Sender, Receiver) {
-    broadcast::channel(100)
+// lines a reader may fall behind on, unless the config says otherwise
+const DEFAULT_CAPACITY: usize = 100;
+
+fn make_channel(capacity: usize) -> (Sender, Receiver) {
+    broadcast::channel(capacity)
 }
 
 pub async fn consume<W, F>(mut rx: Receiver, mut writer: W, formatter: F)
@@ -41,126 +52,684 @@ where
     }
 }
 
-pub async fn produce<R>(tx: Sender, reader: Option<R>)
+// sends the lines read, longer ones cut off at max_line_length bytes so they are
+// never held in full
+pub async fn produce<R>(tx: Sender, reader: Option<R>, max_line_length: Option<usize>)
 where
     R: AsyncRead + std::marker::Unpin,
 {
-    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncReadExt;
 
-    if let Some(reader) = reader {
-        let mut reader = tokio::io::BufReader::new(reader).lines();
+    let mut reader = match reader {
+        Some(reader) => reader,
+        None => return,
+    };
+    let send = |line: String| {
+        if let Err(e) = tx.send(line) {
+            log::debug!("{:?}", e);
+        }
+    };
 
-        while let Some(line) = reader
-            .next_line()
-            .await
-            .map_err(|e| {
+    let mut line = Line::new(max_line_length);
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
                 log::error!("{}", e);
-                e
-            })
-            .ok()
-            .flatten()
-        {
-            if let Err(e) = tx.send(line) {
+                break;
+            }
+        };
+        for (i, chunk) in buf[..n].split(|b| *b == b'\n').enumerate() {
+            if i > 0 {
+                send(line.take());
+            }
+            line.push(chunk);
+        }
+    }
+    if !line.is_empty() {
+        send(line.take());
+    }
+}
+
+// a line as it comes in, with how much was cut off it
+struct Line {
+    bytes: Vec<u8>,
+    cut: usize,
+    max: Option<usize>,
+}
+
+impl Line {
+    fn new(max: Option<usize>) -> Line {
+        Line {
+            bytes: Vec::new(),
+            cut: 0,
+            max,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let room = match self.max {
+            Some(max) => max.saturating_sub(self.bytes.len()).min(chunk.len()),
+            None => chunk.len(),
+        };
+        self.bytes.extend_from_slice(&chunk[..room]);
+        self.cut += chunk.len() - room;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty() && self.cut == 0
+    }
+
+    fn take(&mut self) -> String {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        let mut cut = std::mem::take(&mut self.cut);
+        if cut == 0 {
+            if bytes.last() == Some(&b'\r') {
+                bytes.pop();
+            }
+            return String::from_utf8_lossy(&bytes).into_owned();
+        }
+
+        // not in the middle of a character
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            if e.error_len().is_none() {
+                cut += bytes.len() - e.valid_up_to();
+                bytes.truncate(e.valid_up_to());
+            }
+        }
+        format!("{}… [{} bytes cut]", String::from_utf8_lossy(&bytes), cut)
+    }
+}
+
+// keeps the last few lines of one or more output streams
+#[derive(Clone)]
+pub struct Tail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl Tail {
+    pub fn new(capacity: usize) -> Tail {
+        Tail {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn follow(&self, mut rx: Receiver) {
+        let tail = self.clone();
+        tokio::spawn(async move {
+            use tokio::sync::broadcast::RecvError;
+
+            loop {
+                match rx.recv().await {
+                    Ok(line) => tail.push(line),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().expect("tail lock");
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .expect("tail lock")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+// wraps a sink so that lines beyond the limit are dropped, with a periodic notice
+pub fn rate_limited(sink: Sender, limit: &config::RateLimit, capacity: usize) -> Sender {
+    let (tx, rx) = make_channel(capacity);
+
+    tokio::spawn(limit_rate(rx, sink, limit.clone()));
+    tx
+}
+
+async fn limit_rate(mut rx: Receiver, sink: Sender, limit: config::RateLimit) {
+    use tokio::sync::broadcast::RecvError;
+
+    let notify = |suppressed: &mut u64| {
+        if *suppressed > 0 {
+            let notice = format!("[decompose] {} lines suppressed", suppressed);
+            if let Err(e) = sink.send(notice) {
                 log::debug!("{:?}", e);
             }
+            *suppressed = 0;
+        }
+    };
+
+    let mut bucket = TokenBucket::new(limit.lines_per_sec, limit.burst);
+    let mut suppressed = 0;
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => notify(&mut suppressed),
+            line = rx.recv() => match line {
+                Ok(line) => {
+                    if bucket.take(tokio::time::Instant::now()) {
+                        if let Err(e) = sink.send(line) {
+                            log::debug!("{:?}", e);
+                        }
+                    } else {
+                        suppressed += 1;
+                    }
+                }
+                Err(RecvError::Lagged(n)) => suppressed += n,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+    notify(&mut suppressed);
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Option<tokio::time::Instant>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: u32) -> TokenBucket {
+        let capacity = f64::from(burst.max(1));
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: None,
+        }
+    }
+
+    fn take(&mut self, now: tokio::time::Instant) -> bool {
+        if let Some(last) = self.last {
+            let refill = (now - last).as_secs_f64() * self.rate;
+            self.tokens = (self.tokens + refill).min(self.capacity);
+        }
+        self.last = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
         }
     }
 }
 
-pub struct NullOutputFactory();
+pub struct NullOutputFactory {
+    capacity: usize,
+}
+
+impl NullOutputFactory {
+    pub fn new(capacity: usize) -> NullOutputFactory {
+        NullOutputFactory { capacity }
+    }
+}
 
 impl OutputFactory for NullOutputFactory {
     fn stdout(&mut self, _: &config::Program) -> Sender {
-        let (tx, rx) = make_channel();
+        let (tx, rx) = make_channel(self.capacity);
 
         tokio::spawn(consume(rx, tokio::io::sink(), |s| s));
         tx
     }
 }
 
+// feeds the output to an external process, restarting it if it goes away
+pub struct PipeOutputFactory {
+    cmd: Vec<String>,
+    capacity: usize,
+}
+
+impl PipeOutputFactory {
+    pub fn new(cmd: Vec<String>, capacity: usize) -> PipeOutputFactory {
+        PipeOutputFactory { cmd, capacity }
+    }
+
+    fn stream(&self, prog: &config::Program, stream: &'static str) -> Sender {
+        let (tx, rx) = make_channel(self.capacity);
+
+        tokio::spawn(pipe(rx, self.cmd.clone(), prog.name.clone(), stream));
+        tx
+    }
+}
+
+impl OutputFactory for PipeOutputFactory {
+    fn stdout(&mut self, prog: &config::Program) -> Sender {
+        self.stream(prog, "stdout")
+    }
+
+    fn stderr(&mut self, prog: &config::Program) -> Sender {
+        self.stream(prog, "stderr")
+    }
+}
+
+fn spawn_sink(
+    cmd: &[String],
+    name: &str,
+    stream: &str,
+) -> tokio::io::Result<(tokio::process::Child, tokio::process::ChildStdin)> {
+    let mut child = tokio::process::Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .env("DECOMPOSE_PROGRAM", name)
+        .env("DECOMPOSE_STREAM", stream)
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take().expect("piped stdin");
+    Ok((child, stdin))
+}
+
+async fn pipe(mut rx: Receiver, cmd: Vec<String>, name: String, stream: &'static str) {
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::broadcast::RecvError;
+
+    let (mut child, mut stdin) = match spawn_sink(&cmd, &name, stream) {
+        Ok(sink) => sink,
+        Err(e) => {
+            log::error!("failed to start output sink for {}: {}", name, e);
+            return;
+        }
+    };
+
+    loop {
+        let line = match rx.recv().await {
+            Ok(line) => format!("{}\n", line),
+            Err(RecvError::Lagged(n)) => {
+                log::debug!("output sink for {} lagging, {} lines missing", name, n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        if stdin.write_all(line.as_bytes()).await.is_err() {
+            log::warn!("output sink for {} exited, restarting", name);
+            let _ = child.await;
+
+            let restarted = match spawn_sink(&cmd, &name, stream) {
+                Ok(sink) => sink,
+                Err(e) => {
+                    log::error!("failed to restart output sink for {}: {}", name, e);
+                    return;
+                }
+            };
+            child = restarted.0;
+            stdin = restarted.1;
+
+            if let Err(e) = stdin.write_all(line.as_bytes()).await {
+                log::error!("output sink for {} keeps failing, giving up: {}", name, e);
+                return;
+            }
+        }
+    }
+
+    drop(stdin);
+    if let Err(e) = child.await {
+        log::debug!("output sink for {}: {}", name, e);
+    }
+}
+
+// how times are shown in the combined log, inline output and events
+#[derive(Debug, Clone, Copy)]
+pub enum Timestamps {
+    Wall,
+    // offsets from the start of the system
+    Relative(std::time::Instant),
+}
+
+impl Timestamps {
+    pub fn now(&self) -> String {
+        match self {
+            Timestamps::Wall => chrono::Local::now()
+                .format("%Y-%m-%dT%H:%M:%S%.3f")
+                .to_string(),
+            Timestamps::Relative(start) => format!("+{:.3}s", start.elapsed().as_secs_f64()),
+        }
+    }
+}
+
+// renders json log lines as "LEVEL message key=value ...", other lines are left alone
+#[derive(Debug, Clone, Default)]
+pub struct PrettyJson {
+    // the extra fields to show, all of them if None
+    pub fields: Option<Vec<String>>,
+}
+
+const LEVEL_KEYS: [&str; 3] = ["level", "severity", "lvl"];
+const MESSAGE_KEYS: [&str; 3] = ["msg", "message", "text"];
+// the inline output is about now, timestamps only add noise
+const TIME_KEYS: [&str; 5] = ["time", "ts", "timestamp", "@timestamp", "t"];
+
+impl PrettyJson {
+    pub fn render(&self, line: &str) -> Option<String> {
+        use serde_json::Value;
+
+        let mut object = match serde_json::from_str(line.trim()) {
+            Ok(Value::Object(o)) => o,
+            _ => return None,
+        };
+        let mut take = |keys: &[&str]| keys.iter().find_map(|k| object.remove(*k));
+        let text = |v: Value| match v {
+            Value::String(s) => s,
+            v => v.to_string(),
+        };
+
+        let level = take(&LEVEL_KEYS).map(text);
+        let message = take(&MESSAGE_KEYS).map(text);
+        take(&TIME_KEYS);
+
+        let mut parts = Vec::new();
+        if let Some(level) = level {
+            parts.push(format!("{:5}", level.to_uppercase()));
+        }
+        if let Some(message) = message {
+            parts.push(message);
+        }
+        let fields: Vec<(String, Value)> = match &self.fields {
+            Some(fields) => fields
+                .iter()
+                .filter_map(|f| object.remove(f).map(|v| (f.clone(), v)))
+                .collect(),
+            None => object.into_iter().collect(),
+        };
+        for (key, value) in fields {
+            parts.push(format!("{}={}", key, value));
+        }
+        Some(parts.join(" "))
+    }
+}
+
+// neutral colors only, red is for stderr
+const PROGRAM_COLORS: [Color; 5] = [
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+];
+
+// the same for a program on every run, so it can be told apart at a glance
+fn program_color(name: &str) -> Color {
+    // fnv-1a, as the std hasher makes no promises about staying the same
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    PROGRAM_COLORS[(hash % PROGRAM_COLORS.len() as u64) as usize]
+}
+
 pub struct InlineOutputFactory {
-    color_cycle: std::iter::Cycle<std::slice::Iter<'static, Color>>,
+    pretty_json: Option<Arc<PrettyJson>>,
+    // inline output has no timestamps, unless relative ones are asked for
+    timestamps: Option<Timestamps>,
+    capacity: usize,
 }
 
 impl InlineOutputFactory {
     pub fn new() -> InlineOutputFactory {
         InlineOutputFactory {
-            // restrict to neutral colors for stdout, red is for stderr
-            color_cycle: [
-                Color::Green,
-                Color::Yellow,
-                Color::Blue,
-                Color::Magenta,
-                Color::Cyan,
-            ]
-            .iter()
-            .cycle(),
+            pretty_json: None,
+            timestamps: None,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> InlineOutputFactory {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: Timestamps) -> InlineOutputFactory {
+        if let Timestamps::Relative(_) = timestamps {
+            self.timestamps = Some(timestamps);
         }
+        self
+    }
+
+    // wall clock ones too, when relative ones weren't asked for
+    pub fn with_wall_timestamps(mut self) -> InlineOutputFactory {
+        self.timestamps.get_or_insert(Timestamps::Wall);
+        self
+    }
+
+    pub fn with_pretty_json(mut self, pretty: PrettyJson) -> InlineOutputFactory {
+        self.pretty_json = Some(Arc::new(pretty));
+        self
     }
 
-    fn formatter(&self, prog: &config::Program, color: Color) -> impl Fn(String) -> String {
+    // stderr lines are red, after the name in the program's own color
+    fn formatter(&self, prog: &config::Program, stderr: bool) -> impl Fn(String) -> String {
         use colored::Colorize;
 
         let tag = prog.name.clone();
-        move |s| format!("[{}] {}\n", tag.clone().color(color), s)
+        let color = program_color(tag.as_str());
+        let pretty = self.pretty_json.clone();
+        let timestamps = self.timestamps;
+        move |s| {
+            let s = match pretty.as_ref().and_then(|p| p.render(s.as_str())) {
+                Some(rendered) => rendered,
+                None => s,
+            };
+            let s = match stderr {
+                true => s.red().to_string(),
+                false => s,
+            };
+            match timestamps {
+                Some(ts) => format!("{} [{}] {}\n", ts.now(), tag.clone().color(color), s),
+                None => format!("[{}] {}\n", tag.clone().color(color), s),
+            }
+        }
     }
 }
 
 impl OutputFactory for InlineOutputFactory {
     fn stdout(&mut self, prog: &config::Program) -> Sender {
-        let (tx, rx) = make_channel();
-        let color = *self.color_cycle.next().unwrap();
-        let fmt = self.formatter(prog, color);
+        let (tx, rx) = make_channel(self.capacity);
+        let fmt = self.formatter(prog, false);
 
         tokio::spawn(consume(rx, tokio::io::stdout(), fmt));
         tx
     }
 
     fn stderr(&mut self, prog: &config::Program) -> Sender {
-        let (tx, rx) = make_channel();
-        let fmt = self.formatter(prog, Color::Red);
+        let (tx, rx) = make_channel(self.capacity);
+        let fmt = self.formatter(prog, true);
 
         tokio::spawn(consume(rx, tokio::io::stderr(), fmt));
         tx
     }
 }
 
+fn entries(dir: &Path) -> Vec<std::fs::DirEntry> {
+    std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).collect())
+        .unwrap_or_default()
+}
+
+fn is_dir(entry: &std::fs::DirEntry) -> bool {
+    // not following the latest symlink
+    entry.file_type().map(|t| t.is_dir()).unwrap_or(false)
+}
+
+// the .out and .err files in the run directories next to the current one, also
+// those of programs with a directory of their own
+fn earlier_logs(root: &Path, current: &Path) -> Vec<PathBuf> {
+    fn is_log(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("out") | Some("err")
+        )
+    }
+
+    let mut logs = Vec::new();
+    for run in entries(root) {
+        if !is_dir(&run) || run.path() == current {
+            continue;
+        }
+        for entry in entries(run.path().as_path()) {
+            if is_dir(&entry) {
+                logs.extend(entries(entry.path().as_path()).iter().map(|e| e.path()));
+            } else {
+                logs.push(entry.path());
+            }
+        }
+    }
+    logs.retain(|p| is_log(p));
+    logs.sort();
+    logs
+}
+
+// the earlier run directories beyond the newest keep - 1, the current run being the
+// first to keep. only those with a combined log, the outdir holds more than runs
+fn expired_runs(root: &Path, current: &Path, keep: usize) -> Vec<PathBuf> {
+    let mut runs: Vec<(std::time::SystemTime, PathBuf)> = entries(root)
+        .iter()
+        .filter(|e| is_dir(e) && e.path() != current)
+        .filter(|e| e.path().join("combined.log").exists())
+        .map(|e| {
+            let modified = e.metadata().and_then(|m| m.modified());
+            (modified.unwrap_or(std::time::UNIX_EPOCH), e.path())
+        })
+        .collect();
+    runs.sort();
+    runs.reverse();
+    runs.into_iter()
+        .skip(keep.saturating_sub(1))
+        .map(|(_, path)| path)
+        .collect()
+}
+
+fn remove_runs(runs: Vec<PathBuf>) {
+    for run in runs {
+        log::debug!("removing earlier run {}", run.display());
+        if let Err(e) = std::fs::remove_dir_all(&run) {
+            log::warn!("removing earlier run {}: {}", run.display(), e);
+        }
+    }
+}
+
+// gzip in the background, the run doesn't wait for it
+fn compress(logs: Vec<PathBuf>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        if logs.is_empty() {
+            return;
+        }
+        log::debug!("compressing {} logs of earlier runs", logs.len());
+        match std::process::Command::new("gzip")
+            .arg("-f")
+            .args(&logs)
+            .stdin(std::process::Stdio::null())
+            .status()
+        {
+            Ok(status) if status.success() => (),
+            Ok(status) => log::warn!("compressing earlier logs: gzip {}", status),
+            Err(e) => log::warn!("compressing earlier logs: gzip: {}", e),
+        }
+    })
+}
+
 pub struct OutputFileFactory {
     outdir: PathBuf,
+    per_program: bool,
+    combined: Option<Sender>,
+    timestamps: Timestamps,
+    rotate: Option<config::Rotation>,
+    capacity: usize,
 }
 
 impl OutputFileFactory {
+    #[cfg(test)]
     pub fn new(outdir_root: &Path) -> std::result::Result<OutputFileFactory, std::io::Error> {
+        OutputFileFactory::with_layout(outdir_root, &config::OutdirLayout::default())
+    }
+
+    pub fn with_layout(
+        outdir_root: &Path,
+        layout: &config::OutdirLayout,
+    ) -> std::result::Result<OutputFileFactory, std::io::Error> {
         let outdir_root_buf = outdir_root.to_path_buf();
 
-        let now = chrono::Local::now();
-        let dirname = format!("{}.{}", now.format("%Y-%m-%dT%H:%M:%S"), std::process::id());
+        let outdir = match layout.flat {
+            true => {
+                std::fs::create_dir_all(&outdir_root_buf)?;
+                outdir_root_buf
+            }
+            false => {
+                let now = chrono::Local::now();
+                let dirname = now
+                    .format(layout.run_dir.as_str())
+                    .to_string()
+                    .replace("{pid}", std::process::id().to_string().as_str());
 
-        let mut outdir = outdir_root_buf.clone();
-        outdir.push(dirname.clone());
+                let mut outdir = outdir_root_buf.clone();
+                outdir.push(dirname.clone());
 
-        std::fs::create_dir_all(&outdir)?;
+                std::fs::create_dir_all(&outdir)?;
 
-        let _guard = ChdirGuard::new(outdir_root_buf.as_path())?;
+                if !layout.latest.is_empty() {
+                    let _guard = ChdirGuard::new(outdir_root_buf.as_path())?;
 
-        if let Err(e) = std::fs::remove_file("latest") {
-            log::debug!("can't remove latest: {:?}", e);
-        }
-        std::os::unix::fs::symlink(dirname, "latest")?;
+                    if let Err(e) = std::fs::remove_file(layout.latest.as_str()) {
+                        log::debug!("can't remove {}: {:?}", layout.latest, e);
+                    }
+                    std::os::unix::fs::symlink(dirname, layout.latest.as_str())?;
+                }
+                if let Some(keep) = layout.keep_runs {
+                    remove_runs(expired_runs(outdir_root, outdir.as_path(), keep));
+                }
+                if layout.compress {
+                    compress(earlier_logs(outdir_root, outdir.as_path()));
+                }
+                outdir
+            }
+        };
 
-        Ok(OutputFileFactory { outdir })
+        Ok(OutputFileFactory {
+            outdir,
+            per_program: layout.per_program,
+            combined: None,
+            timestamps: Timestamps::Wall,
+            rotate: layout.rotate.clone(),
+            capacity: DEFAULT_CAPACITY,
+        })
     }
 
-    fn stream(&self, name: String) -> Sender {
-        let path = self.outdir.clone();
-        let (tx, rx) = make_channel();
+    pub fn with_timestamps(mut self, timestamps: Timestamps) -> OutputFileFactory {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> OutputFileFactory {
+        self.capacity = capacity;
+        self
+    }
+
+    fn stream(&self, path: PathBuf, name: String) -> Sender {
+        let (tx, rx) = make_channel(self.capacity);
+        let rotate = self.rotate.clone();
 
         tokio::spawn(async move {
             match open(path, name.as_str()).await {
                 Ok((file, path)) => {
                     log::debug!("opend log file {:?} for {}", path, name);
 
-                    consume(rx, file, |s| format!("{}\n", s)).await;
+                    match rotate {
+                        Some(rotate) => consume_rotating(rx, file, path.as_path(), &rotate).await,
+                        None => consume(rx, file, |s| format!("{}\n", s)).await,
+                    }
                     log::debug!("closing log file {:?} for {}", path, name);
                 }
                 Err(e) => {
@@ -170,19 +739,127 @@ impl OutputFileFactory {
         });
         tx
     }
+
+    fn dir(&self, prog: &config::Program) -> PathBuf {
+        match self.per_program {
+            true => self.outdir.join(prog.name.as_str()),
+            false => self.outdir.clone(),
+        }
+    }
+
+    // opened lazily, there is no runtime yet to write it when the factory is created
+    fn combined(&mut self) -> Sender {
+        if self.combined.is_none() {
+            self.combined = Some(self.stream(self.outdir.clone(), "combined.log".to_string()));
+        }
+        self.combined.clone().unwrap()
+    }
+
+    // copies the lines of a program stream into the combined log, in arrival order
+    fn interleave(&mut self, tx: &Sender, name: String) {
+        let combined = self.combined();
+        let mut rx = tx.subscribe();
+        let timestamps = self.timestamps;
+
+        tokio::spawn(async move {
+            use tokio::sync::broadcast::RecvError;
+
+            loop {
+                match rx.recv().await {
+                    Ok(line) => {
+                        let now = timestamps.now();
+                        let _ = combined.send(format!("{} {} | {}", now, name, line));
+                    }
+                    Err(RecvError::Lagged(n)) => log::debug!("{} lines missing in combined log", n),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
 }
 
 impl OutputFactory for OutputFileFactory {
     fn stdout(&mut self, prog: &config::Program) -> Sender {
-        self.stream(format!("{}.out", prog.name))
+        let tx = self.stream(self.dir(prog), format!("{}.out", prog.name));
+        self.interleave(&tx, prog.name.clone());
+        tx
     }
 
     fn stderr(&mut self, prog: &config::Program) -> Sender {
-        self.stream(format!("{}.err", prog.name))
+        let tx = self.stream(self.dir(prog), format!("{}.err", prog.name));
+        self.interleave(&tx, prog.name.clone());
+        tx
+    }
+
+    fn run_dir(&self) -> Option<&Path> {
+        Some(self.outdir.as_path())
     }
 }
 
+// like consume, but moves the file aside once it is too big or too old
+async fn consume_rotating(
+    mut rx: Receiver,
+    mut file: tokio::fs::File,
+    path: &Path,
+    rotate: &config::Rotation,
+) {
+    use tokio::io::AsyncWriteExt;
+
+    let max_bytes = rotate.max_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+    let max_age = rotate.max_age.map(Duration::from_secs_f64);
+    let mut written = 0;
+    let mut opened = std::time::Instant::now();
+
+    while let Ok(line) = rx.recv().await.map_err(|e| {
+        log::debug!("{}, some output might be missing", e);
+        e
+    }) {
+        let line = format!("{}\n", line);
+        let full = max_bytes.is_some_and(|max| written > 0 && written + line.len() as u64 > max);
+        let old = max_age.is_some_and(|max| opened.elapsed() >= max);
+        if full || old {
+            let reopened = match rotate_file(path, rotate.keep) {
+                Ok(()) => tokio::fs::File::create(path).await,
+                Err(e) => Err(e),
+            };
+            file = match reopened {
+                Ok(file) => file,
+                Err(e) => {
+                    log::error!("rotating {}: {}", path.display(), e);
+                    return;
+                }
+            };
+            written = 0;
+            opened = std::time::Instant::now();
+        }
+
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            log::error!("{}", e);
+            return;
+        }
+        written += line.len() as u64;
+    }
+}
+
+// name.1 becomes name.2 and so on, name becomes name.1, dropping what is beyond keep
+fn rotate_file(path: &Path, keep: usize) -> std::io::Result<()> {
+    let numbered = |i: usize| PathBuf::from(format!("{}.{}", path.display(), i));
+    let ignore_missing = |result: std::io::Result<()>| match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+
+    if keep == 0 {
+        return ignore_missing(std::fs::remove_file(path));
+    }
+    for i in (1..keep).rev() {
+        ignore_missing(std::fs::rename(numbered(i), numbered(i + 1)))?;
+    }
+    ignore_missing(std::fs::rename(path, numbered(1)))
+}
+
 async fn open(mut path: PathBuf, filename: &str) -> tokio::io::Result<(tokio::fs::File, PathBuf)> {
+    tokio::fs::create_dir_all(&path).await?;
     path.push(filename);
     let p = path.clone();
     let f = tokio::fs::File::create(path).await?;
@@ -224,11 +901,15 @@ mod tests {
     #[test]
     fn creates_dirs() {
         let r = root();
-        let _ = OutputFileFactory::new(Path::new(r.path().to_str().unwrap()));
+        let output = OutputFileFactory::new(Path::new(r.path().to_str().unwrap())).unwrap();
 
         let mut latest = r.into_path();
         latest.push("latest");
         assert!(latest.is_dir());
+        assert_eq!(
+            std::fs::canonicalize(&latest).unwrap(),
+            std::fs::canonicalize(output.run_dir().unwrap()).unwrap()
+        );
 
         let symlink = std::fs::read_link(&latest).unwrap();
         let symlink = symlink.as_path();
@@ -273,7 +954,7 @@ mod tests {
             let reader = StringReader::new(data);
             let output = output.stdout(&prog);
 
-            produce(output, Some(reader)).await;
+            produce(output, Some(reader), None).await;
 
             // todo: why is this needed?
             tokio::time::delay_for(std::time::Duration::from_millis(100)).await
@@ -298,12 +979,211 @@ mod tests {
         assert_eq!("hello!\n", buf.as_str());
     }
 
+    #[test]
+    fn writes_combined_log() {
+        let r = root();
+        let output = OutputFileFactory::new(r.path()).expect("output factory");
+
+        produce_data("hello!\nbye!\n".to_string(), output);
+
+        let p = r.path().join("latest").join("combined.log");
+        let buf = std::fs::read_to_string(p).unwrap();
+
+        let re = regex::Regex::new(
+            r"^[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9:.]+ blah \| hello!\n[0-9T:.-]+ blah \| bye!\n$",
+        )
+        .unwrap();
+        assert!(re.is_match(buf.as_str()), "{:?}", buf);
+    }
+
+    #[test]
+    fn custom_layout() {
+        let r = root();
+        let layout = config::OutdirLayout {
+            run_dir: "run.{pid}".to_string(),
+            latest: "current".to_string(),
+            per_program: true,
+            flat: false,
+            compress: false,
+            ..Default::default()
+        };
+        let output = OutputFileFactory::with_layout(r.path(), &layout).expect("output factory");
+
+        produce_data("hello!\n".to_string(), output);
+
+        let run = format!("run.{}", std::process::id());
+        let symlink = std::fs::read_link(r.path().join("current")).unwrap();
+        assert_eq!(Path::new(run.as_str()), symlink);
+        let out = r.path().join(run).join("blah").join("blah.out");
+        assert_eq!("hello!\n", std::fs::read_to_string(out).unwrap());
+        assert!(!r.path().join("latest").exists());
+    }
+
+    #[test]
+    fn flat_layout_overwrites() {
+        let r = root();
+        let layout = config::OutdirLayout {
+            flat: true,
+            ..Default::default()
+        };
+
+        for data in &["first run\n", "second\n"] {
+            let output = OutputFileFactory::with_layout(r.path(), &layout).expect("output factory");
+            produce_data(data.to_string(), output);
+        }
+
+        // no run directories or symlink next to the logs
+        let mut entries: Vec<String> = std::fs::read_dir(r.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        assert_eq!(vec!["blah.out", "combined.log"], entries);
+        let out = r.path().join("blah.out");
+        assert_eq!("second\n", std::fs::read_to_string(out).unwrap());
+    }
+
+    #[test]
+    fn earlier_runs_are_compressed() {
+        let r = root();
+        for file in &["old/a.out", "old/a.err", "old/combined.log", "old/b/b.out"] {
+            let path = r.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "log\n").unwrap();
+        }
+        std::fs::create_dir(r.path().join("current")).unwrap();
+        std::fs::write(r.path().join("current").join("a.out"), "log\n").unwrap();
+        std::os::unix::fs::symlink("current", r.path().join("latest")).unwrap();
+
+        let logs = earlier_logs(r.path(), r.path().join("current").as_path());
+        let expected: Vec<PathBuf> = ["old/a.err", "old/a.out", "old/b/b.out"]
+            .iter()
+            .map(|f| r.path().join(f))
+            .collect();
+        assert_eq!(expected, logs);
+
+        compress(logs).join().unwrap();
+        assert!(r.path().join("old/a.out.gz").exists());
+        assert!(r.path().join("old/b/b.out.gz").exists());
+        assert!(!r.path().join("old/a.out").exists());
+        assert!(r.path().join("old/combined.log").exists());
+        assert!(r.path().join("current/a.out").exists());
+    }
+
+    #[test]
+    fn old_runs_are_removed() {
+        let r = root();
+        for (i, run) in ["run1", "run2", "run3"].iter().enumerate() {
+            let dir = r.path().join(run);
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("combined.log"), "log\n").unwrap();
+            let modified = std::time::SystemTime::now() - Duration::from_secs(100 - i as u64);
+            let file = std::fs::File::open(&dir).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        std::fs::create_dir(r.path().join("cores")).unwrap();
+
+        let layout = config::OutdirLayout {
+            run_dir: "current".to_string(),
+            keep_runs: Some(2),
+            ..Default::default()
+        };
+        let _output = OutputFileFactory::with_layout(r.path(), &layout).expect("output factory");
+
+        assert!(r.path().join("current").is_dir());
+        assert!(r.path().join("run3").is_dir());
+        assert!(!r.path().join("run2").exists());
+        assert!(!r.path().join("run1").exists());
+        assert!(r.path().join("cores").is_dir());
+    }
+
+    #[test]
+    fn rotates_files() {
+        let r = root();
+        let path = r.path().join("a.out");
+        for i in 0..4 {
+            std::fs::write(&path, format!("{}\n", i)).unwrap();
+            rotate_file(path.as_path(), 2).unwrap();
+        }
+        assert!(!path.exists());
+        let read = |i: usize| std::fs::read_to_string(r.path().join(format!("a.out.{}", i)));
+        assert_eq!("3\n", read(1).unwrap());
+        assert_eq!("2\n", read(2).unwrap());
+        assert!(read(3).is_err());
+    }
+
+    #[test]
+    fn rotates_live_files_once_too_big() {
+        let r = root();
+        let layout = config::OutdirLayout {
+            flat: true,
+            rotate: Some(config::Rotation {
+                // room for a line or two
+                max_mb: Some(7.0 / 1024.0 / 1024.0),
+                max_age: None,
+                keep: 5,
+            }),
+            ..Default::default()
+        };
+        let output = OutputFileFactory::with_layout(r.path(), &layout).expect("output factory");
+
+        produce_data("one\ntwo\nthree\n".to_string(), output);
+
+        let read = |name: &str| std::fs::read_to_string(r.path().join(name)).unwrap();
+        assert_eq!("three\n", read("blah.out"));
+        assert_eq!("two\n", read("blah.out.1"));
+        assert_eq!("one\n", read("blah.out.2"));
+    }
+
+    #[test]
+    fn relative_timestamps() {
+        let start = std::time::Instant::now() - Duration::from_millis(3241);
+        let re = regex::Regex::new(r"^\+3\.2[0-9]{2}s$").unwrap();
+        let now = Timestamps::Relative(start).now();
+        assert!(re.is_match(now.as_str()), "{}", now);
+    }
+
+    #[test]
+    fn inline_format() {
+        colored::control::set_override(false);
+        let prog = make_prog("blah");
+
+        let plain = InlineOutputFactory::new().with_timestamps(Timestamps::Wall);
+        assert_eq!(
+            "[blah] hi\n",
+            plain.formatter(&prog, false)("hi".to_string())
+        );
+
+        let stamped = InlineOutputFactory::new().with_wall_timestamps();
+        let line = stamped.formatter(&prog, true)("oops".to_string());
+        let re =
+            regex::Regex::new(r"^[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9:.]+ \[blah\] oops\n$").unwrap();
+        assert!(re.is_match(line.as_str()), "{:?}", line);
+
+        let start = std::time::Instant::now();
+        let relative = InlineOutputFactory::new()
+            .with_timestamps(Timestamps::Relative(start))
+            .with_wall_timestamps();
+        let line = relative.formatter(&prog, false)("hi".to_string());
+        assert!(line.starts_with('+'), "{:?}", line);
+    }
+
+    #[test]
+    fn program_colors_are_stable() {
+        assert_eq!(program_color("api"), program_color("api"));
+        let colors: std::collections::HashSet<String> = ["api", "db", "web", "worker", "cache"]
+            .iter()
+            .map(|name| format!("{:?}", program_color(name)))
+            .collect();
+        assert!(colors.len() > 1);
+    }
+
     #[tokio::test]
     async fn test_produce() {
         let reader = StringReader::new("aap\nnoot\nmies\n".to_string());
-        let (tx, mut rx) = make_channel();
+        let (tx, mut rx) = make_channel(DEFAULT_CAPACITY);
 
-        tokio::spawn(produce(tx, Some(reader)));
+        tokio::spawn(produce(tx, Some(reader), None));
 
         assert_eq!("aap", rx.recv().await.unwrap());
         assert_eq!("noot", rx.recv().await.unwrap());
@@ -311,13 +1191,133 @@ mod tests {
         assert!(rx.recv().await.is_err());
     }
 
+    #[tokio::test]
+    async fn produce_cuts_long_lines() {
+        let long = format!(
+            "{}\r\nshort\r\nabcé{}\nlast",
+            "x".repeat(20000),
+            "y".repeat(10)
+        );
+        let reader = StringReader::new(long);
+        let (tx, mut rx) = make_channel(DEFAULT_CAPACITY);
+
+        tokio::spawn(produce(tx, Some(reader), Some(4)));
+
+        assert_eq!("xxxx… [19997 bytes cut]", rx.recv().await.unwrap());
+        assert_eq!("shor… [2 bytes cut]", rx.recv().await.unwrap());
+        // é is two bytes, the second one didn't fit
+        assert_eq!("abc… [12 bytes cut]", rx.recv().await.unwrap());
+        assert_eq!("last", rx.recv().await.unwrap());
+        assert!(rx.recv().await.is_err());
+    }
+
     #[tokio::test]
     async fn test_produce_does_nothing_on_empty_reader() {
         let reader: Option<StringReader> = None;
-        let (tx, mut rx) = make_channel();
+        let (tx, mut rx) = make_channel(DEFAULT_CAPACITY);
+
+        tokio::spawn(produce(tx, reader, None));
+
+        assert!(rx.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pipe_feeds_lines_to_the_sink() {
+        let r = root();
+        let target = r.path().join("sink.txt");
+        let cmd = vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "while read line; do echo \"$DECOMPOSE_PROGRAM/$DECOMPOSE_STREAM $line\"; done > {}",
+                target.display()
+            ),
+        ];
+
+        let (tx, rx) = make_channel(DEFAULT_CAPACITY);
+        for line in &["aap", "noot"] {
+            tx.send(line.to_string()).unwrap();
+        }
+        drop(tx);
+
+        pipe(rx, cmd, "prog".to_string(), "stdout").await;
+
+        let content = std::fs::read_to_string(target).unwrap();
+        assert_eq!("prog/stdout aap\nprog/stdout noot\n", content);
+    }
+
+    #[tokio::test]
+    async fn tail_keeps_last_lines() {
+        let (tx, rx) = make_channel(DEFAULT_CAPACITY);
+        let tail = Tail::new(2);
+        tail.follow(rx);
+
+        for line in &["aap", "noot", "mies"] {
+            tx.send(line.to_string()).unwrap();
+        }
+        drop(tx);
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
 
-        tokio::spawn(produce(tx, reader));
+        assert_eq!(vec!["noot", "mies"], tail.lines());
+    }
+
+    #[test]
+    fn token_bucket_refills_at_rate() {
+        let start = tokio::time::Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2);
+
+        assert!(bucket.take(start));
+        assert!(bucket.take(start));
+        assert!(!bucket.take(start));
+
+        let later = start + Duration::from_millis(100);
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_reports_suppressed_lines() {
+        let (sink, mut rx) = make_channel(DEFAULT_CAPACITY);
+        let limited = rate_limited(
+            sink,
+            &config::RateLimit {
+                lines_per_sec: 0.001,
+                burst: 2,
+            },
+            DEFAULT_CAPACITY,
+        );
+
+        for line in &["aap", "noot", "mies", "wim", "zus"] {
+            limited.send(line.to_string()).unwrap();
+        }
+        drop(limited);
 
+        assert_eq!("aap", rx.recv().await.unwrap());
+        assert_eq!("noot", rx.recv().await.unwrap());
+        assert_eq!("[decompose] 3 lines suppressed", rx.recv().await.unwrap());
         assert!(rx.recv().await.is_err());
     }
+
+    #[test]
+    fn renders_json_lines() {
+        let pretty = PrettyJson::default();
+        assert_eq!(
+            Some("INFO  listening port=8080".to_string()),
+            pretty.render(r#"{"level":"info","ts":1600000000,"msg":"listening","port":8080}"#)
+        );
+        assert_eq!(
+            Some("ERROR boom err=\"oops\"".to_string()),
+            pretty.render(r#"{"severity":"error","message":"boom","err":"oops"}"#)
+        );
+        assert_eq!(None, pretty.render("plain text"));
+        assert_eq!(None, pretty.render("[1, 2]"));
+
+        let pretty = PrettyJson {
+            fields: Some(vec!["user".to_string(), "missing".to_string()]),
+        };
+        assert_eq!(
+            Some("WARN  denied user=\"bob\"".to_string()),
+            pretty.render(r#"{"level":"warn","msg":"denied","user":"bob","path":"/"}"#)
+        );
+    }
 }
@@ -0,0 +1,143 @@
+use super::config;
+use super::graph::{Graph, NodeHandle};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// the order the system starts in, without starting anything: each wave starts
+// together, once the programs it waits on are ready
+pub fn plan(sys: &config::System) -> Result<String> {
+    let graph = Graph::from_config(sys)?;
+    let width = graph
+        .all()
+        .map(|h| graph.node(h).name.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for (i, wave) in waves(&graph).iter().enumerate() {
+        let mut waits_on: Vec<&str> = wave
+            .iter()
+            .flat_map(|h| graph.dependencies(*h))
+            .map(|h| graph.node(h).name.as_str())
+            .collect();
+        waits_on.sort_unstable();
+        waits_on.dedup();
+        match waits_on.is_empty() {
+            true => writeln!(out, "wave {}", i + 1)?,
+            false => writeln!(out, "wave {}, once {} ready", i + 1, waits_on.join(", "))?,
+        }
+
+        for h in wave {
+            let prog = graph.node(*h);
+            write!(
+                out,
+                "  {:width$}  ready on {}",
+                prog.name,
+                prog.ready.describe(),
+                width = width
+            )?;
+
+            let mut edges: Vec<String> = graph
+                .dependencies(*h)
+                .map(|d| {
+                    let d = graph.node(d);
+                    format!("{} ({})", d.name, d.ready.describe())
+                })
+                .collect();
+            edges.sort_unstable();
+            if !edges.is_empty() {
+                write!(out, ", after {}", edges.join(", "))?;
+            }
+
+            match &prog.excluded {
+                Some(reason) => write!(out, " [excluded: {}]", reason)?,
+                None if prog.disabled => write!(out, " [disabled]")?,
+                None => (),
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(out)
+}
+
+// programs by how many others have to be ready before them, in config order
+fn waves(graph: &Graph) -> Vec<Vec<NodeHandle>> {
+    let mut levels = HashMap::new();
+    let mut waves: Vec<Vec<NodeHandle>> = Vec::new();
+    for h in graph.all() {
+        let level = level(graph, h, &mut levels);
+        if waves.len() <= level {
+            waves.resize(level + 1, Vec::new());
+        }
+        waves[level].push(h);
+    }
+    waves
+}
+
+// the graph has no cycles, so this ends
+fn level(graph: &Graph, h: NodeHandle, levels: &mut HashMap<NodeHandle, usize>) -> usize {
+    if let Some(level) = levels.get(&h) {
+        return *level;
+    }
+    let dependencies: Vec<NodeHandle> = graph.dependencies(h).collect();
+    let level = dependencies
+        .into_iter()
+        .map(|d| level(graph, d, levels) + 1)
+        .max()
+        .unwrap_or(0);
+    levels.insert(h, level);
+    level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_the_waves() {
+        let toml = r#"
+        [[program]]
+        name = "db"
+        exec = "e"
+        ready = {port = 5432}
+
+        [[program]]
+        name = "cache"
+        exec = "e"
+        ready = {stdout = "listening"}
+
+        [[program]]
+        name = "migrate"
+        exec = "e"
+        ready = {completed = {}}
+        depends = ["db"]
+
+        [[program]]
+        name = "api"
+        exec = "e"
+        depends = ["migrate", "cache"]
+
+        [[program]]
+        name = "debugger"
+        exec = "e"
+        disabled = true
+        depends = ["db"]
+        "#;
+        let sys = config::System::from_toml(toml).unwrap();
+
+        let expected = "\
+wave 1
+  db        ready on port 5432
+  cache     ready on stdout
+wave 2, once db ready
+  migrate   ready on completed, after db (port 5432)
+  debugger  ready on nothing, after db (port 5432) [disabled]
+wave 3, once cache, migrate ready
+  api       ready on nothing, after cache (stdout), migrate (completed)
+";
+        assert_eq!(expected, plan(&sys).unwrap());
+    }
+}
@@ -0,0 +1,398 @@
+extern crate log;
+extern crate nix;
+
+use super::config;
+use super::doctor;
+use std::error::Error;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug, PartialEq)]
+pub struct BusyPort {
+    pub program: String,
+    pub port: u16,
+    pub owner: Option<String>,
+}
+
+impl std::fmt::Display for BusyPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "port {} for {} is already in use",
+            self.port, self.program
+        )?;
+        if let Some(owner) = &self.owner {
+            write!(f, " by {}", owner)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn check_ports(sys: &config::System) -> Result<()> {
+    use config::PortCheck;
+
+    if sys.port_check == PortCheck::Off {
+        return Ok(());
+    }
+
+    let busy = busy_ports(sys);
+    for b in busy.iter() {
+        log::warn!("{}", b);
+    }
+
+    match (sys.port_check, busy.is_empty()) {
+        (PortCheck::Fail, false) => {
+            let msgs: Vec<String> = busy.iter().map(|b| b.to_string()).collect();
+            Err(msgs.join("\n").into())
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn check_disabled_dependencies(sys: &config::System) -> Result<()> {
+    use config::DisabledDependency;
+
+    if sys.disabled_dependency == DisabledDependency::Off {
+        return Ok(());
+    }
+
+    let found = disabled_dependencies(sys);
+    for f in found.iter() {
+        log::warn!("{}", f);
+    }
+
+    match (sys.disabled_dependency, found.is_empty()) {
+        (DisabledDependency::Fail, false) => Err(found.join("\n").into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_requirements(sys: &config::System, outdir: &Path) -> Result<()> {
+    let unmet = unmet_requirements(&sys.requires, outdir);
+    match unmet.is_empty() {
+        true => Ok(()),
+        false => Err(format!("host requirements not met:\n{}", unmet.join("\n")).into()),
+    }
+}
+
+pub fn unmet_requirements(requires: &config::Requirements, outdir: &Path) -> Vec<String> {
+    let mut unmet = Vec::new();
+
+    if let Some(needed) = requires.free_disk_mb {
+        match free_disk_mb(outdir) {
+            Ok(free) if free < needed => unmet.push(format!(
+                "{} MB free disk for {}, needs {} MB",
+                free,
+                outdir.display(),
+                needed
+            )),
+            Ok(_) => (),
+            Err(e) => unmet.push(format!("free disk for {}: {}", outdir.display(), e)),
+        }
+    }
+
+    if let Some(needed) = requires.available_memory_mb {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        match mem_available_kb(meminfo.as_str()).map(|kb| kb / 1024) {
+            Some(available) if available < needed => unmet.push(format!(
+                "{} MB memory available, needs {} MB",
+                available, needed
+            )),
+            Some(_) => (),
+            None => unmet.push("available memory is unknown".to_string()),
+        }
+    }
+
+    for binary in requires.binaries.iter() {
+        match doctor::find_executable(binary) {
+            Some(path) if doctor::is_executable(&path) => (),
+            _ => unmet.push(format!("{} not found in PATH", binary)),
+        }
+    }
+
+    let mut ulimits: Vec<_> = requires.ulimits.iter().collect();
+    ulimits.sort();
+    for (name, needed) in ulimits {
+        match soft_limit(name) {
+            Some(Ok(limit)) if limit < *needed => {
+                unmet.push(format!("ulimit {} is {}, needs {}", name, limit, needed))
+            }
+            Some(Ok(_)) => (),
+            Some(Err(e)) => unmet.push(format!("ulimit {}: {}", name, e)),
+            None => unmet.push(format!("unknown ulimit {}", name)),
+        }
+    }
+
+    unmet
+}
+
+fn free_disk_mb(path: &Path) -> nix::Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64 / (1024 * 1024))
+}
+
+// the MemAvailable line, which counts reclaimable caches as available
+fn mem_available_kb(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse().ok())
+}
+
+// none for names we don't know, unlimited is the largest value there is
+fn soft_limit(name: &str) -> Option<std::io::Result<u64>> {
+    use nix::libc;
+
+    let resource = match name {
+        "nofile" => libc::RLIMIT_NOFILE,
+        "nproc" => libc::RLIMIT_NPROC,
+        "core" => libc::RLIMIT_CORE,
+        "stack" => libc::RLIMIT_STACK,
+        "memlock" => libc::RLIMIT_MEMLOCK,
+        "fsize" => libc::RLIMIT_FSIZE,
+        _ => return None,
+    };
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+        return Some(Err(std::io::Error::last_os_error()));
+    }
+    Some(Ok(limit.rlim_cur))
+}
+
+// disabled programs send started and stopped right away, so whatever depends on
+// them goes ahead without them
+pub fn disabled_dependencies(sys: &config::System) -> Vec<String> {
+    let disabled: Vec<&str> = sys
+        .program
+        .iter()
+        .filter(|p| p.disabled)
+        .map(|p| p.name.as_str())
+        .collect();
+
+    let mut result = Vec::new();
+    for prog in sys.program.iter().filter(|p| !p.disabled) {
+        for dep in prog
+            .depends
+            .iter()
+            .filter(|d| disabled.contains(&d.as_str()))
+        {
+            result.push(format!(
+                "{} depends on {}, which is disabled and counts as ready straight away, \
+                 so {} starts without it",
+                prog.name, dep, prog.name
+            ));
+        }
+    }
+    result
+}
+
+pub fn busy_ports(sys: &config::System) -> Vec<BusyPort> {
+    let mut result = Vec::new();
+    for prog in sys.program.iter().filter(|p| !p.disabled) {
+        for port in prog.ports() {
+            if !is_free(port) {
+                result.push(BusyPort {
+                    program: prog.name.clone(),
+                    port,
+                    owner: port_owner(port),
+                });
+            }
+        }
+    }
+    result
+}
+
+fn is_free(port: u16) -> bool {
+    std::net::TcpListener::bind(("0.0.0.0", port)).is_ok()
+}
+
+// best effort: only finds processes whose file descriptors we are allowed to inspect
+pub fn port_owner(port: u16) -> Option<String> {
+    let inodes: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|f| std::fs::read_to_string(f).ok())
+        .flat_map(|table| listening_inodes(table.as_str(), port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        let fds = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.flatten() {
+            let target = match std::fs::read_link(fd.path()) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            let target = target.to_string_lossy();
+            if inodes.iter().any(|i| target == format!("socket:[{}]", i)) {
+                let comm = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+                return Some(format!("{} (pid {})", comm.trim(), pid));
+            }
+        }
+    }
+    None
+}
+
+// parses /proc/net/tcp style tables
+fn listening_inodes(table: &str, port: u16) -> Vec<String> {
+    const LISTEN: &str = "0A";
+
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local = fields.get(1)?;
+            let state = fields.get(3)?;
+            let inode = fields.get(9)?;
+
+            let p = u16::from_str_radix(local.rsplit(':').next()?, 16).ok()?;
+            if p == port && *state == LISTEN {
+                Some(inode.to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listening_inodes() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 12345 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 67890 1 0000000000000000 20 4 30 10 -1
+   2: 0100007F:1F91 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 11111 1 0000000000000000 100 0 0 10 0
+";
+
+        assert_eq!(vec!["12345"], listening_inodes(table, 8080));
+        assert!(listening_inodes(table, 1234).is_empty());
+    }
+
+    #[test]
+    fn detects_busy_port_and_owner() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let toml = format!(
+            r#"
+            port_check = "fail"
+
+            [[program]]
+            name = "prog"
+            exec = "foo"
+            ready = {{port = {}}}
+            "#,
+            port
+        );
+        let sys = config::System::from_toml(toml.as_str()).unwrap();
+
+        let busy = busy_ports(&sys);
+        assert_eq!(1, busy.len());
+        assert_eq!(port, busy[0].port);
+
+        let owner = busy[0].owner.as_ref().expect("owner");
+        assert!(owner.contains(format!("(pid {})", std::process::id()).as_str()));
+
+        check_ports(&sys).unwrap_err();
+
+        drop(listener);
+        assert!(busy_ports(&sys).is_empty());
+        check_ports(&sys).unwrap();
+    }
+
+    #[test]
+    fn parses_available_memory() {
+        let meminfo = "MemTotal:       16314312 kB\nMemFree:         1021312 kB\nMemAvailable:    8388608 kB\n";
+        assert_eq!(Some(8388608), mem_available_kb(meminfo));
+        assert_eq!(None, mem_available_kb("MemTotal:       16314312 kB\n"));
+    }
+
+    #[test]
+    fn reports_unmet_requirements() {
+        let toml = r#"
+            [requires]
+            binaries = ["sh", "surely-not-installed-anywhere"]
+            ulimits = {nofile = 1, bogus = 1}
+
+            [[program]]
+            name = "prog"
+            exec = "foo"
+            "#;
+        let sys = config::System::from_toml(toml).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let unmet = unmet_requirements(&sys.requires, dir.path());
+        assert_eq!(
+            vec![
+                "surely-not-installed-anywhere not found in PATH",
+                "unknown ulimit bogus"
+            ],
+            unmet
+        );
+
+        let toml = toml.replace("[requires]", "[requires]\nfree_disk_mb = 1000000000");
+        let sys = config::System::from_toml(toml.as_str()).unwrap();
+        let err = check_requirements(&sys, dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("MB free disk for"), "{}", err);
+
+        let sys =
+            config::System::from_toml("[[program]]\nname = \"prog\"\nexec = \"foo\"").unwrap();
+        check_requirements(&sys, dir.path()).unwrap();
+    }
+
+    #[test]
+    fn detects_dependencies_on_disabled_programs() {
+        let toml = r#"
+            disabled_dependency = "fail"
+
+            [[program]]
+            name = "db"
+            exec = "db"
+            disabled = true
+
+            [[program]]
+            name = "api"
+            exec = "api"
+            depends = ["db"]
+
+            [[program]]
+            name = "debugger"
+            exec = "dlv"
+            disabled = true
+            depends = ["db"]
+            "#;
+        let sys = config::System::from_toml(toml).unwrap();
+
+        let found = disabled_dependencies(&sys);
+        assert_eq!(1, found.len());
+        assert!(found[0].starts_with("api depends on db"));
+        check_disabled_dependencies(&sys).unwrap_err();
+
+        let sys = config::System::from_toml(toml.replace("\"fail\"", "\"warn\"").as_str()).unwrap();
+        check_disabled_dependencies(&sys).unwrap();
+    }
+}
@@ -1,58 +1,203 @@
 extern crate tokio;
 
+use super::caps;
 use super::config;
+use super::cores;
+use super::environment;
+use super::error;
+use super::executor;
+use super::exports::{self, Exports};
+use super::fds::{self, Descriptors};
 use super::graph::NodeHandle;
+use super::hooks::{self, HookEvent};
+use super::liveness;
 use super::output;
 use super::readysignals;
+use super::report::Report;
+use super::scratch::ScratchDir;
+use super::seccomp;
+use super::sockets;
 use super::tokio_utils;
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
 pub use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::process;
-use tokio::sync::broadcast;
 pub use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 
 #[derive(Debug, Clone)]
 pub enum Command {
-    Start((NodeHandle, config::Program)),
+    Start((NodeHandle, Arc<config::Program>)),
     Stop(NodeHandle),
+    // every program is ready, or skipped
+    SystemReady,
+    // the executor moved on to another phase
+    Phase(executor::Phase),
 }
 
 #[derive(Debug)]
 pub enum Event {
     Started(NodeHandle),
     Stopped(NodeHandle, Option<ExitStatus>),
+    Failed(NodeHandle, ExitStatus),
     Shutdown,
+    // restarts held by the restart limit may go ahead
+    Resume,
+    // asked for over the control socket
+    Control(Control, NodeHandle),
+    // a liveness probe failed too often, or answers again after that
+    Liveness(NodeHandle, bool),
     Err(tokio::io::Error),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Start,
+    Stop,
+    Restart,
+    // restart along with what depends on it, in dependency order
+    Reload,
+}
+
+impl Control {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Control::Start => "start",
+            Control::Stop => "stop",
+            Control::Restart => "restart",
+            Control::Reload => "reload",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Control> {
+        match s {
+            "start" => Some(Control::Start),
+            "stop" => Some(Control::Stop),
+            "restart" => Some(Control::Restart),
+            "reload" => Some(Control::Reload),
+            _ => None,
+        }
+    }
+}
+
 pub struct ProcessManager {
     rx: mpsc::Receiver<Command>,
     tx: mpsc::Sender<Event>,
-    stop_tx: broadcast::Sender<NodeHandle>,
+    // a stop channel to each program started, for only it to hear
+    stops: HashMap<NodeHandle, oneshot::Sender<()>>,
     output_factory: Box<dyn output::OutputFactory>,
+    output_sink: Option<config::OutputSink>,
+    // lines a reader of program output may fall behind on
+    output_capacity: usize,
+    context: Context,
+}
+
+// the programs that are up, by name, for health and chaos to look at
+#[derive(Clone, Default)]
+pub struct Running {
+    pids: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Running {
+    pub fn add(&self, name: &str, pid: u32) {
+        self.pids.lock().unwrap().insert(name.to_string(), pid);
+    }
+
+    pub fn remove(&self, name: &str) {
+        self.pids.lock().unwrap().remove(name);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pids.lock().unwrap().contains_key(name)
+    }
+
+    // by name, for picking from them the same way every time
+    pub fn pids(&self) -> Vec<(String, u32)> {
+        let mut pids: Vec<(String, u32)> = self
+            .pids
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, pid)| (name.clone(), *pid))
+            .collect();
+        pids.sort();
+        pids
+    }
+}
+
+// what every running program shares, besides its own configuration
+#[derive(Clone)]
+struct Context {
+    event_tx: mpsc::Sender<Event>,
+    hooks: config::Hooks,
+    report: Report,
+    exports: Exports,
     start_timeout: Option<Duration>,
     terminate_timeout: Duration,
+    on_parent_death: config::ParentDeath,
+    cores: Option<std::path::PathBuf>,
+    fds: Descriptors,
+    probes: readysignals::Probes,
+    running: Running,
+    overflow: config::Overflow,
+    print_commands: bool,
 }
 
+// lines of output kept around to report on failure
+const TAIL_LINES: usize = 50;
+// output shown when a program doesn't become ready in time
+const DIAGNOSIS_LINES: usize = 10;
+
 impl ProcessManager {
     pub fn new(
         rx: mpsc::Receiver<Command>,
         tx: mpsc::Sender<Event>,
         sys: &config::System,
         output_factory: Box<dyn output::OutputFactory>,
+        report: Report,
+        cores: Option<std::path::PathBuf>,
     ) -> ProcessManager {
-        let (stop_tx, _) = broadcast::channel(10);
+        let context = Context {
+            event_tx: tx.clone(),
+            hooks: sys.hooks.clone(),
+            report,
+            exports: Exports::new(),
+            start_timeout: sys.start_timeout.map(Duration::from_secs_f64),
+            terminate_timeout: Duration::from_secs_f64(sys.terminate_timeout),
+            on_parent_death: sys.on_parent_death,
+            cores,
+            fds: Descriptors::new(),
+            probes: readysignals::Probes::new(sys.max_concurrent_probes),
+            running: Running::default(),
+            overflow: sys.channels.overflow,
+            print_commands: false,
+        };
         ProcessManager {
             rx,
             tx,
-            stop_tx,
+            stops: HashMap::new(),
             output_factory,
-            start_timeout: sys.start_timeout.map(Duration::from_secs_f64),
-            terminate_timeout: Duration::from_secs_f64(sys.terminate_timeout),
+            output_sink: sys.output.clone(),
+            output_capacity: sys.channels.output,
+            context,
         }
     }
 
-    pub async fn run(mut self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // print what exactly is run, for every program and build
+    pub fn with_print_commands(mut self, print_commands: bool) -> ProcessManager {
+        self.context.print_commands = print_commands;
+        self
+    }
+
+    // the programs that are up, shared with whatever wants to mess with them
+    pub fn running(&self) -> Running {
+        self.context.running.clone()
+    }
+
+    pub async fn run(mut self) -> error::Result<()> {
         loop {
             let c = tokio::select! {
                 _ = tokio_utils::wait_for_signal(tokio_utils::SignalKind::interrupt()) => {
@@ -65,6 +210,17 @@ impl ProcessManager {
                     self.send(Event::Shutdown).await;
                     true
                 },
+                // the terminal going away, which programs in their own session don't hear
+                _ = tokio_utils::wait_for_signal(tokio_utils::SignalKind::hangup()) => {
+                    log::debug!("received SIGHUP");
+                    self.send(Event::Shutdown).await;
+                    true
+                },
+                _ = tokio_utils::wait_for_signal(tokio_utils::SignalKind::user_defined1()) => {
+                    log::debug!("received SIGUSR1");
+                    self.send(Event::Resume).await;
+                    true
+                },
                 msg = self.rx.recv() => {
                     match msg {
                         Some(Command::Start((h, p))) => {
@@ -76,6 +232,11 @@ impl ProcessManager {
                             self.stop(h).await;
                             true
                         },
+                        Some(Command::SystemReady) => {
+                            self.system_ready();
+                            true
+                        },
+                        Some(Command::Phase(_)) => true,
                         None => {
                             log::debug!("channel closed");
                             false
@@ -91,39 +252,88 @@ impl ProcessManager {
         Ok(())
     }
 
-    async fn start(&mut self, handle: NodeHandle, prog: config::Program) {
+    async fn start(&mut self, handle: NodeHandle, prog: Arc<config::Program>) {
         log::debug!("starting program {}", prog.name);
 
-        let (stdout, stderr) = (
-            self.output_factory.stdout(&prog),
-            self.output_factory.stderr(&prog),
-        );
+        // quiet programs still get output channels, for the ready signals to monitor
+        let mut dedicated: Option<Box<dyn output::OutputFactory>> = if prog.quiet {
+            Some(Box::new(output::NullOutputFactory::new(
+                self.output_capacity,
+            )))
+        } else {
+            match prog.output.as_ref().or(self.output_sink.as_ref()) {
+                Some(config::OutputSink::Pipe(cmd)) => Some(Box::new(
+                    output::PipeOutputFactory::new(cmd.clone(), self.output_capacity),
+                )),
+                None => None,
+            }
+        };
+        let factory = match dedicated.as_mut() {
+            Some(f) => f.as_mut(),
+            None => self.output_factory.as_mut(),
+        };
+        let (mut stdout, mut stderr) = (factory.stdout(&prog), factory.stderr(&prog));
+
+        if let Some(limit) = &prog.log_rate_limit {
+            stdout = output::rate_limited(stdout, limit, self.output_capacity);
+            stderr = output::rate_limited(stderr, limit, self.output_capacity);
+        }
+
+        // a restart replaces the channel of the run before it
+        let (stop_tx, stop_rx) = oneshot::channel();
+        self.stops.insert(handle, stop_tx);
 
         tokio::spawn(run_program(
             handle,
             prog,
             stdout,
             stderr,
-            self.tx.clone(),
-            self.stop_tx.subscribe(),
-            self.start_timeout,
-            self.terminate_timeout,
+            stop_rx,
+            self.context.clone(),
         ));
     }
 
+    fn system_ready(&self) {
+        log::info!("system ready");
+        eprintln!("==> system ready, all programs are up");
+        hooks::fire_system_ready(&self.context.hooks);
+    }
+
     async fn stop(&mut self, handle: NodeHandle) {
-        if let Err(e) = self.stop_tx.send(handle) {
-            log::warn!("failed to forward stop command: {:?}", e);
+        // gone already if it was never started, or it ended on its own
+        let sent = self.stops.remove(&handle).map(|tx| tx.send(()));
+        if sent != Some(Ok(())) {
+            log::debug!("no program left to forward stop command to");
         }
     }
 
     async fn send(&mut self, msg: Event) {
-        if let Err(e) = self.tx.send(msg).await {
-            log::debug!("channel error: {}", e);
+        if let Err(e) = send(&mut self.tx, msg, self.context.overflow).await {
+            log::debug!("{}", e);
         }
     }
 }
 
+// into the channel, waiting for room in it or failing when it's full
+pub async fn send<T>(
+    tx: &mut mpsc::Sender<T>,
+    x: T,
+    overflow: config::Overflow,
+) -> tokio_utils::Result<()> {
+    use mpsc::error::TrySendError;
+
+    match overflow {
+        config::Overflow::Block => tx
+            .send(x)
+            .await
+            .map_err(|_| tokio_utils::make_err("channel closed")),
+        config::Overflow::Fail => tx.try_send(x).map_err(|e| match e {
+            TrySendError::Full(_) => tokio_utils::make_err("channel full"),
+            TrySendError::Closed(_) => tokio_utils::make_err("channel closed"),
+        }),
+    }
+}
+
 #[derive(Debug, Clone)]
 struct ProcessInfo {
     pub name: String,
@@ -148,27 +358,22 @@ async fn with_timeout<R>(
 
 async fn run_program(
     handle: NodeHandle,
-    prog: config::Program,
+    prog: Arc<config::Program>,
     stdout: output::Sender,
     stderr: output::Sender,
-    event_tx: mpsc::Sender<Event>,
-    stop_rx: broadcast::Receiver<NodeHandle>,
-    start_timeout: Option<std::time::Duration>,
-    terminate_timeout: std::time::Duration,
+    stop_rx: oneshot::Receiver<()>,
+    context: Context,
 ) {
-    let mut tx = event_tx.clone();
-    if let Err(e) = do_run_program(
-        handle,
-        prog,
-        stdout,
-        stderr,
-        event_tx,
-        stop_rx,
-        start_timeout,
-        terminate_timeout,
-    )
-    .await
-    {
+    let mut tx = context.event_tx.clone();
+    let report = context.report.clone();
+    let name = prog.name.clone();
+
+    let tail = output::Tail::new(TAIL_LINES);
+    tail.follow(stdout.subscribe());
+    tail.follow(stderr.subscribe());
+
+    if let Err(e) = do_run_program(handle, prog, stdout, stderr, stop_rx, context, &tail).await {
+        report.failed(name.as_str(), e.to_string().as_str(), tail.lines());
         if let Err(e) = tx.send(Event::Err(e)).await {
             log::warn!("{}", e);
         }
@@ -177,51 +382,152 @@ async fn run_program(
 
 async fn do_run_program(
     handle: NodeHandle,
-    prog: config::Program,
+    mut prog: Arc<config::Program>,
     stdout: output::Sender,
     stderr: output::Sender,
-    mut event_tx: mpsc::Sender<Event>,
-    stop_rx: broadcast::Receiver<NodeHandle>,
-    start_timeout: Option<std::time::Duration>,
-    terminate_timeout: std::time::Duration,
+    stop_rx: oneshot::Receiver<()>,
+    context: Context,
+    tail: &output::Tail,
 ) -> tokio_utils::Result<()> {
     // bit of a monster function, but actually easiest to reason about to think of
     // a straight line of progression
 
     use config::ReadySignal;
 
+    let Context {
+        mut event_tx,
+        hooks: system_hooks,
+        report,
+        exports,
+        start_timeout,
+        terminate_timeout,
+        on_parent_death,
+        cores,
+        fds,
+        probes,
+        running,
+        overflow,
+        print_commands,
+    } = context;
+
     if prog.disabled {
         log::info!("{} disabled, not starting", prog.name);
-        event_tx
-            .send(Event::Started(handle))
-            .await
-            .map_err(tokio_utils::make_err)?;
-        event_tx
-            .send(Event::Stopped(handle, None))
-            .await
-            .map_err(tokio_utils::make_err)?;
+        report.skipped(prog.name.as_str());
+        send(&mut event_tx, Event::Started(handle), overflow).await?;
+        send(&mut event_tx, Event::Stopped(handle, None), overflow).await?;
 
         return Ok(());
     }
 
+    if let config::ProgramType::Subsystem = prog.kind {
+        // its programs are all ready, being its dependencies
+        log::info!("{} ready", prog.name);
+        report.ready(prog.name.as_str());
+        send(&mut event_tx, Event::Started(handle), overflow).await?;
+        send(&mut event_tx, Event::Stopped(handle, None), overflow).await?;
+
+        return Ok(());
+    }
+
+    exports.resolve(&mut prog)?;
+
+    let scratch = match prog.scratch_dir {
+        true => Some(ScratchDir::create(Arc::make_mut(&mut prog))?),
+        false => None,
+    };
+
+    if prog.create_cwd {
+        create_cwd(&prog)?;
+    }
+
+    if !prog.build.is_empty() {
+        log::info!("{} building", prog.name);
+        if print_commands {
+            eprintln!("==> {} build runs {}", prog.name, trace(&prog, &prog.build));
+        }
+        build(&prog, &stdout, &stderr, on_parent_death).await?;
+    }
+
     log::debug!("{} creating child process", prog.name);
-    let (mut proc, info) = create_child_process(&prog)?;
+    if print_commands {
+        let command: Vec<String> = std::iter::once(&prog.exec)
+            .chain(&prog.args)
+            .cloned()
+            .collect();
+        eprintln!("==> {} runs {}", prog.name, trace(&prog, &command));
+    }
+    // there before the program can write to it
+    let fifo = match &prog.ready {
+        ReadySignal::Fifo(path) => Some(readysignals::open_fifo(path)?),
+        _ => None,
+    };
+    let mut opened = fds.open(&prog)?;
+    // the child gets the write end, ours has to go for the read end to see it close
+    let notification = match prog.ready {
+        ReadySignal::NotificationFd(fd) => {
+            let (r, w) = fds::pipe()?;
+            opened.fds.push((w.as_raw_fd(), fd));
+            Some((r, w))
+        }
+        _ => None,
+    };
+    let (mut proc, info) =
+        create_child_process(&prog, on_parent_death, cores.is_some(), opened.fds.clone()).map_err(
+            |source| {
+                tokio_utils::make_err(error::Error::Spawn {
+                    program: prog.name.clone(),
+                    source,
+                })
+            },
+        )?;
+    drop(opened);
+    let notification = notification.map(|(r, _)| r);
+    // a piped stdin stays open for as long as the program runs, it never sees eof
+    let _stdin = proc.stdin.take();
 
     log::info!("{} started", info);
+    report.started(info.name.as_str());
+
+    let hooks = [&prog.hooks, &system_hooks];
+    let fire = |event, status| hooks::fire(&hooks, event, info.name.as_str(), info.pid, status);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let on_exit = |status: ExitStatus| {
+        // exits we asked for are not crashes, whatever the status says
+        if !status.success() && !stopped.load(Ordering::SeqCst) {
+            fire(HookEvent::Crash, Some(status));
+            let message = format!("exited, {}", status);
+            report.failed(info.name.as_str(), message.as_str(), tail.lines());
+            if let Some(dir) = &cores {
+                cores::collect(dir, &prog, info.pid, &status);
+            }
+        } else if let Some(scratch) = &scratch {
+            scratch.succeeded();
+        }
+        fire(HookEvent::Stop, Some(status));
+    };
 
     log::debug!("{} hooking up stop command", info);
     tokio::spawn(wait_for_stop_command(
-        handle,
         info.clone(),
-        terminate_timeout,
+        prog.stop_steps(terminate_timeout.as_secs_f64()),
         stop_rx,
+        stopped.clone(),
     ));
 
     log::debug!("{} hooking up output pipes", info);
     let monitor_out = stdout.subscribe();
     let monitor_err = stderr.subscribe();
-    tokio::spawn(output::produce(stdout, proc.stdout.take()));
-    tokio::spawn(output::produce(stderr, proc.stderr.take()));
+    let captures = capture_exports(&prog, &stdout, &stderr);
+    tokio::spawn(output::produce(
+        stdout,
+        proc.stdout.take(),
+        prog.max_line_length,
+    ));
+    tokio::spawn(output::produce(
+        stderr,
+        proc.stderr.take(),
+        prog.max_line_length,
+    ));
 
     log::debug!("{} waiting for ready signal", info);
 
@@ -229,72 +535,125 @@ async fn do_run_program(
         // special case
         let status = with_timeout(readysignals::completed(proc), start_timeout).await?;
         if status.success() {
+            export(&info, captures, &exports, start_timeout).await?;
             log::info!("{} ready", info);
-            event_tx
-                .send(Event::Started(handle))
-                .await
-                .map_err(tokio_utils::make_err)?;
+            fire(HookEvent::Ready, None);
+            report.ready(info.name.as_str());
+            send(&mut event_tx, Event::Started(handle), overflow).await?;
             log::info!("{} stopped", info);
+            on_exit(status);
 
-            event_tx
-                .send(Event::Stopped(handle, Some(status)))
-                .await
-                .map_err(tokio_utils::make_err)?;
+            send(
+                &mut event_tx,
+                Event::Stopped(handle, Some(status)),
+                overflow,
+            )
+            .await?;
             return Ok(());
         } else {
-            let msg = format!("{} not ready", info);
-            log::error!("{}", msg);
-            return Err(tokio_utils::make_err(msg));
+            log::warn!("{} failed, {}", info, status);
+            on_exit(status);
+            send(&mut event_tx, Event::Failed(handle, status), overflow).await?;
+            return Ok(());
         }
     }
 
-    let rs = match prog.ready {
-        ReadySignal::Nothing => with_timeout(readysignals::nothing(), start_timeout).await?,
+    let (signal, stable_for) = match &prog.ready {
+        ReadySignal::Stable(signal, s) => (signal.as_ref(), Duration::from_secs_f64(*s)),
+        signal => (signal, Duration::default()),
+    };
+    let attempts = readysignals::Attempts::new(info.name.as_str(), prog.ready_debug, stable_for);
+    let rs = match signal {
+        ReadySignal::Nothing => with_timeout(readysignals::nothing(), start_timeout).await,
         ReadySignal::Manual => {
             // not setting timeout on manual trigger
-            readysignals::manual(info.name.as_str()).await?
+            readysignals::manual(info.name.as_str()).await
         }
         ReadySignal::Timer(s) => {
-            let dur = Duration::from_secs_f64(s);
+            let dur = Duration::from_secs_f64(*s);
             // not setting timeout on already time-based signal
-            readysignals::timer(dur).await?
+            readysignals::timer(dur).await
+        }
+        ReadySignal::Port(port) => {
+            with_timeout(readysignals::port(*port, &probes, &attempts), start_timeout).await
         }
-        ReadySignal::Port(port) => with_timeout(readysignals::port(port), start_timeout).await?,
         ReadySignal::Stdout(re) => {
             with_timeout(
-                readysignals::output(monitor_out, re.as_str()),
+                readysignals::output(monitor_out, re.as_str(), &attempts),
                 start_timeout,
             )
-            .await?
+            .await
         }
         ReadySignal::Stderr(re) => {
             with_timeout(
-                readysignals::output(monitor_err, re.as_str()),
+                readysignals::output(monitor_err, re.as_str(), &attempts),
                 start_timeout,
             )
-            .await?
+            .await
         }
         ReadySignal::Healthcheck(endpoint) => {
             with_timeout(
-                readysignals::healthcheck(
-                    endpoint.host.as_str(),
-                    endpoint.port,
-                    endpoint.path.as_str(),
-                ),
+                readysignals::healthcheck(endpoint, &probes, &attempts),
                 start_timeout,
             )
-            .await?
+            .await
         }
-        ReadySignal::Completed => panic!("not handled here"),
+        ReadySignal::Plugin(plugin) => {
+            with_timeout(
+                readysignals::plugin(plugin, info.name.as_str(), info.pid, &probes, &attempts),
+                start_timeout,
+            )
+            .await
+        }
+        ReadySignal::Exec(exec) => {
+            with_timeout(
+                readysignals::exec(exec, info.name.as_str(), info.pid, &probes, &attempts),
+                start_timeout,
+            )
+            .await
+        }
+        ReadySignal::Udp(udp) => {
+            with_timeout(readysignals::udp(udp, &probes, &attempts), start_timeout).await
+        }
+        ReadySignal::Dns(dns) => {
+            with_timeout(readysignals::dns(dns, &probes, &attempts), start_timeout).await
+        }
+        ReadySignal::NotificationFd(_) => {
+            let pipe = notification.expect("notification pipe");
+            with_timeout(readysignals::notification(pipe), start_timeout).await
+        }
+        ReadySignal::Fifo(_) => {
+            let fifo = fifo.expect("fifo");
+            with_timeout(readysignals::fifo(fifo), start_timeout).await
+        }
+        ReadySignal::Completed | ReadySignal::Stable(..) => panic!("not handled here"),
+    };
+    let rs = match rs {
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            let exited = futures::FutureExt::now_or_never(&mut proc);
+            let after = start_timeout.expect("timed out with a timeout");
+            eprintln!(
+                "{}",
+                diagnosis(&info, after, exited, &attempts, tail.lines())
+            );
+            let timeout = error::Error::ReadyTimeout {
+                program: info.to_string(),
+                after,
+            };
+            log::error!("{}", timeout);
+            return Err(tokio_utils::make_err(timeout));
+        }
+        rs => rs?,
     };
 
     match rs {
         true => {
+            export(&info, captures, &exports, start_timeout).await?;
             log::info!("{} ready", info);
-            event_tx
-                .send(Event::Started(handle))
-                .await
-                .expect("event channel error");
+            fire(HookEvent::Ready, None);
+            report.ready(info.name.as_str());
+            running.add(info.name.as_str(), info.pid);
+            send(&mut event_tx, Event::Started(handle), overflow).await?;
         }
         false => {
             let msg = format!("{} not ready", info);
@@ -303,48 +662,208 @@ async fn do_run_program(
         }
     }
 
+    let probing = prog.liveness.clone().map(|section| {
+        log::debug!("{} probing liveness", info);
+        let check = liveness::Check {
+            handle,
+            name: info.name.clone(),
+            pid: info.pid,
+            probes: probes.clone(),
+            events: event_tx.clone(),
+            overflow,
+            stopped: stopped.clone(),
+        };
+        let (check, abort) = futures::future::abortable(liveness::check(section, check));
+        tokio::spawn(check);
+        abort
+    });
+
     log::debug!("{} waiting for completion or stop signal", info);
 
-    let output = proc.wait_with_output().await?;
+    let output = proc.wait_with_output().await;
+    if let Some(probing) = probing {
+        probing.abort();
+    }
+    running.remove(info.name.as_str());
+    let output = output?;
     log::info!("{} stopped, {}", info, output.status);
+    on_exit(output.status);
 
-    event_tx
-        .send(Event::Stopped(handle, Some(output.status)))
-        .await
-        .expect("event channel error");
+    send(
+        &mut event_tx,
+        Event::Stopped(handle, Some(output.status)),
+        overflow,
+    )
+    .await?;
+
+    Ok(())
+}
+
+// why a program didn't become ready: whether it is still there, what the probes
+// ran into and what it said last
+fn diagnosis(
+    info: &ProcessInfo,
+    after: Duration,
+    exited: Option<std::io::Result<ExitStatus>>,
+    attempts: &readysignals::Attempts,
+    output: Vec<String>,
+) -> String {
+    let mut lines = vec![format!("==> {} not ready after {:?}", info, after)];
+    lines.push(match exited {
+        None => "    process: still running".to_string(),
+        Some(Ok(status)) => format!("    process: exited, {}", status),
+        Some(Err(e)) => format!("    process: unknown, {}", e),
+    });
+    let summary = attempts.summary();
+    if !summary.is_empty() {
+        lines.push(format!("    probes: {}", summary));
+    }
+    let skip = output.len().saturating_sub(DIAGNOSIS_LINES);
+    match output.len() {
+        0 => lines.push("    no output".to_string()),
+        n => {
+            lines.push(format!("    last {} lines of output:", n - skip));
+            lines.extend(
+                output
+                    .into_iter()
+                    .skip(skip)
+                    .map(|l| format!("      {}", l)),
+            );
+        }
+    }
+    lines.join("\n")
+}
+
+type Capture = (
+    String,
+    tokio::task::JoinHandle<tokio_utils::Result<Option<String>>>,
+);
+
+// starts looking for the exported values, before any output is produced
+fn capture_exports(
+    prog: &config::Program,
+    stdout: &output::Sender,
+    stderr: &output::Sender,
+) -> Vec<Capture> {
+    prog.exports
+        .iter()
+        .map(|(key, export)| {
+            let (rx, re) = match export {
+                config::Export::FromStdout(re) => (stdout.subscribe(), re.clone()),
+                config::Export::FromStderr(re) => (stderr.subscribe(), re.clone()),
+            };
+            let capture = tokio::spawn(async move { exports::capture(rx, re.as_str()).await });
+            (key.clone(), capture)
+        })
+        .collect()
+}
+
+// a program only counts as ready once its dependents can use everything it exports
+async fn export(
+    info: &ProcessInfo,
+    captures: Vec<Capture>,
+    exports: &Exports,
+    timeout: Option<Duration>,
+) -> tokio_utils::Result<()> {
+    for (key, capture) in captures {
+        let value = with_timeout(
+            async { capture.await.map_err(tokio_utils::make_err)? },
+            timeout,
+        )
+        .await?
+        .ok_or_else(|| tokio_utils::make_err(format!("{} did not export {}", info, key)))?;
 
+        log::debug!("{} exports {}={}", info, key, value);
+        exports.set(info.name.as_str(), key.as_str(), value);
+    }
     Ok(())
 }
 
 async fn wait_for_stop_command(
-    handle: NodeHandle,
     info: ProcessInfo,
-    timeout: std::time::Duration,
-    mut stop_rx: broadcast::Receiver<NodeHandle>,
+    steps: Vec<config::StopStep>,
+    stop_rx: oneshot::Receiver<()>,
+    stopped: Arc<AtomicBool>,
 ) -> tokio_utils::Result<()> {
-    while let Ok(h) = stop_rx
-        .recv()
-        .await
-        .map_err(|e| log::warn!("{}, some programs might fail to terminate", e))
-    {
-        if h == handle {
-            log::debug!("{} received stop command", info);
-            terminate(info.pid)?;
-
-            tokio::time::delay_for(timeout).await;
-
-            if is_alive(info.pid) {
-                log::warn!("{} failed to terminate, killing", info);
-                kill(info.pid)?;
+    // the sender is dropped when the process manager goes, or on a restart
+    if stop_rx.await.is_err() {
+        log::debug!("{} stop channel closed", info);
+        return Ok(());
+    }
+
+    log::debug!("{} received stop command", info);
+    stopped.store(true, Ordering::SeqCst);
+
+    for (i, step) in steps.iter().enumerate() {
+        if i > 0 {
+            if !is_alive(info.pid) {
+                return Ok(());
             }
-            break;
+            log::warn!("{} still running, sending {}", info, step.signal.0);
         }
+        signal(info.pid, step.signal.0)?;
+        tokio::time::delay_for(Duration::from_secs_f64(step.timeout)).await;
+    }
+
+    if is_alive(info.pid) {
+        log::warn!("{} failed to terminate, killing", info);
+        signal(info.pid, nix::sys::signal::Signal::SIGKILL)?;
+    }
+    Ok(())
+}
+
+async fn build(
+    prog: &config::Program,
+    stdout: &output::Sender,
+    stderr: &output::Sender,
+    on_parent_death: config::ParentDeath,
+) -> tokio_utils::Result<()> {
+    let current_dir = std::fs::canonicalize(prog.cwd.clone())?;
+
+    let mut cmd = process::Command::new(&prog.build[0]);
+    cmd.args(&prog.build[1..]);
+    set_env(&mut cmd, prog);
+    cmd.current_dir(current_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    die_with_parent(&mut cmd, on_parent_death);
+    let mut child = cmd.spawn()?;
+
+    tokio::spawn(output::produce(
+        stdout.clone(),
+        child.stdout.take(),
+        prog.max_line_length,
+    ));
+    tokio::spawn(output::produce(
+        stderr.clone(),
+        child.stderr.take(),
+        prog.max_line_length,
+    ));
+
+    let status = child.await?;
+    if !status.success() {
+        let msg = format!("{} build failed, {}", prog.name, status);
+        log::error!("{}", msg);
+        return Err(tokio_utils::make_err(msg));
     }
     Ok(())
 }
 
+fn create_cwd(prog: &config::Program) -> tokio_utils::Result<()> {
+    std::fs::create_dir_all(&prog.cwd).map_err(|e| {
+        tokio_utils::make_err(format!(
+            "{} can't create cwd {}: {}",
+            prog.name, prog.cwd, e
+        ))
+    })
+}
+
 fn create_child_process(
     prog: &config::Program,
+    on_parent_death: config::ParentDeath,
+    core_dumps: bool,
+    fds: Vec<(std::os::unix::io::RawFd, std::os::unix::io::RawFd)>,
 ) -> tokio_utils::Result<(tokio::process::Child, ProcessInfo)> {
     use std::str::FromStr;
 
@@ -358,14 +877,60 @@ fn create_child_process(
         current_dir
     );
 
-    let child = process::Command::new(executable)
-        .args(&prog.args)
-        .envs(&prog.env)
-        .current_dir(current_dir)
+    let mut cmd = match prog.sockets.is_empty() {
+        true => process::Command::new(executable),
+        false => sockets::command(&executable, prog),
+    };
+    cmd.args(&prog.args);
+    fds::remap(&mut cmd, fds);
+    set_env(&mut cmd, prog);
+    cmd.current_dir(current_dir)
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()?;
+        .kill_on_drop(true);
+    die_with_parent(&mut cmd, on_parent_death);
+    if let Some(umask) = prog.umask {
+        set_umask(&mut cmd, umask);
+    }
+    if prog.new_session == Some(true) {
+        new_session(&mut cmd);
+    }
+    if !prog.cpus.is_empty() {
+        set_affinity(&mut cmd, &prog.cpus)
+            .map_err(|e| tokio_utils::make_err(format!("{} cpus: {}", prog.name, e)))?;
+    }
+    if core_dumps {
+        cores::raise_limit(&mut cmd);
+    }
+    if !prog.drop_caps.is_empty() {
+        let kept = caps::kept(prog).map_err(tokio_utils::make_err)?;
+        caps::restrict(&mut cmd, kept);
+    }
+    match &prog.stdin {
+        config::Stdin::Null => {
+            cmd.stdin(std::process::Stdio::null());
+        }
+        config::Stdin::Inherit => {
+            cmd.stdin(std::process::Stdio::inherit());
+        }
+        config::Stdin::Pipe => {
+            cmd.stdin(std::process::Stdio::piped());
+        }
+        config::Stdin::File(_) => {
+            let path = prog.stdin_file().expect("stdin file");
+            let file = std::fs::File::open(&path).map_err(|e| {
+                tokio_utils::make_err(format!("{} stdin {}: {}", prog.name, path.display(), e))
+            })?;
+            cmd.stdin(file);
+        }
+    }
+    // last, the filter applies to anything done before exec after it
+    if let Some(profile) = &prog.seccomp {
+        let filter = seccomp::filter(profile)
+            .map_err(|e| tokio_utils::make_err(format!("{} seccomp: {}", prog.name, e)))?;
+        seccomp::apply(&mut cmd, filter);
+    }
+    let child = cmd.spawn()?;
     let info = ProcessInfo {
         name: prog.name.clone(),
         pid: child.id(),
@@ -374,32 +939,163 @@ fn create_child_process(
     Ok((child, info))
 }
 
-fn terminate(pid: u32) -> tokio_utils::Result<()> {
-    use nix::sys::signal as nix_signal;
+// the command as it will run: resolved executable, arguments, working directory
+// and what is different about the environment from decompose's own
+fn trace(prog: &config::Program, command: &[String]) -> String {
+    let cwd = std::fs::canonicalize(&prog.cwd).unwrap_or_else(|_| prog.cwd.clone().into());
+    format!(
+        "{} with args {:?} in {}, env {}",
+        resolve(prog, command[0].as_str()).display(),
+        &command[1..],
+        cwd.display(),
+        match env_diff(prog, std::env::vars()) {
+            diff if diff.is_empty() => "unchanged".to_string(),
+            diff => diff.join(" "),
+        }
+    )
+}
 
-    let pid = nix::unistd::Pid::from_raw(pid as i32);
-    let sig = nix_signal::Signal::SIGTERM;
+// like the child will find it: a path relative to the working directory, or a
+// name looked up in PATH
+fn resolve(prog: &config::Program, exec: &str) -> std::path::PathBuf {
+    if exec.contains('/') {
+        return std::fs::canonicalize(exec).unwrap_or_else(|_| exec.into());
+    }
+    let path = match prog.env.get("PATH") {
+        Some(path) => Some(path.clone()),
+        None => std::env::var("PATH").ok(),
+    };
+    path.iter()
+        .flat_map(|path| std::env::split_paths(path).collect::<Vec<_>>())
+        .map(|dir| dir.join(exec))
+        .find(|candidate| candidate.is_file())
+        .unwrap_or_else(|| exec.into())
+}
+
+// +NAME=value for added variables, ~NAME=value for changed ones and -NAME for
+// those filtered out, by name
+fn env_diff(prog: &config::Program, own: impl Iterator<Item = (String, String)>) -> Vec<String> {
+    use std::collections::BTreeMap;
+
+    let own: BTreeMap<String, String> = own.collect();
+    let child = environment::resolve(prog, own.clone().into_iter());
 
-    nix_signal::kill(pid, sig).map_err(tokio_utils::make_err)
+    let mut diff: BTreeMap<&String, String> = own
+        .keys()
+        .filter(|k| !child.contains_key(*k))
+        .map(|k| (k, format!("-{}", k)))
+        .collect();
+    for (k, v) in child.iter() {
+        match own.get(k) {
+            None => diff.insert(k, format!("+{}={}", k, v)),
+            Some(o) if o != v => diff.insert(k, format!("~{}={}", k, v)),
+            Some(_) => None,
+        };
+    }
+    diff.into_values().collect()
 }
 
-fn kill(pid: u32) -> tokio_utils::Result<()> {
-    use nix::sys::signal as nix_signal;
+// the filtered inherited environment, then the program's own
+fn set_env(cmd: &mut process::Command, prog: &config::Program) {
+    if prog.filters_env() {
+        cmd.env_clear()
+            .envs(std::env::vars_os().filter(|(k, _)| match k.to_str() {
+                Some(k) => prog.inherits(k),
+                None => false,
+            }));
+    }
+    cmd.envs(&prog.env);
+}
 
-    let pid = nix::unistd::Pid::from_raw(pid as i32);
-    let sig = nix_signal::Signal::SIGKILL;
+// kill_on_drop does nothing when decompose is killed outright, so have the kernel
+// signal the child instead. note the signal is tied to the forking thread, which is
+// fine as long as the runtime is single threaded
+#[cfg(target_os = "linux")]
+fn die_with_parent(cmd: &mut process::Command, on_parent_death: config::ParentDeath) {
+    use nix::libc;
+    use nix::sys::signal::Signal;
+
+    let sig = match on_parent_death {
+        config::ParentDeath::None => return,
+        config::ParentDeath::Term => Signal::SIGTERM,
+        config::ParentDeath::Kill => Signal::SIGKILL,
+    };
+    let parent = nix::unistd::getpid();
+
+    let set_pdeathsig = move || {
+        // only async-signal-safe calls in here, we are between fork and exec
+        if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, sig as libc::c_ulong) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // the parent might have died before the prctl took effect
+        if nix::unistd::getppid() != parent {
+            unsafe { libc::raise(sig as libc::c_int) };
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(set_pdeathsig);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn die_with_parent(_cmd: &mut process::Command, _on_parent_death: config::ParentDeath) {}
+
+fn set_umask(cmd: &mut process::Command, umask: config::Umask) {
+    use nix::sys::stat::{umask as set, Mode};
+
+    let mode = Mode::from_bits_truncate(umask.0 as nix::libc::mode_t);
+    unsafe {
+        cmd.pre_exec(move || {
+            set(mode);
+            Ok(())
+        });
+    }
+}
+
+// out of decompose's session and process group, without a controlling terminal
+fn new_session(cmd: &mut process::Command) {
+    use nix::libc;
 
-    nix_signal::kill(pid, sig).map_err(tokio_utils::make_err)
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+// pinned in the child before exec, so every thread it starts is pinned too
+fn set_affinity(cmd: &mut process::Command, cpus: &[usize]) -> nix::Result<()> {
+    use nix::sched::{sched_setaffinity, CpuSet};
+
+    let mut set = CpuSet::new();
+    for cpu in cpus {
+        set.set(*cpu)?;
+    }
+    unsafe {
+        cmd.pre_exec(move || {
+            sched_setaffinity(nix::unistd::Pid::from_raw(0), &set).map_err(std::io::Error::other)
+        });
+    }
+    Ok(())
+}
+
+fn signal(pid: u32, sig: nix::sys::signal::Signal) -> tokio_utils::Result<()> {
+    let pid = nix::unistd::Pid::from_raw(pid as i32);
+    nix::sys::signal::kill(pid, sig).map_err(tokio_utils::make_err)
 }
 
 fn is_alive(pid: u32) -> bool {
     use nix::sys::wait;
 
     let pid = nix::unistd::Pid::from_raw(pid as i32);
-    match wait::waitpid(pid, Some(wait::WaitPidFlag::WNOHANG)) {
-        Ok(wait::WaitStatus::StillAlive) => true,
-        _ => false,
-    }
+    matches!(
+        wait::waitpid(pid, Some(wait::WaitPidFlag::WNOHANG)),
+        Ok(wait::WaitStatus::StillAlive)
+    )
 }
 
 #[cfg(test)]
@@ -416,4 +1112,278 @@ mod tests {
         let fmt = format!("{}", proc);
         assert_eq!("catname:123", fmt.as_str());
     }
+
+    #[test]
+    fn diagnoses_not_ready() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let info = ProcessInfo {
+            name: "api".to_string(),
+            pid: 123,
+        };
+        let output: Vec<String> = (1..=12).map(|i| format!("line {}", i)).collect();
+        let exited = diagnosis(
+            &info,
+            Duration::from_secs(5),
+            Some(Ok(ExitStatus::from_raw(256))),
+            &readysignals::Attempts::default(),
+            output,
+        );
+        let expected = "==> api:123 not ready after 5s
+    process: exited, exit status: 1
+    last 10 lines of output:
+      line 3";
+        assert!(exited.starts_with(expected), "{}", exited);
+        assert!(exited.ends_with("      line 12"), "{}", exited);
+
+        let attempts = readysignals::Attempts::default();
+        let running = diagnosis(
+            &info,
+            Duration::from_millis(1500),
+            None,
+            &attempts,
+            Vec::new(),
+        );
+        assert_eq!(
+            "==> api:123 not ready after 1.5s\n    process: still running\n    no output",
+            running
+        );
+    }
+
+    fn make_prog(build: &str) -> config::Program {
+        let toml = format!(
+            r#"
+            [[program]]
+            name = "prog"
+            exec = "e"
+            build = {}
+            "#,
+            build
+        );
+        let sys = config::System::from_toml(toml.as_str()).expect("sys");
+        sys.program[0].clone()
+    }
+
+    #[test]
+    fn traces_environment_changes() {
+        let mut prog = make_prog("[]");
+        prog.env_deny = vec!["AWS_*".to_string()];
+        prog.env = [("HOME", "/tmp"), ("PORT", "8080"), ("USER", "me")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let own = [
+            ("AWS_SECRET", "x"),
+            ("HOME", "/root"),
+            ("TERM", "xterm"),
+            ("USER", "me"),
+        ];
+        let own = own.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+
+        assert_eq!(
+            vec!["-AWS_SECRET", "~HOME=/tmp", "+PORT=8080"],
+            env_diff(&prog, own)
+        );
+    }
+
+    #[test]
+    fn resolves_executables_like_the_child() {
+        let mut prog = make_prog("[]");
+        prog.env
+            .insert("PATH".to_string(), "/nonexistent:/bin".to_string());
+        assert_eq!(std::path::Path::new("/bin/sh"), resolve(&prog, "sh"));
+        assert_eq!(std::path::Path::new("nope"), resolve(&prog, "nope"));
+    }
+
+    #[tokio::test]
+    async fn umask_is_applied() {
+        let mut prog = make_prog("[]");
+        prog.exec = "/bin/sh".to_string();
+        prog.args = vec!["-c".to_string(), "umask".to_string()];
+        prog.umask = Some(config::Umask(0o027));
+
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        assert_eq!("0027", String::from_utf8_lossy(&output.stdout).trim());
+    }
+
+    #[tokio::test]
+    async fn cpus_are_pinned() {
+        let mut prog = make_prog("[]");
+        prog.exec = "/bin/sh".to_string();
+        prog.args = vec![
+            "-c".to_string(),
+            "grep Cpus_allowed_list /proc/self/status".to_string(),
+        ];
+        prog.cpus = vec![0];
+
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        assert_eq!(
+            "Cpus_allowed_list:\t0",
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+
+        prog.cpus = vec![100_000];
+        assert!(create_child_process(&prog, config::ParentDeath::Term, false, Vec::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn stdin_from_file() {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut input, b"aap\nnoot\n").unwrap();
+
+        let mut prog = make_prog("[]");
+        prog.exec = "/bin/cat".to_string();
+        prog.stdin = config::Stdin::File(input.path().to_string_lossy().to_string());
+
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        assert_eq!("aap\nnoot\n", String::from_utf8_lossy(&output.stdout));
+
+        prog.stdin = config::Stdin::File("/no/such/file".to_string());
+        assert!(create_child_process(&prog, config::ParentDeath::Term, false, Vec::new()).is_err());
+
+        // relative to the program's cwd, not ours
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("fixtures")).unwrap();
+        std::fs::write(dir.path().join("fixtures/input.jsonl"), "mies\n").unwrap();
+        prog.cwd = dir.path().to_string_lossy().to_string();
+        prog.stdin = config::Stdin::File("fixtures/input.jsonl".to_string());
+
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        assert_eq!("mies\n", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[tokio::test]
+    async fn new_session_leads_its_own() {
+        let mut prog = make_prog("[]");
+        prog.exec = "/bin/sh".to_string();
+        prog.args = vec![
+            "-c".to_string(),
+            "echo $$ $(cut -d' ' -f6 /proc/$$/stat)".to_string(),
+        ];
+        let session = |prog: &config::Program| {
+            let (proc, _) =
+                create_child_process(prog, config::ParentDeath::Term, false, Vec::new())
+                    .expect("spawn");
+            async {
+                let output = proc.wait_with_output().await.expect("wait");
+                let out = String::from_utf8_lossy(&output.stdout).to_string();
+                let ids: Vec<String> = out.split_whitespace().map(String::from).collect();
+                ids[0] == ids[1]
+            }
+        };
+
+        prog.new_session = Some(false);
+        assert!(!session(&prog).await);
+        prog.new_session = Some(true);
+        assert!(session(&prog).await);
+    }
+
+    #[tokio::test]
+    async fn stdin_null_or_pipe() {
+        let mut prog = make_prog("[]");
+        prog.exec = "/bin/cat".to_string();
+
+        // null by default, cat is done right away
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+
+        // a pipe keeps it going until closed
+        prog.stdin = config::Stdin::Pipe;
+        let (mut proc, info) =
+            create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+                .expect("spawn");
+        let stdin = proc.stdin.take().expect("piped stdin");
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        assert!(is_alive(info.pid));
+        drop(stdin);
+        let output = proc.wait_with_output().await.expect("wait");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn cwd_is_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut prog = make_prog("[]");
+        prog.cwd = dir.path().join("var/run").to_string_lossy().to_string();
+
+        create_cwd(&prog).expect("create");
+        assert!(dir.path().join("var/run").is_dir());
+        // already there is fine too
+        create_cwd(&prog).expect("create");
+    }
+
+    #[tokio::test]
+    async fn inherited_env_is_filtered() {
+        let mut prog = make_prog("[]");
+        prog.exec = "/usr/bin/env".to_string();
+        prog.env_allow = Some(vec!["PATH".to_string(), "HOME".to_string()]);
+        prog.env_deny = vec!["HOME".to_string()];
+        prog.env.insert("HOME".to_string(), "/home/dev".to_string());
+
+        let (proc, _) = create_child_process(&prog, config::ParentDeath::Term, false, Vec::new())
+            .expect("spawn");
+        let output = proc.wait_with_output().await.expect("wait");
+        let mut vars: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(String::from)
+            .collect();
+        vars.sort();
+        assert_eq!(2, vars.len());
+        assert_eq!("HOME=/home/dev", vars[0]);
+        assert!(vars[1].starts_with("PATH="));
+    }
+
+    #[tokio::test]
+    async fn build_succeeds() {
+        let prog = make_prog(r#"["/bin/sh", "-c", "echo building"]"#);
+        let (stdout, mut rx) = tokio::sync::broadcast::channel(10);
+        let (stderr, _) = tokio::sync::broadcast::channel(10);
+
+        build(&prog, &stdout, &stderr, config::ParentDeath::Term)
+            .await
+            .expect("build");
+        assert_eq!("building", rx.recv().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn build_failure_is_an_error() {
+        let prog = make_prog(r#"["/bin/sh", "-c", "exit 1"]"#);
+        let (stdout, _) = tokio::sync::broadcast::channel(10);
+        let (stderr, _) = tokio::sync::broadcast::channel(10);
+
+        build(&prog, &stdout, &stderr, config::ParentDeath::Term)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn send_blocks_or_fails_on_a_full_channel() {
+        use config::Overflow;
+
+        let (mut tx, mut rx) = mpsc::channel(1);
+        send(&mut tx, 1, Overflow::Fail).await.unwrap();
+        let err = send(&mut tx, 2, Overflow::Fail).await.unwrap_err();
+        assert_eq!("channel full", err.to_string());
+
+        let blocked = tokio::spawn(async move { send(&mut tx, 3, Overflow::Block).await });
+        assert_eq!(Some(1), rx.recv().await);
+        assert_eq!(Some(3), rx.recv().await);
+        blocked.await.unwrap().unwrap();
+
+        let (mut tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let err = send(&mut tx, 1, Overflow::Block).await.unwrap_err();
+        assert_eq!("channel closed", err.to_string());
+    }
 }
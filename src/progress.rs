@@ -0,0 +1,191 @@
+extern crate nix;
+
+use super::config;
+use super::graph::{Graph, NodeHandle};
+use super::process::{mpsc, Command, Event};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// a live "ready 7/12: waiting on db (port 5432)" line on the terminal, while the
+// system starts
+#[derive(Clone)]
+pub struct Progress {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    total: usize,
+    ready: usize,
+    names: HashMap<NodeHandle, String>,
+    // started but not ready yet, in the order they were started, with their signal
+    waiting: Vec<(NodeHandle, String)>,
+    done: bool,
+}
+
+impl Progress {
+    pub fn new(sys: &config::System) -> Result<Progress> {
+        let graph = Graph::from_config(sys)?;
+        let names = graph
+            .all()
+            .map(|h| (h, graph.node(h).name.clone()))
+            .collect();
+
+        Ok(Progress {
+            state: Arc::new(Mutex::new(State {
+                total: sys.program.len(),
+                ready: 0,
+                names,
+                waiting: Vec::new(),
+                done: false,
+            })),
+        })
+    }
+
+    // only on a terminal, anything else would get the control characters
+    pub fn wanted() -> bool {
+        nix::unistd::isatty(nix::libc::STDERR_FILENO).unwrap_or(false)
+    }
+
+    // pass commands on from rx to tx, following each
+    pub async fn commands(self, rx: mpsc::Receiver<Command>, tx: mpsc::Sender<Command>) {
+        self.forward(rx, tx, State::command).await
+    }
+
+    // pass events on from rx to tx, following each
+    pub async fn events(self, rx: mpsc::Receiver<Event>, tx: mpsc::Sender<Event>) {
+        self.forward(rx, tx, State::event).await
+    }
+
+    async fn forward<T>(
+        self,
+        mut rx: mpsc::Receiver<T>,
+        mut tx: mpsc::Sender<T>,
+        follow: fn(&mut State, &T),
+    ) {
+        while let Some(x) = rx.recv().await {
+            self.update(|state| follow(state, &x));
+            if tx.send(x).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn update<F: FnOnce(&mut State)>(&self, f: F) {
+        let mut state = self.state.lock().expect("progress lock");
+        if state.done {
+            return;
+        }
+        f(&mut state);
+
+        let mut stderr = std::io::stderr();
+        // back to the start of the line and clear it, the last one stays cleared
+        let _ = match state.line() {
+            Some(line) => write!(stderr, "\r\x1b[K{}", line),
+            None => write!(stderr, "\r\x1b[K"),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+impl State {
+    fn command(&mut self, cmd: &Command) {
+        match cmd {
+            Command::Start((h, prog)) => self.waiting.push((*h, prog.ready.describe())),
+            Command::Stop(_) | Command::Phase(_) => (),
+            Command::SystemReady => self.done = true,
+        }
+    }
+
+    fn event(&mut self, event: &Event) {
+        match event {
+            Event::Started(h) => {
+                self.ready += 1;
+                self.waiting.retain(|(w, _)| w != h);
+            }
+            Event::Stopped(h, _) | Event::Failed(h, _) => self.waiting.retain(|(w, _)| w != h),
+            Event::Shutdown | Event::Err(_) => self.done = true,
+            Event::Resume | Event::Control(..) | Event::Liveness(..) => (),
+        }
+    }
+
+    // nothing once startup is over
+    fn line(&self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+        let mut line = format!("ready {}/{}", self.ready, self.total);
+        if !self.waiting.is_empty() {
+            let waiting: Vec<String> = self
+                .waiting
+                .iter()
+                .map(|(h, signal)| format!("{} ({})", self.names[h], signal))
+                .collect();
+            line = format!("{}: waiting on {}", line, waiting.join(", "));
+        }
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::process::ExitStatus;
+    use std::os::unix::process::ExitStatusExt;
+
+    const TOML: &str = r#"
+        [[program]]
+        name = "db"
+        exec = "e"
+        ready = {port = 5432}
+
+        [[program]]
+        name = "cache"
+        exec = "e"
+        ready = {stdout = "listening"}
+
+        [[program]]
+        name = "api"
+        exec = "e"
+        depends = ["db", "cache"]
+        "#;
+
+    #[test]
+    fn follows_startup() {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let graph = Graph::from_config(&sys).unwrap();
+        let progress = Progress::new(&sys).unwrap();
+        let mut state = progress.state.lock().unwrap();
+        let start = |name: &str| {
+            let h = graph.find(name).unwrap();
+            Command::Start((h, graph.program(h)))
+        };
+        let db = graph.find("db").unwrap();
+        let cache = graph.find("cache").unwrap();
+
+        assert_eq!(Some("ready 0/3".to_string()), state.line());
+
+        state.command(&start("db"));
+        state.command(&start("cache"));
+        assert_eq!(
+            Some("ready 0/3: waiting on db (port 5432), cache (stdout)".to_string()),
+            state.line()
+        );
+
+        state.event(&Event::Started(db));
+        assert_eq!(
+            Some("ready 1/3: waiting on cache (stdout)".to_string()),
+            state.line()
+        );
+
+        state.event(&Event::Failed(cache, ExitStatus::from_raw(256)));
+        assert_eq!(Some("ready 1/3".to_string()), state.line());
+
+        state.command(&Command::SystemReady);
+        assert_eq!(None, state.line());
+    }
+}
@@ -3,13 +3,134 @@ extern crate log;
 extern crate nix;
 extern crate regex;
 extern crate reqwest;
+extern crate serde_json;
 extern crate tokio;
 
+use super::config::{Dns, Endpoint, Exec, JsonExpectation, Plugin, Udp};
 use super::output::Receiver;
 use super::tokio_utils::make_err;
 
 type Result = std::result::Result<bool, tokio::io::Error>;
 
+// limits how many port, healthcheck, udp, dns, plugin and exec probes run at once. waiting probes
+// get their turn in order, so no program is starved
+#[derive(Clone, Default)]
+pub struct Probes {
+    permits: Option<std::sync::Arc<tokio::sync::Semaphore>>,
+}
+
+impl Probes {
+    pub fn new(max: Option<usize>) -> Probes {
+        Probes {
+            permits: max.map(|n| std::sync::Arc::new(tokio::sync::Semaphore::new(n))),
+        }
+    }
+
+    pub async fn run<F: std::future::Future>(&self, probe: F) -> F::Output {
+        match &self.permits {
+            Some(permits) => {
+                let _permit = permits.acquire().await;
+                probe.await
+            }
+            None => probe.await,
+        }
+    }
+}
+
+// what came of the probes for one program, counted by outcome in order of first
+// occurrence, to tell why it never became ready
+#[derive(Clone, Default)]
+pub struct Attempts {
+    outcomes: std::sync::Arc<std::sync::Mutex<Vec<(String, usize)>>>,
+    name: String,
+    // log every attempt at info instead of debug
+    trace: bool,
+    // how long the probes have to keep succeeding, and since when they do
+    stable_for: std::time::Duration,
+    up_since: std::sync::Arc<std::sync::Mutex<Option<std::time::Instant>>>,
+}
+
+impl Attempts {
+    pub fn new(name: &str, trace: bool, stable_for: std::time::Duration) -> Attempts {
+        Attempts {
+            name: name.to_string(),
+            trace,
+            stable_for,
+            ..Default::default()
+        }
+    }
+
+    fn log(&self, message: std::fmt::Arguments) {
+        let level = match self.trace {
+            true => log::Level::Info,
+            false => log::Level::Debug,
+        };
+        log::log!(level, "{} ready probe {}", self.name, message);
+    }
+
+    // a failed attempt, and how long until the next one
+    fn record<S: Into<String>>(&self, outcome: S, retry: std::time::Duration) {
+        let outcome = outcome.into();
+        if let Some(since) = self.up_since.lock().expect("attempts lock").take() {
+            self.log(format_args!("down again after {:?}", since.elapsed()));
+        }
+        let mut outcomes = self.outcomes.lock().expect("attempts lock");
+        let attempt = outcomes.iter().map(|(_, count)| count).sum::<usize>() + 1;
+        self.log(format_args!(
+            "#{}: {}, retrying in {:?}",
+            attempt, outcome, retry
+        ));
+        match outcomes.iter_mut().find(|(o, _)| *o == outcome) {
+            Some((_, count)) => *count += 1,
+            None => outcomes.push((outcome, 1)),
+        }
+    }
+
+    // a successful attempt, true once they have been succeeding for long enough
+    fn holds(&self) -> bool {
+        let mut up_since = self.up_since.lock().expect("attempts lock");
+        if up_since.is_none() && self.stable_for > std::time::Duration::default() {
+            self.log(format_args!("up, has to hold for {:?}", self.stable_for));
+        }
+        let up = up_since
+            .get_or_insert_with(std::time::Instant::now)
+            .elapsed();
+        up >= self.stable_for
+    }
+
+    fn succeeded(&self) {
+        let outcomes = self.outcomes.lock().expect("attempts lock");
+        let attempt = outcomes.iter().map(|(_, count)| count).sum::<usize>() + 1;
+        self.log(format_args!("#{}: ready", attempt));
+    }
+
+    // a line of output looked at, for the stdout and stderr signals
+    fn saw(&self, line: &str, matched: bool) {
+        match matched {
+            true => self.log(format_args!("line {:?} matches", line)),
+            false => self.log(format_args!("line {:?} doesn't match", line)),
+        }
+    }
+
+    // like "120x connection refused, 3x HTTP 500", empty if nothing was probed
+    pub fn summary(&self) -> String {
+        let outcomes = self.outcomes.lock().expect("attempts lock");
+        outcomes
+            .iter()
+            .map(|(outcome, count)| format!("{}x {}", count, outcome))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+fn connect_failure(e: &std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::ConnectionRefused => "connection refused".to_string(),
+        std::io::ErrorKind::TimedOut => "timed out".to_string(),
+        _ => e.to_string(),
+    }
+}
+
 pub async fn nothing() -> Result {
     Ok(true)
 }
@@ -33,19 +154,32 @@ pub async fn timer(dur: std::time::Duration) -> Result {
     Ok(true)
 }
 
-pub async fn port(port: u16) -> Result {
-    host_and_port("127.0.0.1", port).await
+pub async fn port(port: u16, probes: &Probes, attempts: &Attempts) -> Result {
+    host_and_port("127.0.0.1", port, probes, attempts).await
 }
 
-async fn host_and_port(host: &str, port: u16) -> Result {
+// a single connect, for probes that are not waiting for ready
+pub async fn port_once(port: u16) -> std::result::Result<(), String> {
+    tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .map(|_| ())
+        .map_err(|e| connect_failure(&e))
+}
+
+async fn host_and_port(host: &str, port: u16, probes: &Probes, attempts: &Attempts) -> Result {
     use tokio::net::TcpStream;
 
     let interval = std::time::Duration::from_millis(1);
     let address = format!("{}:{}", host, port);
 
     loop {
-        if TcpStream::connect(&address).await.is_ok() {
-            return Ok(true);
+        match probes.run(TcpStream::connect(&address)).await {
+            Ok(_) if attempts.holds() => {
+                attempts.succeeded();
+                return Ok(true);
+            }
+            Ok(_) => (),
+            Err(e) => attempts.record(connect_failure(&e), interval),
         }
         tokio::time::delay_for(interval).await;
     }
@@ -57,7 +191,7 @@ pub async fn completed(
     proc.wait_with_output().await.map(|o| o.status)
 }
 
-pub async fn output(mut rx: Receiver, re: &str) -> Result {
+pub async fn output(mut rx: Receiver, re: &str, attempts: &Attempts) -> Result {
     let re = regex::Regex::new(re).map_err(make_err)?;
 
     loop {
@@ -68,7 +202,9 @@ pub async fn output(mut rx: Receiver, re: &str) -> Result {
                 let rn: &[_] = &['\r', '\n'];
                 let line = line.trim_end_matches(rn);
 
-                if re.is_match(line) {
+                let matched = re.is_match(line);
+                attempts.saw(line, matched);
+                if matched {
                     return Ok(true);
                 }
             }
@@ -76,20 +212,362 @@ pub async fn output(mut rx: Receiver, re: &str) -> Result {
     }
 }
 
-pub async fn healthcheck(host: &str, port: u16, path: &str) -> Result {
+// a newline on the notification pipe, false if the program closes it without
+pub async fn notification(pipe: std::fs::File) -> Result {
+    use tokio::io::AsyncReadExt;
+
+    let mut pipe = tokio::fs::File::from_std(pipe);
+    let mut buf = [0; 64];
+    loop {
+        let n = pipe.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(false);
+        }
+        if buf[..n].contains(&b'\n') {
+            return Ok(true);
+        }
+    }
+}
+
+// creates the fifo unless it is already there, and opens it without waiting for a writer
+pub fn open_fifo(path: &str) -> std::io::Result<std::fs::File> {
+    use nix::sys::stat::Mode;
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+    match std::fs::metadata(path) {
+        Ok(m) if m.file_type().is_fifo() => (),
+        Ok(_) => {
+            let msg = format!("{} exists and is not a fifo", path);
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, msg));
+        }
+        Err(_) => nix::unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+            .map_err(|e| std::io::Error::other(format!("mkfifo {}: {}", path, e)))?,
+    }
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NONBLOCK)
+        .open(path)
+}
+
+// anything written to the fifo. without a writer reads are empty, with one
+// that hasn't written yet they would block
+pub async fn fifo(mut fifo: std::fs::File) -> Result {
+    use std::io::Read;
+
     let interval = std::time::Duration::from_millis(1);
-    let endpoint = format!("http://{}:{}{}", host, port, path);
+    let mut buf = [0; 64];
+    loop {
+        match fifo.read(&mut buf) {
+            Ok(n) if n > 0 => return Ok(true),
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+pub async fn udp(udp: &Udp, probes: &Probes, attempts: &Attempts) -> Result {
+    let interval = std::time::Duration::from_millis(1);
+    let address = format!("{}:{}", udp.host, udp.port);
+
+    loop {
+        match probes.run(udp_probe(udp, address.as_str())).await {
+            Ok(()) if attempts.holds() => {
+                attempts.succeeded();
+                return Ok(true);
+            }
+            Ok(()) => (),
+            Err(outcome) => attempts.record(outcome, interval),
+        }
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+// a closed port shows up as a refused receive, through the icmp port unreachable
+// coming back. silence means someone took the datagram
+async fn udp_probe(udp: &Udp, address: &str) -> std::result::Result<(), String> {
+    use tokio::net::UdpSocket;
+
+    let local = match udp.host.contains(':') {
+        true => "[::]:0",
+        false => "0.0.0.0:0",
+    };
+    let mut socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+    socket
+        .connect(address)
+        .await
+        .map_err(|e| connect_failure(&e))?;
+    socket
+        .send(udp.payload.as_bytes())
+        .await
+        .map_err(|e| connect_failure(&e))?;
+
+    let mut buf = [0; 1];
+    let timeout = std::time::Duration::from_secs_f64(udp.timeout);
+    match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(connect_failure(&e)),
+        Err(_) if udp.expect_reply => Err("no reply".to_string()),
+        Err(_) => Ok(()),
+    }
+}
+
+pub async fn dns(dns: &Dns, probes: &Probes, attempts: &Attempts) -> Result {
+    let interval = std::time::Duration::from_millis(10);
+
     loop {
-        let response = reqwest::get(endpoint.as_str()).await;
-        if let Ok(r) = response {
-            if r.status().is_success() {
+        let probe = async {
+            match &dns.server {
+                Some(server) => resolves_at(dns.name.as_str(), server.as_str()).await,
+                None => match tokio::net::lookup_host((dns.name.as_str(), 0)).await {
+                    Ok(mut addrs) => addrs.next().map(|_| ()).ok_or("no address".to_string()),
+                    Err(e) => Err(e.to_string()),
+                },
+            }
+        };
+        match probes.run(probe).await {
+            Ok(()) if attempts.holds() => {
+                attempts.succeeded();
                 return Ok(true);
             }
+            Ok(()) => (),
+            Err(outcome) => attempts.record(outcome, interval),
         }
         tokio::time::delay_for(interval).await;
     }
 }
 
+// ask the server for the a record of name, over udp. resolved means at least one
+// answer came back
+async fn resolves_at(name: &str, server: &str) -> std::result::Result<(), String> {
+    use tokio::net::UdpSocket;
+
+    let query = dns_query(name);
+    let local = match server.starts_with('[') {
+        true => "[::]:0",
+        false => "0.0.0.0:0",
+    };
+    let mut socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+    socket
+        .connect(server)
+        .await
+        .map_err(|e| connect_failure(&e))?;
+    socket.send(&query).await.map_err(|e| connect_failure(&e))?;
+
+    let mut buf = [0; 512];
+    let timeout = std::time::Duration::from_millis(500);
+    match tokio::time::timeout(timeout, socket.recv(&mut buf)).await {
+        Ok(Ok(n)) if dns_answered(&query, &buf[..n]) => Ok(()),
+        Ok(Ok(_)) => Err("no answer".to_string()),
+        Ok(Err(e)) => Err(connect_failure(&e)),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+const DNS_ID: [u8; 2] = [0xdc, 0x05];
+
+// a recursive query for the a record of name
+fn dns_query(name: &str) -> Vec<u8> {
+    let mut query = DNS_ID.to_vec();
+    // recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    // root, type a, class in
+    query.extend_from_slice(&[0, 0, 1, 0, 1]);
+    query
+}
+
+// a response to our query, without error and with answers
+fn dns_answered(query: &[u8], response: &[u8]) -> bool {
+    response.len() >= 12
+        && response[..2] == query[..2]
+        && response[2] & 0x80 != 0
+        && response[3] & 0x0f == 0
+        && u16::from_be_bytes([response[6], response[7]]) > 0
+}
+
+pub async fn healthcheck(endpoint: &Endpoint, probes: &Probes, attempts: &Attempts) -> Result {
+    let interval = std::time::Duration::from_millis(1);
+    let http = Http::new(endpoint)?;
+
+    loop {
+        match probes.run(http.probe(endpoint)).await {
+            Ok(()) if attempts.holds() => {
+                attempts.succeeded();
+                return Ok(true);
+            }
+            Ok(()) => (),
+            Err(outcome) => attempts.record(outcome, interval),
+        }
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+// the client and request line for an endpoint, set up once for all its probes
+pub struct Http {
+    client: reqwest::Client,
+    method: reqwest::Method,
+    url: String,
+}
+
+impl Http {
+    pub fn new(endpoint: &Endpoint) -> std::result::Result<Http, tokio::io::Error> {
+        let scheme = if endpoint.tls { "https" } else { "http" };
+        let url = format!(
+            "{}://{}:{}{}",
+            scheme, endpoint.host, endpoint.port, endpoint.path
+        );
+        let method = reqwest::Method::from_bytes(endpoint.method.to_uppercase().as_bytes())
+            .map_err(make_err)?;
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(endpoint.insecure)
+            .build()
+            .map_err(make_err)?;
+        Ok(Http {
+            client,
+            method,
+            url,
+        })
+    }
+
+    pub async fn probe(&self, endpoint: &Endpoint) -> std::result::Result<(), String> {
+        let mut request = self.client.request(self.method.clone(), self.url.as_str());
+        for (key, value) in endpoint.headers.iter() {
+            request = request.header(key.as_str(), value.as_str());
+        }
+        if let Some(body) = &endpoint.body {
+            request = request.body(body.clone());
+        }
+
+        match request.send().await {
+            Ok(r) => {
+                let code = r.status().as_u16();
+                if !status_accepted(code, endpoint) {
+                    Err(format!("HTTP {}", code))
+                } else if !body_matches(r, endpoint.expect_json.as_ref()).await {
+                    Err(format!("HTTP {}, body not matching", code))
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) if e.is_timeout() => Err("timed out".to_string()),
+            Err(e) if e.is_connect() => Err("connection refused".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+fn status_accepted(code: u16, endpoint: &Endpoint) -> bool {
+    if endpoint.status.is_empty() {
+        (200..300).contains(&code)
+    } else {
+        endpoint.status.iter().any(|r| r.contains(code))
+    }
+}
+
+async fn body_matches(response: reqwest::Response, expect: Option<&JsonExpectation>) -> bool {
+    match expect {
+        None => true,
+        Some(expect) => match response.text().await {
+            Ok(body) => json_matches(body.as_str(), expect),
+            Err(_) => false,
+        },
+    }
+}
+
+fn json_matches(body: &str, expect: &JsonExpectation) -> bool {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.pointer(expect.pointer.as_str()).cloned())
+        .map(|v| v == expect.equals)
+        .unwrap_or(false)
+}
+
+pub async fn plugin(
+    plugin: &Plugin,
+    name: &str,
+    pid: u32,
+    probes: &Probes,
+    attempts: &Attempts,
+) -> Result {
+    let mut command = tokio::process::Command::new(&plugin.exec);
+    command.args(&plugin.args);
+    let interval = std::time::Duration::from_secs_f64(plugin.interval);
+    until_success(command, "plugin", name, pid, interval, probes, attempts).await
+}
+
+pub async fn exec(
+    exec: &Exec,
+    name: &str,
+    pid: u32,
+    probes: &Probes,
+    attempts: &Attempts,
+) -> Result {
+    let command = exec_command(exec);
+    let interval = std::time::Duration::from_secs_f64(exec.interval);
+    until_success(command, "exec", name, pid, interval, probes, attempts).await
+}
+
+pub fn exec_command(exec: &Exec) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(&exec.command[0]);
+    command.args(&exec.command[1..]);
+    command
+}
+
+// runs the command again after every failure, until it exits 0
+async fn until_success(
+    mut command: tokio::process::Command,
+    kind: &str,
+    name: &str,
+    pid: u32,
+    interval: std::time::Duration,
+    probes: &Probes,
+    attempts: &Attempts,
+) -> Result {
+    loop {
+        match probes.run(run_once(&mut command, kind, name, pid)).await? {
+            Err(outcome) => attempts.record(outcome, interval),
+            Ok(()) if attempts.holds() => {
+                attempts.succeeded();
+                return Ok(true);
+            }
+            Ok(()) => (),
+        }
+
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+// runs the command to completion, the outer error is failing to run it at all and the
+// inner one it exiting with anything but 0
+pub async fn run_once(
+    command: &mut tokio::process::Command,
+    kind: &str,
+    name: &str,
+    pid: u32,
+) -> std::result::Result<std::result::Result<(), String>, tokio::io::Error> {
+    let output = command
+        .env("DECOMPOSE_PROGRAM", name)
+        .env("DECOMPOSE_PID", pid.to_string())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        log::debug!("{} {}: {}", name, kind, line);
+    }
+    match output.status.success() {
+        true => Ok(Ok(())),
+        false => Ok(Err(format!("{} {}", kind, output.status))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tokio;
@@ -107,10 +585,189 @@ mod tests {
         // cheating on unit test rules: is opening a port okay?
         let _listener = std::net::TcpListener::bind("127.0.0.1:9092").expect("open 9292");
 
-        let result = port(9092).await.expect("port");
+        let result = port(9092, &Probes::default(), &Attempts::default())
+            .await
+            .expect("port");
         assert!(result);
     }
 
+    #[tokio::test]
+    async fn attempts_are_counted() {
+        let port = {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap().port()
+        };
+        let attempts = Attempts::default();
+        assert_eq!("", attempts.summary());
+
+        let probes = Probes::default();
+        let probe = super::port(port, &probes, &attempts);
+        let timeout = std::time::Duration::from_millis(20);
+        assert!(tokio::time::timeout(timeout, probe).await.is_err());
+
+        let summary = attempts.summary();
+        assert!(summary.ends_with("x connection refused"), "{}", summary);
+        assert!(!summary.starts_with("0x"), "{}", summary);
+
+        attempts.record("HTTP 500", std::time::Duration::from_millis(1));
+        attempts.record("HTTP 500", std::time::Duration::from_millis(1));
+        assert!(attempts.summary().ends_with(", 2x HTTP 500"));
+    }
+
+    #[tokio::test]
+    async fn stable_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stable_for = std::time::Duration::from_millis(50);
+
+        let start = std::time::Instant::now();
+        let attempts = Attempts::new("p", false, stable_for);
+        assert!(super::port(port, &Probes::default(), &attempts)
+            .await
+            .expect("port"));
+        assert!(start.elapsed() >= stable_for);
+
+        // going down in between starts the wait over
+        attempts.holds();
+        attempts.record("connection refused", std::time::Duration::from_millis(1));
+        assert!(!attempts.holds());
+    }
+
+    fn udp_check(port: u16, expect_reply: bool) -> Udp {
+        Udp {
+            port,
+            host: "127.0.0.1".to_string(),
+            payload: "ping".to_string(),
+            expect_reply,
+            timeout: 0.05,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        assert!(udp(
+            &udp_check(port, false),
+            &Probes::default(),
+            &Attempts::default()
+        )
+        .await
+        .expect("udp"));
+        assert_eq!(
+            Err("no reply".to_string()),
+            udp_probe(&udp_check(port, true), &format!("127.0.0.1:{}", port)).await
+        );
+
+        let echo = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = echo.local_addr().unwrap().port();
+        let echo = std::thread::spawn(move || {
+            let mut buf = [0; 16];
+            let (n, from) = echo.recv_from(&mut buf).unwrap();
+            echo.send_to(&buf[..n], from).unwrap();
+        });
+        assert!(udp(
+            &udp_check(port, true),
+            &Probes::default(),
+            &Attempts::default()
+        )
+        .await
+        .expect("udp"));
+        echo.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_udp_refused() {
+        let port = {
+            let closed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            closed.local_addr().unwrap().port()
+        };
+        assert_eq!(
+            Err("connection refused".to_string()),
+            udp_probe(&udp_check(port, false), &format!("127.0.0.1:{}", port)).await
+        );
+    }
+
+    // a server answering one query with the given rcode and answer count
+    fn dns_server(rcode: u8, answers: u16) -> (String, std::thread::JoinHandle<()>) {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (n, from) = server.recv_from(&mut buf).unwrap();
+            let mut response = buf[..n].to_vec();
+            response[2] |= 0x80;
+            response[3] = rcode;
+            response[6..8].copy_from_slice(&answers.to_be_bytes());
+            server.send_to(&response, from).unwrap();
+        });
+        (address, handle)
+    }
+
+    #[tokio::test]
+    async fn test_dns() {
+        let (server, handle) = dns_server(0, 1);
+        assert!(resolves_at("minio.local", server.as_str()).await.is_ok());
+        handle.join().unwrap();
+
+        let (server, handle) = dns_server(3, 0);
+        assert_eq!(
+            Err("no answer".to_string()),
+            resolves_at("minio.local", server.as_str()).await
+        );
+        handle.join().unwrap();
+
+        let check = Dns {
+            name: "localhost".to_string(),
+            server: None,
+        };
+        assert!(dns(&check, &Probes::default(), &Attempts::default())
+            .await
+            .expect("dns"));
+    }
+
+    #[test]
+    fn test_dns_query() {
+        let query = dns_query("minio.local.");
+        assert_eq!(b"\x05minio\x05local\x00", &query[12..25]);
+        assert_eq!(&[0, 1, 0, 1], &query[25..]);
+    }
+
+    #[tokio::test]
+    async fn test_notification() {
+        use std::io::Write;
+
+        let (r, mut w) = super::super::fds::pipe().unwrap();
+        w.write_all(b"ready\n").unwrap();
+        assert!(notification(r).await.expect("notification"));
+
+        let (r, w) = super::super::fds::pipe().unwrap();
+        drop(w);
+        assert!(!notification(r).await.expect("notification"));
+    }
+
+    #[tokio::test]
+    async fn test_fifo() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready.fifo");
+        let path = path.to_str().unwrap();
+
+        let reader = open_fifo(path).unwrap();
+        // again, as on a restart
+        let reader = open_fifo(path).map(|_| reader).unwrap();
+
+        let mut writer = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        writer.write_all(b"x").unwrap();
+        assert!(fifo(reader).await.expect("fifo"));
+
+        let file = dir.path().join("plain");
+        std::fs::write(&file, "").unwrap();
+        assert!(open_fifo(file.to_str().unwrap()).is_err());
+    }
+
     #[tokio::test]
     async fn test_output_good() {
         let (tx, rx) = tokio::sync::broadcast::channel(10);
@@ -120,7 +777,9 @@ mod tests {
         }
         drop(tx);
 
-        let result = output(rx, "^program:[0-9]+.*$").await.expect("re");
+        let result = output(rx, "^program:[0-9]+.*$", &Attempts::default())
+            .await
+            .expect("re");
         assert!(result);
     }
 
@@ -133,10 +792,112 @@ mod tests {
         }
         drop(tx);
 
-        let result = output(rx, "^program:[0-9]+.*$").await.expect("re");
+        let result = output(rx, "^program:[0-9]+.*$", &Attempts::default())
+            .await
+            .expect("re");
         assert!(!result);
     }
 
+    #[test]
+    fn test_json_matches() {
+        let expect = JsonExpectation {
+            pointer: "/status".to_string(),
+            equals: serde_json::Value::String("UP".to_string()),
+        };
+
+        assert!(json_matches(r#"{"status": "UP"}"#, &expect));
+        assert!(!json_matches(r#"{"status": "STARTING"}"#, &expect));
+        assert!(!json_matches(r#"{"health": "UP"}"#, &expect));
+        assert!(!json_matches("UP", &expect));
+
+        let nested = JsonExpectation {
+            pointer: "/components/db/ready".to_string(),
+            equals: serde_json::Value::Bool(true),
+        };
+        assert!(json_matches(
+            r#"{"components": {"db": {"ready": true}}}"#,
+            &nested
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_plugin() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+
+        // not ready the first time round, ready the second
+        let check = Plugin {
+            exec: "/bin/sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                format!(
+                    "test \"$DECOMPOSE_PROGRAM\" = prog && test -e {0} || (touch {0}; exit 1)",
+                    marker.display()
+                ),
+            ],
+            interval: 0.01,
+        };
+
+        let attempts = Attempts::default();
+        let result = plugin(&check, "prog", 123, &Probes::new(Some(1)), &attempts)
+            .await
+            .expect("plugin");
+        assert!(result);
+        assert!(marker.exists());
+        assert_eq!("1x plugin exit status: 1", attempts.summary());
+    }
+
+    #[tokio::test]
+    async fn test_exec() {
+        let attempts = Attempts::default();
+        let probes = Probes::default();
+        let check = Exec {
+            command: vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                "exit 0".to_string(),
+            ],
+            interval: 0.01,
+        };
+        let result = exec(&check, "prog", 123, &probes, &attempts)
+            .await
+            .expect("exec");
+        assert!(result);
+        assert_eq!("", attempts.summary());
+
+        let check = Exec {
+            command: vec!["/bin/false".to_string()],
+            interval: 0.01,
+        };
+        let never = exec(&check, "prog", 123, &probes, &attempts);
+        let timeout = std::time::Duration::from_millis(50);
+        assert!(tokio::time::timeout(timeout, never).await.is_err());
+        assert!(
+            attempts.summary().contains("x exec exit"),
+            "{}",
+            attempts.summary()
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_are_limited() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let probes = Probes::new(Some(2));
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        let probe = || async {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+        futures::future::join_all((0..6).map(|_| probes.run(probe()))).await;
+
+        assert_eq!(2, most.load(Ordering::SeqCst));
+    }
+
     #[tokio::test]
     async fn test_completed() {
         let proc = tokio::process::Command::new("/bin/ls")
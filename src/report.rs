@@ -0,0 +1,236 @@
+extern crate log;
+
+use super::history::History;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// records how each program fared, to be written out as a junit test report
+#[derive(Clone, Default)]
+pub struct Report {
+    cases: Arc<Mutex<Vec<Case>>>,
+    // earlier runs, to warn about programs becoming slow to start
+    history: Arc<History>,
+}
+
+// what became of a program so far
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Outcome {
+    pub ready: bool,
+    pub failed: bool,
+    pub skipped: bool,
+}
+
+struct Case {
+    name: String,
+    started: Instant,
+    ready: Option<Duration>,
+    failed: Option<Duration>,
+    failure: Option<(String, Vec<String>)>,
+    skipped: bool,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    pub fn with_history(mut self, history: History) -> Report {
+        self.history = Arc::new(history);
+        self
+    }
+
+    pub fn started(&self, name: &str) {
+        self.update(name, |_| ());
+    }
+
+    pub fn ready(&self, name: &str) {
+        let history = self.history.clone();
+        self.update(name, |c| {
+            let took = c.started.elapsed();
+            if c.ready.is_none() {
+                if let Some(warning) = history.check(name, took) {
+                    log::warn!("{}", warning);
+                }
+            }
+            c.ready = Some(took);
+        });
+    }
+
+    // the first time to ready of the programs that got there
+    pub fn ready_times(&self) -> Vec<(String, Duration)> {
+        let cases = self.cases.lock().expect("report lock");
+        cases
+            .iter()
+            .filter_map(|c| c.ready.map(|r| (c.name.clone(), r)))
+            .collect()
+    }
+
+    pub fn skipped(&self, name: &str) {
+        self.update(name, |c| c.skipped = true);
+    }
+
+    // only the first failure of a program is kept
+    pub fn failed(&self, name: &str, message: &str, output: Vec<String>) {
+        self.update(name, |c| {
+            if c.failure.is_none() {
+                c.failed = Some(c.started.elapsed());
+                c.failure = Some((message.to_string(), output));
+            }
+        });
+    }
+
+    pub fn outcome(&self, name: &str) -> Outcome {
+        let cases = self.cases.lock().expect("report lock");
+        cases
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| Outcome {
+                ready: c.ready.is_some(),
+                failed: c.failure.is_some(),
+                skipped: c.skipped,
+            })
+            .unwrap_or_default()
+    }
+
+    fn update<F: FnOnce(&mut Case)>(&self, name: &str, f: F) {
+        let mut cases = self.cases.lock().expect("report lock");
+        match cases.iter_mut().find(|c| c.name == name) {
+            Some(c) => f(c),
+            None => {
+                let mut c = Case {
+                    name: name.to_string(),
+                    started: Instant::now(),
+                    ready: None,
+                    failed: None,
+                    failure: None,
+                    skipped: false,
+                };
+                f(&mut c);
+                cases.push(c);
+            }
+        }
+    }
+
+    // programs that never got started are reported as skipped
+    pub fn junit(&self, programs: &[String]) -> String {
+        let cases = self.cases.lock().expect("report lock");
+
+        let mut body = String::new();
+        let (mut failures, mut skipped, mut total) = (0, 0, Duration::from_secs(0));
+        for name in programs {
+            let case = cases.iter().find(|c| &c.name == name);
+            let time = case.and_then(|c| c.ready.or(c.failed)).unwrap_or_default();
+            total += time;
+
+            body += &format!(
+                "    <testcase name=\"{}\" classname=\"decompose\" time=\"{:.3}\"",
+                escape(name),
+                time.as_secs_f64()
+            );
+
+            match case {
+                None
+                | Some(Case {
+                    skipped: true,
+                    failure: None,
+                    ..
+                }) => {
+                    skipped += 1;
+                    body += ">\n      <skipped/>\n    </testcase>\n";
+                }
+                Some(Case {
+                    failure: Some((message, output)),
+                    ..
+                }) => {
+                    failures += 1;
+                    body += &failure(message, output);
+                }
+                Some(Case { ready: None, .. }) => {
+                    failures += 1;
+                    body += &failure("never became ready", &[]);
+                }
+                Some(_) => body += "/>\n",
+            }
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites>\n  \
+             <testsuite name=\"decompose\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n\
+             {}  </testsuite>\n\
+             </testsuites>\n",
+            programs.len(),
+            failures,
+            skipped,
+            total.as_secs_f64(),
+            body
+        )
+    }
+
+    pub fn write_junit(&self, path: &str, programs: &[String]) -> std::io::Result<()> {
+        log::debug!("writing junit report to {}", path);
+        std::fs::write(path, self.junit(programs))
+    }
+}
+
+fn failure(message: &str, output: &[String]) -> String {
+    format!(
+        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+        escape(message),
+        escape(output.join("\n").as_str())
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(ns: &[&str]) -> Vec<String> {
+        ns.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_passes_failures_and_skips() {
+        let report = Report::new();
+
+        report.started("server");
+        report.ready("server");
+
+        report.started("task");
+        report.failed("task", "exited <1>", vec!["oops & bye".to_string()]);
+
+        report.started("slow");
+
+        report.skipped("disabled");
+
+        let xml = report.junit(&names(&["server", "task", "slow", "disabled", "later"]));
+
+        assert!(
+            xml.contains("<testsuite name=\"decompose\" tests=\"5\" failures=\"2\" skipped=\"2\"")
+        );
+        assert!(xml.contains("<testcase name=\"server\" classname=\"decompose\" time=\"0.000\"/>"));
+        assert!(xml.contains("<failure message=\"exited &lt;1&gt;\">oops &amp; bye</failure>"));
+        assert!(xml.contains("<failure message=\"never became ready\"></failure>"));
+        assert_eq!(2, xml.matches("<skipped/>").count());
+    }
+
+    #[test]
+    fn only_first_failure_is_kept() {
+        let report = Report::new();
+
+        report.failed("prog", "first", Vec::new());
+        report.failed("prog", "second", Vec::new());
+
+        let xml = report.junit(&names(&["prog"]));
+        assert!(xml.contains("message=\"first\""));
+        assert!(!xml.contains("message=\"second\""));
+    }
+}
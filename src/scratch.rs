@@ -0,0 +1,107 @@
+extern crate log;
+
+use super::config::Program;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// a fresh writable directory for a single run of a program, removed once the
+// program is done with it. assumed failed until told otherwise, so errors on the
+// way out leave it around when asked to keep it on failure
+pub struct ScratchDir {
+    path: PathBuf,
+    keep_on_failure: bool,
+    succeeded: AtomicBool,
+}
+
+impl ScratchDir {
+    // creates the directory and hands it to the program as DECOMPOSE_SCRATCH
+    pub fn create(prog: &mut Program) -> std::io::Result<ScratchDir> {
+        let path =
+            std::env::temp_dir().join(format!("decompose-{}-{}", std::process::id(), prog.name));
+
+        // left over from a previous start within this run
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        log::debug!("{} scratch dir is {:?}", prog.name, path);
+
+        prog.env.insert(
+            "DECOMPOSE_SCRATCH".to_string(),
+            path.to_string_lossy().to_string(),
+        );
+
+        Ok(ScratchDir {
+            path,
+            keep_on_failure: prog.keep_scratch_on_failure,
+            succeeded: AtomicBool::new(false),
+        })
+    }
+
+    pub fn succeeded(&self) {
+        self.succeeded.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.keep_on_failure && !self.succeeded.load(Ordering::SeqCst) {
+            log::warn!("keeping scratch dir {:?}", self.path);
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            log::warn!("failed to remove scratch dir {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::System;
+    use super::*;
+
+    fn make_prog(name: &str, keep: bool) -> Program {
+        let toml = format!(
+            r#"
+            [[program]]
+            name = "{}"
+            exec = "e"
+            scratch_dir = true
+            keep_scratch_on_failure = {}
+            "#,
+            name, keep
+        );
+        System::from_toml(toml.as_str()).unwrap().program.remove(0)
+    }
+
+    #[test]
+    fn created_and_removed() {
+        let mut prog = make_prog("scratch_removed", false);
+
+        let scratch = ScratchDir::create(&mut prog).unwrap();
+        let path = PathBuf::from(&prog.env["DECOMPOSE_SCRATCH"]);
+        assert!(path.is_dir());
+
+        drop(scratch);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn kept_on_failure() {
+        let mut prog = make_prog("scratch_kept", true);
+
+        let scratch = ScratchDir::create(&mut prog).unwrap();
+        let path = PathBuf::from(&prog.env["DECOMPOSE_SCRATCH"]);
+        drop(scratch);
+        assert!(path.is_dir());
+
+        // a restart starts afresh, and succeeding cleans up
+        std::fs::write(path.join("file"), "data").unwrap();
+        let scratch = ScratchDir::create(&mut prog).unwrap();
+        assert!(!path.join("file").exists());
+
+        scratch.succeeded();
+        drop(scratch);
+        assert!(!path.exists());
+    }
+}
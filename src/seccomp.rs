@@ -0,0 +1,190 @@
+extern crate nix;
+
+use super::config::Seccomp;
+use nix::libc;
+
+// syscalls no program in a dev setup should need, but that can do real harm to
+// the host. denied with EPERM, so well behaved programs just see an error
+const DENIED: [libc::c_long; 25] = [
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_keyctl,
+];
+
+// the kernel's audit arch of this build, anything else gets the process killed
+#[cfg(target_arch = "x86_64")]
+const ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const ARCH: u32 = 0xc000_00b7;
+
+// the kernel's limit on the length of a filter
+const MAX_INSTRUCTIONS: usize = 4096;
+
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+// a filter failing the given syscalls with EPERM, allowing everything else
+fn deny(syscalls: &[libc::c_long]) -> Vec<libc::sock_filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    // offsets of nr and arch in struct seccomp_data
+    let mut filter = vec![
+        statement(BPF_LD | BPF_W | BPF_ABS, 4),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, ARCH, 1, 0),
+        statement(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD | BPF_W | BPF_ABS, 0),
+        // the x32 abi numbers its syscalls from here, under the same arch
+        jump(BPF_JMP | BPF_JGE | BPF_K, 0x4000_0000, 0, 1),
+        statement(BPF_RET | BPF_K, eperm),
+    ];
+    for nr in syscalls {
+        filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr as u32, 0, 1));
+        filter.push(statement(BPF_RET | BPF_K, eperm));
+    }
+    filter.push(statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+    filter
+}
+
+// a raw filter as written by e.g. seccomp_export_bpf, in native byte order
+fn parse(raw: &[u8]) -> Result<Vec<libc::sock_filter>, String> {
+    const SIZE: usize = std::mem::size_of::<libc::sock_filter>();
+
+    if raw.is_empty() || !raw.len().is_multiple_of(SIZE) || raw.len() / SIZE > MAX_INSTRUCTIONS {
+        return Err(format!(
+            "expected 1 to {} instructions of {} bytes, got {} bytes",
+            MAX_INSTRUCTIONS,
+            SIZE,
+            raw.len()
+        ));
+    }
+    Ok(raw
+        .chunks(SIZE)
+        .map(|c| libc::sock_filter {
+            code: u16::from_ne_bytes([c[0], c[1]]),
+            jt: c[2],
+            jf: c[3],
+            k: u32::from_ne_bytes([c[4], c[5], c[6], c[7]]),
+        })
+        .collect())
+}
+
+pub fn filter(seccomp: &Seccomp) -> Result<Vec<libc::sock_filter>, String> {
+    match seccomp {
+        Seccomp::Default => Ok(deny(&DENIED)),
+        Seccomp::Bpf(path) => {
+            let raw = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            parse(&raw).map_err(|e| format!("{}: {}", path, e))
+        }
+    }
+}
+
+// loads the filter in the child right before exec, so it has to be the last of
+// the pre_exec steps. no_new_privs is what allows doing so unprivileged
+pub fn apply(cmd: &mut tokio::process::Command, filter: Vec<libc::sock_filter>) {
+    let load = move || {
+        let prog = libc::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_ptr() as *mut libc::sock_filter,
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &prog as *const libc::sock_fprog,
+                ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(load);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(filter: &[libc::sock_filter]) -> Vec<u8> {
+        filter
+            .iter()
+            .flat_map(|f| {
+                let mut raw = f.code.to_ne_bytes().to_vec();
+                raw.extend_from_slice(&[f.jt, f.jf]);
+                raw.extend_from_slice(&f.k.to_ne_bytes());
+                raw
+            })
+            .collect()
+    }
+
+    async fn run(filter: Vec<libc::sock_filter>, script: &str) -> String {
+        let mut cmd = tokio::process::Command::new("/bin/sh");
+        cmd.arg("-c").arg(script);
+        apply(&mut cmd, filter);
+        let output = cmd.output().await.unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    #[tokio::test]
+    async fn denies_syscalls() {
+        let script = "cd / && echo allowed || echo denied";
+
+        let default = filter(&Seccomp::Default).unwrap();
+        assert_eq!("allowed\n", run(default, script).await);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("no-chdir.bpf");
+        std::fs::write(&path, serialize(&deny(&[libc::SYS_chdir]))).unwrap();
+        let custom = filter(&Seccomp::Bpf(path.to_string_lossy().to_string())).unwrap();
+        assert_eq!("denied\n", run(custom, script).await);
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&[0; 12]).is_err());
+        assert_eq!(2, parse(&[0; 16]).unwrap().len());
+        assert!(filter(&Seccomp::Bpf("/no/such/file".to_string())).is_err());
+    }
+}
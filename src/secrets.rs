@@ -0,0 +1,151 @@
+extern crate log;
+extern crate regex;
+
+use std::error::Error;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug, PartialEq)]
+pub enum Encryption {
+    None,
+    // the whole file, by the age tool
+    Age,
+    // values inside a structured file, by sops
+    Sops,
+}
+
+pub fn detect(filename: &str, raw: &[u8]) -> Encryption {
+    const AGE_BINARY: &[u8] = b"age-encryption.org/";
+    const AGE_ARMORED: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+    if filename.ends_with(".age") || raw.starts_with(AGE_BINARY) || raw.starts_with(AGE_ARMORED) {
+        return Encryption::Age;
+    }
+
+    // sops keeps its metadata in a top level sops entry
+    let sops = regex::Regex::new(r#"(?m)^(sops:|\[sops\]|\s*"sops"\s*:)"#).expect("sops regex");
+    match std::str::from_utf8(raw) {
+        Ok(text) if sops.is_match(text) => Encryption::Sops,
+        _ => Encryption::None,
+    }
+}
+
+// the plain text of the config, and the name to guess its format by. the key is an
+// age identity file, without one the tools look in their usual places
+pub fn decrypt(filename: &str, raw: Vec<u8>, key: Option<&str>) -> Result<(String, String)> {
+    let key = key
+        .map(String::from)
+        .or_else(|| std::env::var("SOPS_AGE_KEY_FILE").ok());
+
+    match detect(filename, &raw) {
+        Encryption::None => Ok((String::from_utf8(raw)?, filename.to_string())),
+        Encryption::Age => {
+            log::debug!("decrypting {} with age", filename);
+            let mut cmd = std::process::Command::new("age");
+            cmd.arg("--decrypt");
+            if let Some(key) = &key {
+                cmd.arg("--identity").arg(key);
+            }
+            cmd.arg(filename);
+
+            let plain = filename.strip_suffix(".age").unwrap_or(filename);
+            Ok((run(cmd, filename)?, plain.to_string()))
+        }
+        Encryption::Sops => {
+            log::debug!("decrypting {} with sops", filename);
+            let mut cmd = std::process::Command::new("sops");
+            cmd.arg("--decrypt");
+            if let Some(format) = sops_format(filename) {
+                cmd.args(["--input-type", format, "--output-type", format]);
+            }
+            if let Some(key) = &key {
+                cmd.env("SOPS_AGE_KEY_FILE", key);
+            }
+            cmd.arg(filename);
+
+            Ok((run(cmd, filename)?, filename.to_string()))
+        }
+    }
+}
+
+// sops has no toml support of its own, toml configs are encrypted as binary files
+fn sops_format(filename: &str) -> Option<&'static str> {
+    match Path::new(filename).extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => Some("yaml"),
+        Some("json") => Some("json"),
+        Some("toml") => Some("binary"),
+        _ => None,
+    }
+}
+
+fn run(mut cmd: std::process::Command, filename: &str) -> Result<String> {
+    let program = format!("{:?}", cmd.get_program());
+    let output = cmd
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|e| {
+            format!(
+                "can't decrypt {}, running {} failed: {}",
+                filename, program, e
+            )
+        })?;
+
+    if !output.status.success() {
+        let msg = format!(
+            "can't decrypt {}: {}",
+            filename,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(msg.into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_encryption() {
+        assert_eq!(
+            Encryption::None,
+            detect("sys.toml", b"[[program]]\nname = \"x\"\n")
+        );
+        assert_eq!(Encryption::Age, detect("sys.toml.age", b"whatever"));
+        assert_eq!(
+            Encryption::Age,
+            detect("sys.toml", b"age-encryption.org/v1\n-> X25519 abc\n")
+        );
+        assert_eq!(
+            Encryption::Age,
+            detect("sys.toml", b"-----BEGIN AGE ENCRYPTED FILE-----\nYWdl\n")
+        );
+        assert_eq!(
+            Encryption::Sops,
+            detect(
+                "sys.yaml",
+                b"program:\n  - name: ENC[AES256_GCM,data:x]\nsops:\n  version: 3.8.1\n"
+            )
+        );
+        assert_eq!(
+            Encryption::Sops,
+            detect("sys.json", b"{\n  \"program\": [],\n  \"sops\": {}\n}\n")
+        );
+    }
+
+    #[test]
+    fn plain_files_pass_through() {
+        let (text, name) = decrypt("sys.toml", b"[[program]]\n".to_vec(), None).unwrap();
+        assert_eq!("[[program]]\n", text);
+        assert_eq!("sys.toml", name);
+    }
+
+    #[test]
+    fn sops_formats() {
+        assert_eq!(Some("yaml"), sops_format("sys.yml"));
+        assert_eq!(Some("json"), sops_format("sys.json"));
+        assert_eq!(Some("binary"), sops_format("sys.toml"));
+        assert_eq!(None, sops_format("sys"));
+    }
+}
@@ -0,0 +1,407 @@
+extern crate log;
+extern crate serde_json;
+extern crate string_error;
+
+use super::config;
+use super::executor::Executor;
+use super::graph::{Graph, NodeHandle};
+use super::process::{mpsc, Command, Control, Event, ExitStatus};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// how long replay waits for a command the session says the executor sends
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+// one line of a session file, seconds since the start of the run and what happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub at: f64,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+// commands from the executor and events from the process manager, programs by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    Start {
+        program: String,
+    },
+    Stop {
+        program: String,
+    },
+    SystemReady,
+    Phase {
+        phase: String,
+    },
+    Started {
+        program: String,
+    },
+    Stopped {
+        program: String,
+        status: Option<i32>,
+    },
+    Failed {
+        program: String,
+        status: i32,
+    },
+    Shutdown,
+    Resume,
+    Control {
+        action: String,
+        program: String,
+    },
+    Liveness {
+        program: String,
+        alive: bool,
+    },
+    Err {
+        message: String,
+    },
+}
+
+type Names = HashMap<NodeHandle, String>;
+
+impl Entry {
+    fn command(cmd: &Command, names: &Names) -> Entry {
+        match cmd {
+            Command::Start((_, p)) => Entry::Start {
+                program: p.name.clone(),
+            },
+            Command::Stop(h) => Entry::Stop {
+                program: names[h].clone(),
+            },
+            Command::SystemReady => Entry::SystemReady,
+            Command::Phase(phase) => Entry::Phase {
+                phase: phase.to_string(),
+            },
+        }
+    }
+
+    fn event(event: &Event, names: &Names) -> Entry {
+        match event {
+            Event::Started(h) => Entry::Started {
+                program: names[h].clone(),
+            },
+            Event::Stopped(h, s) => Entry::Stopped {
+                program: names[h].clone(),
+                status: s.map(|s| s.into_raw()),
+            },
+            Event::Failed(h, s) => Entry::Failed {
+                program: names[h].clone(),
+                status: s.into_raw(),
+            },
+            Event::Shutdown => Entry::Shutdown,
+            Event::Resume => Entry::Resume,
+            Event::Control(action, h) => Entry::Control {
+                action: action.as_str().to_string(),
+                program: names[h].clone(),
+            },
+            Event::Liveness(h, alive) => Entry::Liveness {
+                program: names[h].clone(),
+                alive: *alive,
+            },
+            Event::Err(e) => Entry::Err {
+                message: e.to_string(),
+            },
+        }
+    }
+
+    fn is_command(&self) -> bool {
+        matches!(
+            self,
+            Entry::Start { .. } | Entry::Stop { .. } | Entry::SystemReady | Entry::Phase { .. }
+        )
+    }
+
+    // the event to feed the executor, programs are looked up in the graph
+    fn to_event(&self, graph: &Graph) -> Result<Event> {
+        let find = |name: &str| {
+            graph
+                .find(name)
+                .ok_or_else(|| string_error::into_err(format!("No such program: {}", name)))
+        };
+        Ok(match self {
+            Entry::Started { program } => Event::Started(find(program)?),
+            Entry::Stopped { program, status } => {
+                Event::Stopped(find(program)?, status.map(ExitStatus::from_raw))
+            }
+            Entry::Failed { program, status } => {
+                Event::Failed(find(program)?, ExitStatus::from_raw(*status))
+            }
+            Entry::Shutdown => Event::Shutdown,
+            Entry::Resume => Event::Resume,
+            Entry::Control { action, program } => {
+                let action = Control::parse(action)
+                    .ok_or_else(|| format!("unknown control action {}", action))?;
+                Event::Control(action, find(program)?)
+            }
+            Entry::Liveness { program, alive } => Event::Liveness(find(program)?, *alive),
+            Entry::Err { message } => Event::Err(std::io::Error::other(message.clone())),
+            Entry::Start { .. } | Entry::Stop { .. } | Entry::SystemReady | Entry::Phase { .. } => {
+                panic!("not an event: {:?}", self)
+            }
+        })
+    }
+}
+
+// writes everything passing between the executor and the process manager to a
+// session file, one json record per line
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+    start: Instant,
+    names: Arc<Names>,
+}
+
+impl Recorder {
+    pub fn create(path: &str, sys: &config::System) -> Result<Recorder> {
+        let graph = Graph::from_config(sys)?;
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+
+        Ok(Recorder {
+            file: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+            names: Arc::new(names(&graph)),
+        })
+    }
+
+    fn write(&self, entry: Entry) {
+        let record = Record {
+            at: self.start.elapsed().as_secs_f64(),
+            entry,
+        };
+        let line = serde_json::to_string(&record).expect("records serialize");
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            log::warn!("can't record session: {}", e);
+        }
+    }
+
+    // pass commands on from rx to tx, recording each
+    pub async fn commands(self, rx: mpsc::Receiver<Command>, tx: mpsc::Sender<Command>) {
+        self.forward(rx, tx, Entry::command).await
+    }
+
+    // pass events on from rx to tx, recording each
+    pub async fn events(self, rx: mpsc::Receiver<Event>, tx: mpsc::Sender<Event>) {
+        self.forward(rx, tx, Entry::event).await
+    }
+
+    async fn forward<T>(
+        self,
+        mut rx: mpsc::Receiver<T>,
+        mut tx: mpsc::Sender<T>,
+        entry: fn(&T, &Names) -> Entry,
+    ) {
+        while let Some(x) = rx.recv().await {
+            self.write(entry(&x, &self.names));
+            if tx.send(x).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<Record>> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    std::io::BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|(i, line)| {
+            let record = serde_json::from_str(line?.as_str())
+                .map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+            Ok(record)
+        })
+        .collect()
+}
+
+// drive the executor with the recorded events, at their recorded times, and
+// check it sends the recorded commands. no programs are started
+pub async fn replay(sys: &config::System, records: Vec<Record>) -> Result<()> {
+    let graph = Graph::from_config(sys)?;
+    let names = names(&graph);
+    let (cmd_tx, mut cmd_rx) = mpsc::channel(sys.channels.commands);
+    let (mut event_tx, event_rx) = mpsc::channel(sys.channels.events);
+    let exec = Executor::from_config(sys, cmd_tx, event_rx)?.run();
+
+    let drive = async move {
+        let start = tokio::time::Instant::now();
+        for (i, record) in records.iter().enumerate() {
+            // phases follow from the rest, and older sessions don't have them
+            if let Entry::Phase { .. } = record.entry {
+                continue;
+            }
+            let diverged = |what: String| -> Box<dyn std::error::Error> {
+                format!("record {}: expected {:?}, {}", i + 1, record.entry, what).into()
+            };
+
+            if record.entry.is_command() {
+                let cmd = loop {
+                    let cmd = tokio::time::timeout(COMMAND_TIMEOUT, cmd_rx.recv())
+                        .await
+                        .map_err(|_| diverged("the executor sent nothing".to_string()))?
+                        .ok_or_else(|| diverged("the executor stopped".to_string()))?;
+                    if !matches!(cmd, Command::Phase(_)) {
+                        break cmd;
+                    }
+                };
+                let got = Entry::command(&cmd, &names);
+                if got != record.entry {
+                    return Err(diverged(format!("the executor sent {:?}", got)));
+                }
+            } else {
+                let event = record.entry.to_event(&graph)?;
+                let at = Duration::from_secs_f64(record.at.max(0.0));
+                tokio::time::delay_until(start + at).await;
+                if event_tx.send(event).await.is_err() {
+                    return Err(diverged("the executor stopped".to_string()));
+                }
+            }
+        }
+        log::info!("replayed {} records", records.len());
+        Ok(())
+    };
+
+    futures::pin_mut!(drive, exec);
+    match futures::future::select(drive, exec).await {
+        // the rest of the executor's run is not in the session
+        futures::future::Either::Left((result, _)) => result,
+        futures::future::Either::Right((result, drive)) => {
+            result?;
+            drive.await
+        }
+    }
+}
+
+fn names(graph: &Graph) -> Names {
+    graph
+        .all()
+        .map(|h| (h, graph.node(h).name.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[program]]
+        name = "a"
+        exec = "e"
+
+        [[program]]
+        name = "b"
+        exec = "e"
+        depends = ["a"]
+        "#;
+
+    fn records(lines: &str) -> Vec<Record> {
+        lines
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn record_format() {
+        let record = Record {
+            at: 1.5,
+            entry: Entry::Stopped {
+                program: "a".to_string(),
+                status: Some(256),
+            },
+        };
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            r#"{"at":1.5,"type":"stopped","program":"a","status":256}"#,
+            line
+        );
+        assert_eq!(record, serde_json::from_str(line.as_str()).unwrap());
+
+        let shutdown: Record = serde_json::from_str(r#"{"at":0,"type":"shutdown"}"#).unwrap();
+        assert_eq!(Entry::Shutdown, shutdown.entry);
+    }
+
+    #[tokio::test]
+    async fn replays_a_session() {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let session = records(
+            r#"
+            {"at":0.0,"type":"start","program":"a"}
+            {"at":0.001,"type":"started","program":"a"}
+            {"at":0.001,"type":"start","program":"b"}
+            {"at":0.002,"type":"started","program":"b"}
+            {"at":0.002,"type":"system_ready"}
+            {"at":0.003,"type":"shutdown"}
+            {"at":0.003,"type":"stop","program":"b"}
+            {"at":0.004,"type":"stopped","program":"b","status":0}
+            {"at":0.004,"type":"stop","program":"a"}
+            {"at":0.005,"type":"stopped","program":"a","status":0}
+            "#,
+        );
+
+        replay(&sys, session).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_divergence() {
+        let sys = config::System::from_toml(TOML).unwrap();
+        let session = records(
+            r#"
+            {"at":0.0,"type":"start","program":"a"}
+            {"at":0.001,"type":"started","program":"a"}
+            {"at":0.001,"type":"start","program":"c"}
+            "#,
+        );
+
+        let err = replay(&sys, session).await.unwrap_err().to_string();
+        assert!(err.starts_with("record 3: "), "{}", err);
+        assert!(err.contains("program: \"b\""), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn records_traffic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let path = path.to_str().unwrap();
+        let sys = config::System::from_toml(TOML).unwrap();
+        let recorder = Recorder::create(path, &sys).unwrap();
+        let graph = Graph::from_config(&sys).unwrap();
+        let a = graph.find("a").unwrap();
+
+        let (mut tx, rx) = mpsc::channel(10);
+        let (fwd_tx, mut fwd_rx) = mpsc::channel(10);
+        let forward = tokio::spawn(recorder.events(rx, fwd_tx));
+        tx.send(Event::Started(a)).await.unwrap();
+        tx.send(Event::Failed(a, ExitStatus::from_raw(256)))
+            .await
+            .unwrap();
+        drop(tx);
+        forward.await.unwrap();
+
+        assert!(matches!(fwd_rx.recv().await, Some(Event::Started(h)) if h == a));
+        let entries: Vec<Entry> = read(path).unwrap().into_iter().map(|r| r.entry).collect();
+        assert_eq!(
+            vec![
+                Entry::Started {
+                    program: "a".to_string()
+                },
+                Entry::Failed {
+                    program: "a".to_string(),
+                    status: 256
+                },
+            ],
+            entries
+        );
+    }
+}
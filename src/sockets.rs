@@ -0,0 +1,102 @@
+use super::config;
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use tokio::process;
+
+// listening sockets bound by decompose and passed to programs the systemd way.
+// they are kept across restarts, so clients never see the port closed
+#[derive(Clone, Default)]
+pub struct Sockets {
+    bound: Arc<Mutex<HashMap<(String, u16), TcpListener>>>,
+}
+
+impl Sockets {
+    // the sockets of the program, bound on first use. the fds stay valid as long
+    // as this is around
+    pub fn listeners(&self, prog: &config::Program) -> std::io::Result<Vec<RawFd>> {
+        prog.sockets
+            .iter()
+            .map(|socket| {
+                self.bind(socket.host.as_str(), socket.port).map_err(|e| {
+                    let msg = format!("{} socket {}: {}", prog.name, socket.port, e);
+                    std::io::Error::new(e.kind(), msg)
+                })
+            })
+            .collect()
+    }
+
+    pub fn bind(&self, host: &str, port: u16) -> std::io::Result<RawFd> {
+        let mut bound = self.bound.lock().unwrap();
+        let key = (host.to_string(), port);
+        if !bound.contains_key(&key) {
+            bound.insert(key.clone(), TcpListener::bind((host, port))?);
+        }
+        Ok(bound[&key].as_raw_fd())
+    }
+}
+
+// a command running exec with LISTEN_FDS and friends set, the listeners are to be
+// mapped to fds 3 and up. LISTEN_PID has to be the pid of the program itself, which
+// only a shell exec-ing it can tell
+pub fn command(exec: &std::path::Path, prog: &config::Program) -> process::Command {
+    let names: Vec<String> = prog
+        .sockets
+        .iter()
+        .map(|s| s.name.clone().unwrap_or_else(|| s.port.to_string()))
+        .collect();
+
+    let mut cmd = process::Command::new("/bin/sh");
+    cmd.arg("-c")
+        .arg("LISTEN_PID=$$ exec \"$0\" \"$@\"")
+        .arg(exec)
+        .env("LISTEN_FDS", prog.sockets.len().to_string())
+        .env("LISTEN_FDNAMES", names.join(":"));
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fds;
+    use super::*;
+
+    #[test]
+    fn binds_once() {
+        let prog = config::Program::from_spec(
+            r#"name=server exec=server sockets='[{"port":0,"name":"http"}]'"#,
+        )
+        .unwrap();
+        let sockets = Sockets::default();
+
+        let fds = sockets.listeners(&prog).unwrap();
+        assert_eq!(1, fds.len());
+        assert_eq!(fds, sockets.listeners(&prog).unwrap());
+
+        let none = config::Program::from_spec("name=other exec=other").unwrap();
+        assert!(sockets.listeners(&none).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn passes_listeners() {
+        let prog = config::Program::from_spec(
+            r#"name=server exec=server sockets='[{"port":0,"name":"http"},{"port":0}]'"#,
+        )
+        .unwrap();
+        let sockets = Sockets::default();
+        let fds = sockets.listeners(&prog).unwrap();
+        let fds = fds.into_iter().zip(3..).collect();
+
+        let script = "echo $LISTEN_FDS $LISTEN_FDNAMES; \
+                      test \"$LISTEN_PID\" = $$ && echo pid; \
+                      test -e /proc/$$/fd/3 -a -e /proc/$$/fd/4 && echo fds";
+        let mut cmd = command(std::path::Path::new("/bin/sh"), &prog);
+        fds::remap(&mut cmd, fds);
+        let output = cmd.arg("-c").arg(script).output().await.unwrap();
+        assert_eq!(
+            "2 http:0\npid\nfds\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
@@ -20,9 +20,7 @@ pub fn make_err<E>(e: E) -> tokio::io::Error
 where
     E: Into<Box<dyn std::error::Error + 'static + Sync + Send>>,
 {
-    use tokio::io::{Error, ErrorKind};
-
-    Error::new(ErrorKind::Other, e)
+    tokio::io::Error::other(e)
 }
 
 pub async fn with_timeout<R>(
@@ -32,7 +30,7 @@ pub async fn with_timeout<R>(
     tokio::select! {
         x = f => x,
         _ = tokio::time::delay_for(timeout) => {
-            Err(make_err("timeout"))
+            Err(tokio::io::Error::new(tokio::io::ErrorKind::TimedOut, "timeout"))
         }
     }
 }
@@ -69,9 +67,9 @@ pub mod tests {
         fn poll_read(
             mut self: std::pin::Pin<&mut Self>,
             _: &mut futures::task::Context,
-            mut buf: &mut [u8],
+            buf: &mut [u8],
         ) -> Poll<std::io::Result<usize>> {
-            let r = std::io::Read::read(&mut self.cursor, &mut buf);
+            let r = std::io::Read::read(&mut self.cursor, buf);
             Poll::Ready(r)
         }
     }
@@ -0,0 +1,301 @@
+extern crate log;
+extern crate tokio;
+
+use super::config;
+use super::graph::{Graph, NodeHandle};
+use super::process::{self, mpsc, Control, Event};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// polling is plenty for a dev loop, and needs nothing platform specific
+const INTERVAL: Duration = Duration::from_millis(500);
+
+type Files = HashMap<PathBuf, SystemTime>;
+
+// the watched files of one program, as last seen
+struct Watch {
+    name: String,
+    handle: NodeHandle,
+    // reloading if what depends on it restarts too
+    action: Control,
+    cwd: PathBuf,
+    patterns: Vec<String>,
+    seen: Files,
+}
+
+impl Watch {
+    // whether a file was added, changed or removed since the last look
+    fn changed(&mut self) -> bool {
+        let now = scan(self.cwd.as_path(), &self.patterns);
+        let changed = now != self.seen;
+        self.seen = now;
+        changed
+    }
+}
+
+// restarts programs when their watched files change, asking the executor the same way
+// the control socket does
+pub struct Watcher {
+    watches: Vec<Watch>,
+    events: mpsc::Sender<Event>,
+    overflow: config::Overflow,
+}
+
+impl Watcher {
+    // none if no program watches anything
+    pub fn new(sys: &config::System, events: mpsc::Sender<Event>) -> Result<Option<Watcher>> {
+        let graph = Graph::from_config(sys)?;
+        let watches: Vec<Watch> = graph
+            .all()
+            .filter(|h| !graph.node(*h).watch.is_empty())
+            .map(|h| {
+                let prog = graph.node(h);
+                let action = match prog.watch_dependents {
+                    true => Control::Reload,
+                    false => Control::Restart,
+                };
+                let cwd = PathBuf::from(prog.cwd.as_str());
+                Watch {
+                    name: prog.name.clone(),
+                    handle: h,
+                    action,
+                    seen: scan(cwd.as_path(), &prog.watch),
+                    cwd,
+                    patterns: prog.watch.clone(),
+                }
+            })
+            .collect();
+
+        if watches.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Watcher {
+            watches,
+            events,
+            overflow: sys.channels.overflow,
+        }))
+    }
+
+    // what to restart or reload for the files changed since the last look, the
+    // executor takes care of the order dependents go in
+    fn changes(&mut self) -> Vec<(Control, NodeHandle)> {
+        let mut restart = Vec::new();
+        for watch in self.watches.iter_mut() {
+            if watch.changed() {
+                log::info!("{}: watched files changed", watch.name);
+                restart.push((watch.action, watch.handle));
+            }
+        }
+        restart
+    }
+}
+
+pub async fn run(mut watcher: Watcher) {
+    loop {
+        tokio::time::delay_for(INTERVAL).await;
+
+        // walking the tree blocks, and the runtime has a single thread
+        let (back, changes) = match tokio::task::spawn_blocking(move || {
+            let changes = watcher.changes();
+            (watcher, changes)
+        })
+        .await
+        {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                log::error!("watch: {}", e);
+                return;
+            }
+        };
+        watcher = back;
+
+        for (action, h) in changes {
+            let event = Event::Control(action, h);
+            if let Err(e) = process::send(&mut watcher.events, event, watcher.overflow).await {
+                log::debug!("watch: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+// the files matching any of the patterns, where * matches within a path component and
+// ** any number of directories
+fn scan(cwd: &Path, patterns: &[String]) -> Files {
+    let mut found = Files::new();
+    for pattern in patterns {
+        let (base, pattern) = match pattern.strip_prefix('/') {
+            Some(rest) => (Path::new("/"), rest),
+            None => (cwd, pattern.as_str()),
+        };
+        let parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+        walk(base, &parts, &mut found);
+    }
+    found
+}
+
+fn walk(dir: &Path, pattern: &[&str], found: &mut Files) {
+    let (first, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    if *first == "**" {
+        // at the end, it stands for every file
+        let rest = match rest.is_empty() {
+            true => &["*"][..],
+            false => rest,
+        };
+        walk(dir, rest, found);
+        for entry in entries(dir) {
+            // not following symlinks, which could loop
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                walk(entry.path().as_path(), pattern, found);
+            }
+        }
+    } else if !first.contains('*') {
+        visit(dir.join(first).as_path(), rest, found);
+    } else {
+        for entry in entries(dir) {
+            if config::wildcard_match(first, entry.file_name().to_string_lossy().as_ref()) {
+                visit(entry.path().as_path(), rest, found);
+            }
+        }
+    }
+}
+
+// a file if the pattern is done with, a directory to go into otherwise
+fn visit(path: &Path, rest: &[&str], found: &mut Files) {
+    if !rest.is_empty() {
+        if path.is_dir() {
+            walk(path, rest, found);
+        }
+        return;
+    }
+    if let Ok(meta) = std::fs::metadata(path) {
+        if meta.is_file() {
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.insert(path.to_path_buf(), modified);
+        }
+    }
+}
+
+fn entries(dir: &Path) -> impl Iterator<Item = std::fs::DirEntry> {
+    std::fs::read_dir(dir).into_iter().flatten().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(dir: &Path, file: &str) {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn scans_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in &[
+            "src/main.rs",
+            "src/net/tcp.rs",
+            "src/notes.txt",
+            "config/app.toml",
+            "config/old/app.toml",
+        ] {
+            touch(dir.path(), file);
+        }
+
+        let patterns = vec!["src/**/*.rs".to_string(), "config/*.toml".to_string()];
+        let mut found: Vec<String> = scan(dir.path(), &patterns)
+            .keys()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().display().to_string())
+            .collect();
+        found.sort();
+        assert_eq!(
+            vec!["config/app.toml", "src/main.rs", "src/net/tcp.rs"],
+            found
+        );
+
+        assert_eq!(3, scan(dir.path(), &["src/**".to_string()]).len());
+
+        let absolute = vec![dir.path().join("src/*.txt").display().to_string()];
+        assert_eq!(1, scan(Path::new("/nonexistent"), &absolute).len());
+    }
+
+    #[test]
+    fn changes_restart_the_program_and_its_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "db/schema.sql");
+
+        let toml = format!(
+            r#"
+            [[program]]
+            name = "db"
+            exec = "e"
+            cwd = "{0}"
+            watch = ["db/*.sql"]
+            watch_dependents = true
+
+            [[program]]
+            name = "api"
+            exec = "e"
+            depends = ["db"]
+
+            [[program]]
+            name = "web"
+            exec = "e"
+            cwd = "{0}"
+            depends = ["api"]
+            watch = ["web/**"]
+            "#,
+            dir.path().display()
+        );
+        let sys = config::System::from_toml(toml.as_str()).unwrap();
+        let graph = Graph::from_config(&sys).unwrap();
+        let (tx, _rx) = mpsc::channel(10);
+        let mut watcher = Watcher::new(&sys, tx).unwrap().expect("watcher");
+        let named = |changes: Vec<(Control, NodeHandle)>| -> Vec<(Control, String)> {
+            changes
+                .into_iter()
+                .map(|(action, h)| (action, graph.node(h).name.clone()))
+                .collect()
+        };
+
+        assert!(watcher.changes().is_empty());
+
+        touch(dir.path(), "db/seed.sql");
+        assert_eq!(
+            vec![(Control::Reload, "db".to_string())],
+            named(watcher.changes())
+        );
+        assert!(watcher.changes().is_empty());
+
+        std::fs::remove_file(dir.path().join("db/schema.sql")).unwrap();
+        touch(dir.path(), "web/index.html");
+        assert_eq!(
+            vec![
+                (Control::Reload, "db".to_string()),
+                (Control::Restart, "web".to_string())
+            ],
+            named(watcher.changes())
+        );
+    }
+
+    #[test]
+    fn nothing_to_watch() {
+        let toml = r#"
+            [[program]]
+            name = "db"
+            exec = "e"
+            "#;
+        let sys = config::System::from_toml(toml).unwrap();
+        let (tx, _rx) = mpsc::channel(10);
+        assert!(Watcher::new(&sys, tx).unwrap().is_none());
+    }
+}
@@ -85,6 +85,7 @@ pub struct Fixture {
     process: Option<Child>,
     reader: std::io::BufReader<std::process::ChildStdout>,
     writer: std::io::BufWriter<std::process::ChildStdin>,
+    _outdir: tempfile::TempDir,
 }
 
 #[allow(dead_code)]
@@ -98,12 +99,16 @@ impl Fixture {
         });
         BIN_INIT.call_once(link_helpers);
 
+        // instances lock their outdir, and tests run side by side
+        let outdir = tempfile::tempdir().expect("outdir");
         let mut proc = escargot::CargoBuild::new()
             .run()
             .expect("cargo run")
             .command()
             .arg("--output=null")
             .arg("--log=debug")
+            .arg("--outdir")
+            .arg(outdir.path())
             .arg(data_file(config))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -117,6 +122,7 @@ impl Fixture {
             process: Some(proc),
             reader,
             writer,
+            _outdir: outdir,
         }
     }
 
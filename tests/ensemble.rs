@@ -85,4 +85,22 @@ mod ensemble {
         f.expect_program_terminates(&proxy);
         f.expect_stop();
     }
+
+    #[test]
+    fn dependents_get_exported_values() {
+        let mut f = Fixture::new("exports.yaml");
+        f.expect_start();
+
+        let setup = f.expect_program_ready();
+        assert_eq!("setup", setup.name);
+
+        let srv = f.expect_program_ready();
+        assert_eq!("server", srv.name);
+
+        call(9097, "hello").expect("call");
+        f.stop();
+
+        f.expect_program_terminates(&srv);
+        f.expect_stop();
+    }
 }
@@ -45,12 +45,37 @@ mod readysignals {
         f.expect_program_ready();
     }
 
+    #[test]
+    fn stdout_probes_are_logged() {
+        let mut f = Fixture::new("rs_stdout.yaml");
+        f.expect_line(r#"prog ready probe line ".*SIGTERM.*" matches"#);
+        f.expect_program_ready();
+    }
+
+    #[test]
+    fn stdout_quiet() {
+        let mut f = Fixture::new("rs_stdout_quiet.yaml");
+        f.expect_program_ready();
+    }
+
     #[test]
     fn stderr() {
         let mut f = Fixture::new("rs_stderr.yaml");
         f.expect_program_ready();
     }
 
+    #[test]
+    fn notification_fd() {
+        let mut f = Fixture::new("rs_notification_fd.yaml");
+        f.expect_program_ready();
+    }
+
+    #[test]
+    fn fifo() {
+        let mut f = Fixture::new("rs_fifo.yaml");
+        f.expect_program_ready();
+    }
+
     #[test]
     fn completed() {
         let mut f = Fixture::new("rs_completed.yaml");
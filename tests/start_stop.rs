@@ -14,6 +14,25 @@ mod start_stop {
         f.expect_stop();
     }
 
+    #[test]
+    fn stops_reach_every_program() {
+        // all stopped at once, each has to hear its own stop
+        let mut f = Fixture::new("many.toml");
+        f.expect_start();
+        for _ in 0..16 {
+            f.expect_program_ready();
+        }
+        f.stop();
+
+        let mut stopped: Vec<String> = (0..16)
+            .map(|_| f.expect_line(r"\[decompose::process\] (p[0-9]+):[0-9]+ stopped")[1].clone())
+            .collect();
+        stopped.sort();
+        stopped.dedup();
+        assert_eq!(16, stopped.len());
+        f.expect_stop();
+    }
+
     #[test]
     fn stop_if_all_programs_die() {
         let mut f = Fixture::new("single.toml");
@@ -36,6 +55,22 @@ mod start_stop {
         f.expect_stop();
     }
 
+    #[test]
+    fn stop_signals_escalate() {
+        let mut f = Fixture::new("escalation.toml");
+        f.expect_start();
+
+        let prog = f.expect_program_ready();
+        f.stop();
+        let re = format!(
+            "\\[decompose::process\\] {} still running, sending SIGUSR2",
+            prog
+        );
+        f.expect_line(re.as_str());
+        f.expect_program_terminates(&prog);
+        f.expect_stop();
+    }
+
     #[test]
     fn errors_on_start_timeout() {
         let mut f = Fixture::new("timeout.yaml");